@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diff::{self, FileDiff};
+
+/// State of one file before the first edit made to it during a turn.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CheckpointFile {
+    pub path: String,
+    pub existed: bool,
+    pub blob: Option<String>,
+    pub tool: String,
+    pub tool_call_id: String,
+    pub captured_at: i64,
+}
+
+/// All files touched by the agent during a single turn.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TurnCheckpoint {
+    pub turn_id: String,
+    pub session_id: String,
+    pub work_dir: String,
+    pub prompt: String,
+    pub created_at: i64,
+    pub files: Vec<CheckpointFile>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RestoreResult {
+    pub restored: Vec<String>,
+    pub removed: Vec<String>,
+    pub turns: Vec<String>,
+}
+
+fn checkpoints_root() -> PathBuf {
+    crate::kimi_share_dir().join("checkpoints")
+}
+
+fn session_dir(session_id: &str) -> Result<PathBuf, String> {
+    if session_id.is_empty()
+        || session_id.contains(['/', '\\'])
+        || session_id == "."
+        || session_id == ".."
+    {
+        return Err("Invalid session id".to_string());
+    }
+    Ok(checkpoints_root().join(session_id))
+}
+
+fn manifest_path(session_id: &str, turn_id: &str) -> Result<PathBuf, String> {
+    if turn_id.is_empty() || turn_id.contains(['/', '\\', '.']) {
+        return Err("Invalid turn id".to_string());
+    }
+    Ok(session_dir(session_id)?.join(format!("{turn_id}.json")))
+}
+
+fn blob_path(session_id: &str, blob: &str) -> Result<PathBuf, String> {
+    Ok(session_dir(session_id)?.join("blobs").join(blob))
+}
+
+fn load_turn(path: &Path) -> Result<TurnCheckpoint, String> {
+    let raw =
+        fs::read_to_string(path).map_err(|error| format!("Failed to read {path:?}: {error}"))?;
+    serde_json::from_str(&raw).map_err(|error| format!("Invalid checkpoint {path:?}: {error}"))
+}
+
+fn save_turn(turn: &TurnCheckpoint) -> Result<(), String> {
+    let path = manifest_path(&turn.session_id, &turn.turn_id)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create directory {parent:?}: {error}"))?;
+    }
+    let raw = serde_json::to_string_pretty(turn)
+        .map_err(|error| format!("Failed to encode checkpoint: {error}"))?;
+    fs::write(&path, raw).map_err(|error| format!("Failed to write {path:?}: {error}"))
+}
+
+fn store_blob(session_id: &str, content: &[u8]) -> Result<String, String> {
+    let hash = format!("{:x}", Sha256::digest(content));
+    let path = blob_path(session_id, &hash)?;
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|error| format!("Failed to create directory {parent:?}: {error}"))?;
+        }
+        fs::write(&path, content).map_err(|error| format!("Failed to write {path:?}: {error}"))?;
+    }
+    Ok(hash)
+}
+
+fn read_blob(session_id: &str, blob: &str) -> Result<Vec<u8>, String> {
+    let path = blob_path(session_id, blob)?;
+    fs::read(&path).map_err(|error| format!("Failed to read {path:?}: {error}"))
+}
+
+/// Turns of a session, oldest first.
+fn load_turns(session_id: &str) -> Result<Vec<TurnCheckpoint>, String> {
+    let dir = session_dir(session_id)?;
+    let mut turns = Vec::new();
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                turns.push(load_turn(&path)?);
+            }
+        }
+    }
+    turns.sort_by_key(|turn| turn.created_at);
+    Ok(turns)
+}
+
+/// Save the current content of `paths` before a tool modifies them. Only the
+/// first capture of a path within a turn is kept, so the checkpoint always
+/// reflects the state before the turn started touching it.
+pub fn snapshot(
+    session_id: &str,
+    turn_id: &str,
+    work_dir: &str,
+    prompt: &str,
+    tool: &str,
+    tool_call_id: &str,
+    paths: &[PathBuf],
+) -> Result<(), String> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let manifest = manifest_path(session_id, turn_id)?;
+    let mut turn = if manifest.exists() {
+        load_turn(&manifest)?
+    } else {
+        TurnCheckpoint {
+            turn_id: turn_id.to_string(),
+            session_id: session_id.to_string(),
+            work_dir: work_dir.to_string(),
+            prompt: prompt.chars().take(200).collect(),
+            created_at: chrono::Utc::now().timestamp_millis(),
+            files: Vec::new(),
+        }
+    };
+
+    let mut changed = false;
+    for path in paths {
+        let path_str = path.to_string_lossy().to_string();
+        if turn.files.iter().any(|file| file.path == path_str) {
+            continue;
+        }
+        let (existed, blob) = if path.is_file() {
+            let content =
+                fs::read(path).map_err(|error| format!("Failed to read {path:?}: {error}"))?;
+            (true, Some(store_blob(session_id, &content)?))
+        } else {
+            (false, None)
+        };
+        turn.files.push(CheckpointFile {
+            path: path_str,
+            existed,
+            blob,
+            tool: tool.to_string(),
+            tool_call_id: tool_call_id.to_string(),
+            captured_at: chrono::Utc::now().timestamp_millis(),
+        });
+        changed = true;
+    }
+
+    if changed {
+        save_turn(&turn)?;
+    }
+    Ok(())
+}
+
+fn file_content(session_id: &str, file: &CheckpointFile) -> Result<Option<String>, String> {
+    match (&file.blob, file.existed) {
+        (Some(blob), true) => {
+            let bytes = read_blob(session_id, blob)?;
+            Ok(Some(String::from_utf8_lossy(&bytes).to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn display_path(path: &str, work_dir: &str) -> String {
+    Path::new(path)
+        .strip_prefix(work_dir)
+        .map(|relative| relative.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+#[tauri::command]
+pub fn checkpoint_list(session_id: String) -> Result<Vec<TurnCheckpoint>, String> {
+    let mut turns = load_turns(&session_id)?;
+    turns.reverse();
+    Ok(turns)
+}
+
+/// Diff of the changes made during a turn: the checkpointed content against
+/// the state captured by the next turn that touched the file, or the current
+/// file on disk.
+#[tauri::command]
+pub fn checkpoint_diff(session_id: String, turn_id: String) -> Result<Vec<FileDiff>, String> {
+    let turns = load_turns(&session_id)?;
+    let index = turns
+        .iter()
+        .position(|turn| turn.turn_id == turn_id)
+        .ok_or_else(|| "Checkpoint not found".to_string())?;
+    let turn = &turns[index];
+
+    let mut diffs = Vec::new();
+    for file in &turn.files {
+        let before = file_content(&session_id, file)?;
+        let later = turns[index + 1..]
+            .iter()
+            .flat_map(|later| later.files.iter())
+            .find(|later| later.path == file.path);
+        let after = match later {
+            Some(later) => file_content(&session_id, later)?,
+            None => fs::read(&file.path)
+                .ok()
+                .map(|bytes| String::from_utf8_lossy(&bytes).to_string()),
+        };
+
+        let path = display_path(&file.path, &turn.work_dir);
+        let mut file_diff =
+            diff::unified_diff(&path, before.as_deref(), after.as_deref().unwrap_or(""));
+        if after.is_none() {
+            file_diff.diff = format!("{}(file deleted)\n", file_diff.diff);
+        }
+        diffs.push(file_diff);
+    }
+    Ok(diffs)
+}
+
+/// Restore every file touched in `turn_id` or any later turn to its state
+/// before `turn_id`, then drop those checkpoints.
+#[tauri::command]
+pub fn checkpoint_restore(session_id: String, turn_id: String) -> Result<RestoreResult, String> {
+    let turns = load_turns(&session_id)?;
+    let index = turns
+        .iter()
+        .position(|turn| turn.turn_id == turn_id)
+        .ok_or_else(|| "Checkpoint not found".to_string())?;
+
+    // The earliest capture of each path is its state before the chosen turn.
+    let mut earliest: HashMap<String, &CheckpointFile> = HashMap::new();
+    let mut order = Vec::new();
+    for turn in &turns[index..] {
+        for file in &turn.files {
+            if !earliest.contains_key(&file.path) {
+                earliest.insert(file.path.clone(), file);
+                order.push(file.path.clone());
+            }
+        }
+    }
+
+    let mut result = RestoreResult {
+        restored: Vec::new(),
+        removed: Vec::new(),
+        turns: Vec::new(),
+    };
+    for path in order {
+        let file = earliest[&path];
+        let target = Path::new(&path);
+        match (&file.blob, file.existed) {
+            (Some(blob), true) => {
+                let content = read_blob(&session_id, blob)?;
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(|error| {
+                        format!("Failed to create directory {parent:?}: {error}")
+                    })?;
+                }
+                fs::write(target, content)
+                    .map_err(|error| format!("Failed to restore {target:?}: {error}"))?;
+                result.restored.push(path);
+            }
+            _ => {
+                if target.is_file() {
+                    fs::remove_file(target)
+                        .map_err(|error| format!("Failed to remove {target:?}: {error}"))?;
+                }
+                result.removed.push(path);
+            }
+        }
+    }
+
+    for turn in &turns[index..] {
+        let manifest = manifest_path(&session_id, &turn.turn_id)?;
+        let _ = fs::remove_file(manifest);
+        result.turns.push(turn.turn_id.clone());
+    }
+
+    Ok(result)
+}
+
+/// Remove all checkpoints of a session.
+pub fn delete_session(session_id: &str) -> Result<(), String> {
+    let dir = session_dir(session_id)?;
+    if dir.exists() {
+        fs::remove_dir_all(&dir)
+            .map_err(|error| format!("Failed to delete checkpoints {dir:?}: {error}"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use encoding_rs::{
+    CoderResult, Decoder, Encoding, BIG5, EUC_KR, GB18030, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8,
+    WINDOWS_1252,
+};
+use std::io::{self, Read};
+
+/// Bytes inspected to decide what kind of file we are reading.
+pub const SNIFF_BYTES: usize = 8192;
+
+/// Legacy encodings tried, in order, for text that is not valid UTF-8.
+const LEGACY_ENCODINGS: [&Encoding; 4] = [GB18030, BIG5, SHIFT_JIS, EUC_KR];
+
+/// What a file looks like based on its first bytes.
+pub enum ContentKind {
+    Text {
+        encoding: &'static Encoding,
+        has_bom: bool,
+    },
+    /// An image format vision models accept.
+    Image {
+        mime: &'static str,
+    },
+    Binary {
+        mime: Option<&'static str>,
+    },
+}
+
+/// Classify a file from a sample of its first bytes.
+pub fn sniff(sample: &[u8]) -> ContentKind {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return ContentKind::Text {
+            encoding,
+            has_bom: true,
+        };
+    }
+
+    let detected = infer::get(sample);
+    if let Some(kind) = &detected {
+        match kind.mime_type() {
+            "image/png" | "image/jpeg" => {
+                return ContentKind::Image {
+                    mime: kind.mime_type(),
+                }
+            }
+            // Text-based formats that infer recognizes.
+            "text/xml" | "text/html" | "application/rtf" | "text/x-shellscript" => {}
+            mime => return ContentKind::Binary { mime: Some(mime) },
+        }
+    }
+
+    if let Some(encoding) = utf16_without_bom(sample) {
+        return ContentKind::Text {
+            encoding,
+            has_bom: false,
+        };
+    }
+    if looks_binary(sample) {
+        return ContentKind::Binary { mime: None };
+    }
+    ContentKind::Text {
+        encoding: detect_text_encoding(sample),
+        has_bom: false,
+    }
+}
+
+/// UTF-16 text without a BOM shows up as zero bytes in every other position.
+fn utf16_without_bom(sample: &[u8]) -> Option<&'static Encoding> {
+    let pairs = sample.len() / 2;
+    if pairs < 8 {
+        return None;
+    }
+    let even_zeros = sample.iter().step_by(2).filter(|byte| **byte == 0).count();
+    let odd_zeros = sample
+        .iter()
+        .skip(1)
+        .step_by(2)
+        .filter(|byte| **byte == 0)
+        .count();
+    if odd_zeros * 10 > pairs * 4 && even_zeros * 20 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 > pairs * 4 && odd_zeros * 20 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+fn looks_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|byte| matches!(**byte, 0x01..=0x08 | 0x0E..=0x1A | 0x1C..=0x1F | 0x7F))
+        .count();
+    control * 10 > sample.len()
+}
+
+/// Pick the encoding of text that has no BOM: UTF-8 if it decodes cleanly,
+/// otherwise the first common CJK encoding that does, falling back to
+/// Windows-1252.
+fn detect_text_encoding(sample: &[u8]) -> &'static Encoding {
+    match std::str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        // Only the last character was cut off by the sample boundary.
+        Err(err) if err.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+
+    // Avoid judging a character split by the end of the sample.
+    let complete = match sample.iter().rposition(|byte| *byte == b'\n') {
+        Some(end) if sample.len() >= SNIFF_BYTES => &sample[..=end],
+        _ => sample,
+    };
+    LEGACY_ENCODINGS
+        .into_iter()
+        .find(|encoding| {
+            encoding
+                .decode_without_bom_handling_and_without_replacement(complete)
+                .is_some()
+        })
+        .unwrap_or(WINDOWS_1252)
+}
+
+/// Decodes a byte stream to UTF-8 as it is read, removing any BOM.
+pub struct TranscodingReader<R> {
+    inner: R,
+    decoder: Decoder,
+    input: Vec<u8>,
+    input_pos: usize,
+    input_len: usize,
+    output: Vec<u8>,
+    output_pos: usize,
+    output_len: usize,
+    eof: bool,
+    finished: bool,
+}
+
+impl<R: Read> TranscodingReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        TranscodingReader {
+            inner,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            input: vec![0; 8192],
+            input_pos: 0,
+            input_len: 0,
+            output: vec![0; 16384],
+            output_pos: 0,
+            output_len: 0,
+            eof: false,
+            finished: false,
+        }
+    }
+}
+
+impl<R: Read> Read for TranscodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.output_pos < self.output_len {
+                let count = buf.len().min(self.output_len - self.output_pos);
+                buf[..count]
+                    .copy_from_slice(&self.output[self.output_pos..self.output_pos + count]);
+                self.output_pos += count;
+                return Ok(count);
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            if self.input_pos == self.input_len && !self.eof {
+                self.input_len = self.inner.read(&mut self.input)?;
+                self.input_pos = 0;
+                self.eof = self.input_len == 0;
+            }
+
+            let (result, read, written, _) = self.decoder.decode_to_utf8(
+                &self.input[self.input_pos..self.input_len],
+                &mut self.output,
+                self.eof,
+            );
+            self.input_pos += read;
+            self.output_pos = 0;
+            self.output_len = written;
+            if self.eof && result == CoderResult::InputEmpty {
+                self.finished = true;
+            }
+        }
+    }
+}
+
+/// Pixel size of a PNG or JPEG image, if its header can be parsed.
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let width = u32::from_be_bytes(bytes.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(bytes.get(20..24)?.try_into().ok()?);
+        return Some((width, height));
+    }
+
+    // JPEG: walk the segments until a start-of-frame marker.
+    let mut pos = 2;
+    while pos + 9 < bytes.len() {
+        if bytes[pos] != 0xFF {
+            return None;
+        }
+        let marker = bytes[pos + 1];
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let is_frame = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_frame {
+            let height = u16::from_be_bytes([bytes[pos + 5], bytes[pos + 6]]) as u32;
+            let width = u16::from_be_bytes([bytes[pos + 7], bytes[pos + 8]]) as u32;
+            return Some((width, height));
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Hex dump of the first bytes of a file, 16 bytes per line.
+pub fn hex_preview(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    for (index, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{byte:02x}")).collect();
+        let text: String = chunk
+            .iter()
+            .map(|byte| {
+                if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        output.push_str(&format!(
+            "{:08x}  {:<47}  {}\n",
+            index * 16,
+            hex.join(" "),
+            text
+        ));
+    }
+    output
+}
+
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
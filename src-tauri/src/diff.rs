@@ -0,0 +1,62 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FileDiff {
+    pub path: String,
+    pub new_file: bool,
+    pub diff: String,
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Build a unified diff between `old` and `new` for `path`.
+/// `old` is `None` when the file does not exist yet.
+pub fn unified_diff(path: &str, old: Option<&str>, new: &str) -> FileDiff {
+    change_diff(path, old, Some(new))
+}
+
+/// Like `unified_diff`, but `new` is `None` when the file is deleted.
+pub fn change_diff(path: &str, old: Option<&str>, new: Option<&str>) -> FileDiff {
+    let new_file = old.is_none();
+    let deleted = new.is_none();
+    let old = old.unwrap_or("");
+    let new = new.unwrap_or("");
+    let text_diff = TextDiff::from_lines(old, new);
+
+    let mut added = 0usize;
+    let mut removed = 0usize;
+    for change in text_diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    let old_header = if new_file {
+        "/dev/null".to_string()
+    } else {
+        format!("a/{path}")
+    };
+    let new_header = if deleted {
+        "/dev/null".to_string()
+    } else {
+        format!("b/{path}")
+    };
+    let diff = text_diff
+        .unified_diff()
+        .context_radius(CONTEXT_LINES)
+        .header(&old_header, &new_header)
+        .to_string();
+
+    FileDiff {
+        path: path.to_string(),
+        new_file,
+        diff,
+        added,
+        removed,
+    }
+}
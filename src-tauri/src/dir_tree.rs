@@ -0,0 +1,223 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::file_index::IgnoreStack;
+
+/// Entries returned per request; larger directories are paged.
+pub const PAGE_SIZE: usize = 500;
+
+/// Subdirectory sizes are counted up to this many entries; the tree only
+/// needs to know which folders are empty.
+pub const COUNT_LIMIT: usize = 100;
+
+/// Hidden when the GUI settings do not set `hidden_files`.
+pub const DEFAULT_HIDDEN: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini"];
+
+#[derive(Clone, Serialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Visible entries in a directory, counted up to `COUNT_LIMIT`.
+    pub child_count: Option<usize>,
+    /// A symlink to this directory or one of its ancestors. Such entries are
+    /// not expandable.
+    pub is_loop: bool,
+}
+
+/// One page of a directory's visible entries, directories first.
+#[derive(Clone, Serialize)]
+pub struct DirListing {
+    pub path: String,
+    pub entries: Vec<DirEntry>,
+    /// Visible entries in the whole directory.
+    pub total: usize,
+    pub offset: usize,
+}
+
+/// Names hidden from the tree on top of the ignore files. Patterns with a
+/// slash match the path relative to the root, others match the name.
+pub struct HiddenList {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl HiddenList {
+    pub fn new(patterns: &[String]) -> Self {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let pattern = pattern.trim_matches('/');
+            let Ok(glob) = Glob::new(pattern) else {
+                continue;
+            };
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        HiddenList {
+            names: names.build().unwrap_or_else(|_| GlobSet::empty()),
+            paths: paths.build().unwrap_or_else(|_| GlobSet::empty()),
+        }
+    }
+
+    fn is_hidden(&self, name: &str, rel: &str) -> bool {
+        self.names.is_match(name) || self.paths.is_match(rel)
+    }
+}
+
+struct Candidate {
+    name: String,
+    path: PathBuf,
+    rel: String,
+    is_dir: bool,
+    is_symlink: bool,
+}
+
+fn join_rel(rel: &str, name: &str) -> String {
+    if rel.is_empty() {
+        name.to_string()
+    } else {
+        format!("{rel}/{name}")
+    }
+}
+
+/// Visible entries of `dir`, sorted with directories first. `ignores` must
+/// already hold the rules of `dir`.
+fn visible_entries(
+    dir: &Path,
+    rel: &str,
+    ignores: &IgnoreStack,
+    hidden: &HiddenList,
+    limit: usize,
+) -> Vec<Candidate> {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    for entry in read_dir.flatten() {
+        if entries.len() >= limit {
+            break;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        let is_symlink = file_type.is_symlink();
+        let is_dir = if is_symlink {
+            path.is_dir()
+        } else {
+            file_type.is_dir()
+        };
+        let child_rel = join_rel(rel, &name);
+        if hidden.is_hidden(&name, &child_rel) || ignores.is_ignored(&child_rel, is_dir) {
+            continue;
+        }
+        entries.push(Candidate {
+            name,
+            path,
+            rel: child_rel,
+            is_dir,
+            is_symlink,
+        });
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    entries
+}
+
+/// Refuse directories reached through a symlink back to one of their own
+/// ancestors, which would nest forever.
+fn check_loop(root: &Path, dir: &Path, canonical: &Path) -> Result<(), String> {
+    for ancestor in dir.ancestors().skip(1) {
+        if !ancestor.starts_with(root) {
+            break;
+        }
+        if ancestor.canonicalize().is_ok_and(|path| path == canonical) {
+            return Err(format!("{} is a symlink loop", dir.display()));
+        }
+    }
+    Ok(())
+}
+
+/// One page of `dir`'s children, with each subdirectory's size so the tree
+/// can show which folders are empty without loading them. `ignores` holds
+/// the rules of `root` as built by `IgnoreStack::for_root`.
+pub fn list_dir(
+    root: &Path,
+    dir: &Path,
+    ignores: &IgnoreStack,
+    hidden: &HiddenList,
+    offset: usize,
+    limit: usize,
+) -> Result<DirListing, String> {
+    let rel_path = dir
+        .strip_prefix(root)
+        .map_err(|_| format!("{} is outside {}", dir.display(), root.display()))?;
+    if rel_path
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return Err(format!("Invalid directory: {}", dir.display()));
+    }
+    if !dir.is_dir() {
+        return Err("Path is not a directory".to_string());
+    }
+    let canonical = dir
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", dir.display(), e))?;
+    check_loop(root, dir, &canonical)?;
+
+    let rel = rel_path.to_string_lossy().replace('\\', "/");
+    let mut ignores = ignores.clone();
+    ignores.enter_dirs(root, rel_path);
+    let candidates = visible_entries(dir, &rel, &ignores, hidden, usize::MAX);
+    let total = candidates.len();
+
+    let entries = candidates
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|candidate| {
+            let is_loop = candidate.is_symlink
+                && candidate.is_dir
+                && candidate
+                    .path
+                    .canonicalize()
+                    .is_ok_and(|target| canonical.starts_with(target));
+            let child_count = (candidate.is_dir && !is_loop).then(|| {
+                let depth = ignores.enter(&candidate.path, &candidate.rel);
+                let count = visible_entries(
+                    &candidate.path,
+                    &candidate.rel,
+                    &ignores,
+                    hidden,
+                    COUNT_LIMIT,
+                )
+                .len();
+                ignores.leave(depth);
+                count
+            });
+            DirEntry {
+                name: candidate.name,
+                path: candidate.path.to_string_lossy().to_string(),
+                is_dir: candidate.is_dir,
+                is_symlink: candidate.is_symlink,
+                child_count,
+                is_loop,
+            }
+        })
+        .collect();
+
+    Ok(DirListing {
+        path: dir.to_string_lossy().to_string(),
+        entries,
+        total,
+        offset,
+    })
+}
@@ -0,0 +1,347 @@
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use reqwest::{redirect, Url};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::content;
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const MAX_REDIRECTS: usize = 10;
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+     (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36";
+
+/// Which URLs `FetchURL` may request, read from the `[fetch]` section of the
+/// config file:
+///
+/// ```toml
+/// [fetch]
+/// allowed_domains = []          # when non-empty, only these are fetched
+/// denied_domains = ["example.com"]
+/// allow_private_network = false
+/// max_bytes = 10485760
+/// timeout_seconds = 30
+/// ```
+///
+/// A domain entry also covers its subdomains.
+#[derive(Clone, Debug)]
+pub struct FetchPolicy {
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+    /// Allow loopback, private and link-local addresses.
+    pub allow_private_network: bool,
+    pub max_bytes: u64,
+    pub timeout: Duration,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        FetchPolicy {
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+            allow_private_network: false,
+            max_bytes: DEFAULT_MAX_BYTES,
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        }
+    }
+}
+
+impl FetchPolicy {
+    pub fn from_config(config: Option<&serde_json::Value>) -> Self {
+        let mut policy = FetchPolicy::default();
+        let Some(section) = config.and_then(|config| config.get("fetch")) else {
+            return policy;
+        };
+        let domains = |key: &str| -> Vec<String> {
+            section
+                .get(key)
+                .and_then(|v| v.as_array())
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|v| v.as_str())
+                        .map(normalize_domain)
+                        .filter(|domain| !domain.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        policy.allowed_domains = domains("allowed_domains");
+        policy.denied_domains = domains("denied_domains");
+        if let Some(allow) = section
+            .get("allow_private_network")
+            .and_then(|v| v.as_bool())
+        {
+            policy.allow_private_network = allow;
+        }
+        if let Some(max_bytes) = section.get("max_bytes").and_then(|v| v.as_u64()) {
+            policy.max_bytes = max_bytes.max(1);
+        }
+        if let Some(seconds) = section.get("timeout_seconds").and_then(|v| v.as_u64()) {
+            policy.timeout = Duration::from_secs(seconds.max(1));
+        }
+        policy
+    }
+
+    /// Checks that need no network access: scheme, domain lists and hosts
+    /// that are IP literals or `localhost`.
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!(
+                "Blocked request to {url}: only http and https URLs can be fetched."
+            ));
+        }
+        let host = url
+            .host_str()
+            .map(|host| host.trim_matches(['[', ']']).to_ascii_lowercase())
+            .ok_or_else(|| format!("Blocked request to {url}: the URL has no host."))?;
+
+        if let Some(entry) = self
+            .denied_domains
+            .iter()
+            .find(|domain| domain_matches(domain, &host))
+        {
+            return Err(format!(
+                "Blocked request to {url}: {host} matches the denied domain {entry}."
+            ));
+        }
+        if !self.allowed_domains.is_empty()
+            && !self
+                .allowed_domains
+                .iter()
+                .any(|domain| domain_matches(domain, &host))
+        {
+            return Err(format!(
+                "Blocked request to {url}: {host} is not in the allowed domains."
+            ));
+        }
+
+        if self.allow_private_network {
+            return Ok(());
+        }
+        if host == "localhost" || host.ends_with(".localhost") {
+            return Err(private_refusal(url, &host, "a loopback host"));
+        }
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            if let Some(kind) = restricted_kind(ip) {
+                return Err(private_refusal(url, &host, kind));
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve the URL's host and refuse it if any address is restricted.
+    /// Returns the vetted addresses so the connection can be pinned to them.
+    async fn resolve(&self, url: &Url) -> Result<Vec<SocketAddr>, String> {
+        let host = url.host_str().unwrap_or_default();
+        if self.allow_private_network || host.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+            return Ok(Vec::new());
+        }
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|err| format!("Failed to resolve {host}: {err}"))?
+            .collect();
+        for addr in &addrs {
+            if let Some(kind) = restricted_kind(addr.ip()) {
+                return Err(private_refusal(
+                    url,
+                    &format!("{host} ({})", addr.ip()),
+                    kind,
+                ));
+            }
+        }
+        Ok(addrs)
+    }
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain
+        .trim()
+        .trim_start_matches("*.")
+        .trim_matches('.')
+        .to_ascii_lowercase()
+}
+
+fn domain_matches(domain: &str, host: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn private_refusal(url: &Url, host: &str, kind: &str) -> String {
+    format!(
+        "Blocked request to {url}: {host} is {kind}. Fetching private network addresses is disabled; set allow_private_network in the [fetch] config section to allow it."
+    )
+}
+
+/// Why an address must not be fetched, if it is loopback, private,
+/// link-local or otherwise not publicly routable.
+fn restricted_kind(ip: IpAddr) -> Option<&'static str> {
+    match ip {
+        IpAddr::V4(ip) => restricted_v4(ip),
+        IpAddr::V6(ip) => restricted_v6(ip),
+    }
+}
+
+fn restricted_v4(ip: Ipv4Addr) -> Option<&'static str> {
+    let [a, b, c, _] = ip.octets();
+    if ip.is_loopback() {
+        Some("a loopback address")
+    } else if ip.is_private() || (a == 100 && (64..128).contains(&b)) {
+        Some("a private address")
+    } else if ip.is_link_local() {
+        Some("a link-local address")
+    } else if ip.is_unspecified() || a == 0 {
+        Some("an unspecified address")
+    } else if ip.is_multicast()
+        || ip.is_broadcast()
+        || a >= 240
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+    {
+        Some("a reserved address")
+    } else if ip.is_documentation() {
+        Some("a documentation address")
+    } else {
+        None
+    }
+}
+
+fn restricted_v6(ip: Ipv6Addr) -> Option<&'static str> {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return restricted_v4(v4);
+    }
+    let segments = ip.segments();
+    // NAT64 addresses embed an IPv4 address in the last 32 bits.
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., high, low] = segments;
+        let v4 = Ipv4Addr::from(((high as u32) << 16) | low as u32);
+        return restricted_v4(v4);
+    }
+    if ip.is_loopback() {
+        Some("a loopback address")
+    } else if ip.is_unspecified() {
+        Some("an unspecified address")
+    } else if segments[0] & 0xfe00 == 0xfc00 {
+        Some("a private address")
+    } else if segments[0] & 0xffc0 == 0xfe80 || segments[0] & 0xffc0 == 0xfec0 {
+        Some("a link-local address")
+    } else if ip.is_multicast() {
+        Some("a reserved address")
+    } else if segments[0] == 0x2001 && segments[1] == 0xdb8 {
+        Some("a documentation address")
+    } else {
+        None
+    }
+}
+
+pub struct Fetched {
+    /// The URL after following redirects.
+    pub url: Url,
+    pub content_type: String,
+    pub body: Vec<u8>,
+    /// Set when the body was cut off at the download limit.
+    pub truncated: bool,
+}
+
+/// GET a URL under the policy. Redirects are followed by hand so every hop
+/// is checked, and connections are pinned to the addresses that passed the
+/// check so a second DNS lookup cannot point them elsewhere.
+pub async fn get(policy: &FetchPolicy, url: &str) -> Result<Fetched, String> {
+    let mut url = Url::parse(url.trim()).map_err(|err| format!("Invalid URL: {err}"))?;
+    let deadline = Instant::now() + policy.timeout;
+
+    for _ in 0..=MAX_REDIRECTS {
+        policy.check_url(&url)?;
+        let addrs = policy.resolve(&url).await?;
+
+        let mut builder = reqwest::Client::builder()
+            .redirect(redirect::Policy::none())
+            .user_agent(USER_AGENT)
+            .connect_timeout(policy.timeout);
+        if let (Some(host), false) = (url.host_str(), addrs.is_empty()) {
+            builder = builder.resolve_to_addrs(host, &addrs);
+        }
+        let client = builder
+            .build()
+            .map_err(|err| format!("Failed to create HTTP client: {err}"))?;
+
+        let response = tokio::time::timeout_at(deadline, client.get(url.clone()).send())
+            .await
+            .map_err(|_| timeout_message(policy))?
+            .map_err(|err| format!("Failed to fetch URL: {err}"))?;
+
+        if response.status().is_redirection() {
+            if let Some(location) = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+            {
+                url = url
+                    .join(location)
+                    .map_err(|err| format!("Invalid redirect location {location}: {err}"))?;
+                continue;
+            }
+        }
+        if !response.status().is_success() {
+            return Err(format!("Fetch failed with status {}", response.status()));
+        }
+
+        let declared_len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if let Some(len) = declared_len.filter(|len| *len > policy.max_bytes) {
+            return Err(format!(
+                "Response is too large ({}, limit {}).",
+                content::format_size(len),
+                content::format_size(policy.max_bytes)
+            ));
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_lowercase();
+        let final_url = response.url().clone();
+
+        let mut response = response;
+        let mut body = Vec::new();
+        let mut truncated = false;
+        loop {
+            let chunk = tokio::time::timeout_at(deadline, response.chunk())
+                .await
+                .map_err(|_| timeout_message(policy))?
+                .map_err(|err| format!("Failed to read response body: {err}"))?;
+            let Some(chunk) = chunk else {
+                break;
+            };
+            let room = policy.max_bytes as usize - body.len();
+            if chunk.len() > room {
+                body.extend_from_slice(&chunk[..room]);
+                truncated = true;
+                break;
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        return Ok(Fetched {
+            url: final_url,
+            content_type,
+            body,
+            truncated,
+        });
+    }
+    Err(format!("Stopped after {MAX_REDIRECTS} redirects."))
+}
+
+fn timeout_message(policy: &FetchPolicy) -> String {
+    format!(
+        "Fetch timed out after {} seconds.",
+        policy.timeout.as_secs()
+    )
+}
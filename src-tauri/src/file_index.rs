@@ -0,0 +1,477 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Never listed, whatever the ignore files say.
+const ALWAYS_SKIPPED: &[&str] = &[".git", ".svn", ".hg", ".DS_Store"];
+
+/// Heavy directories skipped even without an ignore file. They have the
+/// lowest precedence, so a `!build/` rule brings one back.
+const DEFAULT_IGNORES: &str = "node_modules/\ntarget/\ndist/\nbuild/\n.venv/\nvenv/\n\
+     __pycache__/\n.pytest_cache/\n.idea/\n.vscode/\n.next/\n.nuxt/\n";
+
+/// Ignore files read in every directory; later ones take precedence.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+const MAX_INDEXED: usize = 200_000;
+const INDEX_TTL: Duration = Duration::from_secs(30);
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+// Scoring constants, after fzf.
+const SCORE_MATCH: i64 = 16;
+const GAP_START: i64 = -3;
+const GAP_EXTENSION: i64 = -1;
+const BONUS_BOUNDARY: i64 = 8;
+const BONUS_SEPARATOR: i64 = 9;
+const BONUS_CAMEL: i64 = 7;
+const BONUS_CONSECUTIVE: i64 = 4;
+const BONUS_BASENAME: i64 = 24;
+const BONUS_EXACT_NAME: i64 = 100;
+const BONUS_NAME_PREFIX: i64 = 40;
+
+/// Gitignore-style rules in effect while walking a tree: built-in
+/// defaults, the global excludes file, `.git/info/exclude`, ignore files
+/// above the root inside the repository, then those of each directory
+/// entered. Later matchers take precedence.
+#[derive(Clone)]
+pub struct IgnoreStack {
+    root: PathBuf,
+    matchers: Vec<Gitignore>,
+}
+
+impl IgnoreStack {
+    pub fn for_root(root: &Path) -> Self {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let mut defaults = GitignoreBuilder::new(&root);
+        for line in DEFAULT_IGNORES.lines() {
+            let _ = defaults.add_line(None, line);
+        }
+        let mut matchers: Vec<Gitignore> = defaults.build().into_iter().collect();
+        // `core.excludesFile` is read from git's config files, not by
+        // running git.
+        let (global, _) = GitignoreBuilder::new(&root).build_global();
+        matchers.push(global);
+
+        let repo = root
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(Path::to_path_buf);
+        if let Some(repo) = repo {
+            if let Some(exclude) = git_dir(&repo).map(|dir| dir.join("info").join("exclude")) {
+                matchers.extend(load(&repo, &[exclude]));
+            }
+            // Ignore files between the repository root and the listing root.
+            let mut ancestors: Vec<&Path> = root
+                .ancestors()
+                .skip(1)
+                .take_while(|dir| dir.starts_with(&repo))
+                .collect();
+            ancestors.reverse();
+            for dir in ancestors {
+                matchers.extend(load(dir, &IGNORE_FILES.map(|name| dir.join(name))));
+            }
+        }
+        matchers.retain(|matcher| !matcher.is_empty());
+        IgnoreStack { root, matchers }
+    }
+
+    /// Load the ignore files of `root` and every directory down to `rel`
+    /// onto a stack built for `root`, giving the rules in effect inside
+    /// `rel`.
+    pub fn enter_dirs(&mut self, root: &Path, rel: &Path) {
+        self.enter(root, "");
+        let mut dir = root.to_path_buf();
+        let mut dir_rel = String::new();
+        for component in rel.components() {
+            let name = component.as_os_str().to_string_lossy();
+            dir.push(name.as_ref());
+            if !dir_rel.is_empty() {
+                dir_rel.push('/');
+            }
+            dir_rel.push_str(&name);
+            self.enter(&dir, &dir_rel);
+        }
+    }
+
+    /// Load the ignore files of a directory about to be walked. Returns the
+    /// depth to pass to `leave` once the directory is done.
+    pub fn enter(&mut self, dir: &Path, rel: &str) -> usize {
+        let depth = self.matchers.len();
+        let base = self.root.join(rel);
+        self.matchers
+            .extend(load(&base, &IGNORE_FILES.map(|name| dir.join(name))));
+        depth
+    }
+
+    pub fn leave(&mut self, depth: usize) {
+        self.matchers.truncate(depth);
+    }
+
+    /// Whether a path relative to the root, with `/` separators, is ignored.
+    pub fn is_ignored(&self, rel: &str, is_dir: bool) -> bool {
+        let name = rel.rsplit('/').next().unwrap_or(rel);
+        if ALWAYS_SKIPPED.contains(&name) {
+            return true;
+        }
+        let path = self.root.join(rel);
+        self.matchers
+            .iter()
+            .rev()
+            .map(|matcher| matcher.matched(&path, is_dir))
+            .find(|matched| !matched.is_none())
+            .is_some_and(|matched| matched.is_ignore())
+    }
+}
+
+/// The rules of the ignore files that exist among `files`, matched against
+/// paths under `base`. Later files take precedence.
+fn load(base: &Path, files: &[PathBuf]) -> Option<Gitignore> {
+    let mut builder = GitignoreBuilder::new(base);
+    for file in files {
+        // Missing files and invalid lines are skipped.
+        let _ = builder.add(file);
+    }
+    builder.build().ok().filter(|matcher| !matcher.is_empty())
+}
+
+/// The git directory of the checkout at `repo`. In linked worktrees and
+/// submodules `.git` is a file pointing at it.
+pub fn worktree_git_dir(repo: &Path) -> Option<PathBuf> {
+    let dot_git = repo.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let content = fs::read_to_string(&dot_git).ok()?;
+    Some(repo.join(content.trim().strip_prefix("gitdir:")?.trim()))
+}
+
+/// The directory holding `info/exclude`. A linked worktree's git directory
+/// has a `commondir` leading back to the main repository.
+pub fn git_dir(repo: &Path) -> Option<PathBuf> {
+    let dir = worktree_git_dir(repo)?;
+    match fs::read_to_string(dir.join("commondir")) {
+        Ok(common) => Some(dir.join(common.trim())),
+        Err(_) => Some(dir),
+    }
+}
+
+struct IndexedPath {
+    /// Relative to its root, after the root's prefix.
+    path: String,
+    root: usize,
+    /// Lowercased `path`, for the quick subsequence check.
+    lower: String,
+    is_dir: bool,
+    modified: Option<SystemTime>,
+}
+
+/// Every listable path under a work dir, kept between queries.
+pub struct FileIndex {
+    /// Each root's path prefix (empty or `name:`) and directory.
+    roots: Vec<(String, PathBuf)>,
+    entries: Vec<IndexedPath>,
+    truncated: bool,
+    built_at: Instant,
+}
+
+#[derive(Clone, Serialize)]
+pub struct FilePage {
+    pub files: Vec<String>,
+    /// Number of matches across all pages.
+    pub total: usize,
+    pub offset: usize,
+    /// Set when the work dir holds more paths than the index keeps.
+    pub truncated: bool,
+}
+
+/// A file in the index.
+pub struct IndexedFile<'a> {
+    /// As listed, with the root's prefix.
+    pub path: &'a str,
+    /// Relative to the root.
+    pub rel: &'a str,
+    pub full: PathBuf,
+}
+
+impl FileIndex {
+    /// Index one or more roots, listing each one's paths after its prefix.
+    pub fn build_roots(roots: Vec<(String, PathBuf)>) -> Self {
+        fn walk(
+            dir: &Path,
+            rel: &str,
+            root: (usize, &str),
+            ignores: &mut IgnoreStack,
+            entries: &mut Vec<IndexedPath>,
+        ) -> bool {
+            let Ok(read_dir) = fs::read_dir(dir) else {
+                return true;
+            };
+            let depth = ignores.enter(dir, rel);
+            let mut children: Vec<_> = read_dir.flatten().collect();
+            children.sort_by_key(|entry| entry.file_name());
+            for entry in children {
+                if entries.len() >= MAX_INDEXED {
+                    ignores.leave(depth);
+                    return false;
+                }
+                let name = entry.file_name().to_string_lossy().to_string();
+                let child_rel = if rel.is_empty() {
+                    name
+                } else {
+                    format!("{rel}/{name}")
+                };
+                // Symlinked directories are listed but not followed.
+                let Ok(file_type) = entry.file_type() else {
+                    continue;
+                };
+                let is_dir = file_type.is_dir();
+                if ignores.is_ignored(&child_rel, is_dir) {
+                    continue;
+                }
+                let path = format!("{}{child_rel}", root.1);
+                entries.push(IndexedPath {
+                    lower: path.to_lowercase(),
+                    path,
+                    root: root.0,
+                    is_dir,
+                    modified: entry.metadata().ok().and_then(|m| m.modified().ok()),
+                });
+                if is_dir && !walk(&entry.path(), &child_rel, root, ignores, entries) {
+                    ignores.leave(depth);
+                    return false;
+                }
+            }
+            ignores.leave(depth);
+            true
+        }
+
+        let mut entries = Vec::new();
+        let mut complete = true;
+        for (index, (prefix, dir)) in roots.iter().enumerate() {
+            let mut ignores = IgnoreStack::for_root(dir);
+            if !walk(dir, "", (index, prefix), &mut ignores, &mut entries) {
+                complete = false;
+                break;
+            }
+        }
+        FileIndex {
+            roots,
+            entries,
+            truncated: !complete,
+            built_at: Instant::now(),
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.built_at.elapsed() < INDEX_TTL
+    }
+
+    /// Indexed files, without directories, in walk order.
+    pub fn files(&self) -> impl Iterator<Item = IndexedFile<'_>> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.is_dir)
+            .map(|entry| {
+                let (prefix, dir) = &self.roots[entry.root];
+                let rel = &entry.path[prefix.len()..];
+                IndexedFile {
+                    path: &entry.path,
+                    rel,
+                    full: dir.join(rel),
+                }
+            })
+    }
+
+    /// Whether the work dir held more paths than the index keeps.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Rank the index against `query`; an empty query lists the most
+    /// recently modified files first.
+    pub fn search(&self, query: &str, offset: usize, limit: usize) -> FilePage {
+        let terms: Vec<Vec<char>> = query
+            .split_whitespace()
+            .map(|term| term.to_lowercase().chars().collect())
+            .collect();
+        let now = SystemTime::now();
+
+        let mut ranked: Vec<(i64, &IndexedPath)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let mut score = 0;
+                if !terms.is_empty() {
+                    if !terms.iter().all(|term| is_subsequence(term, &entry.lower)) {
+                        return None;
+                    }
+                    let chars: Vec<char> = entry.path.chars().collect();
+                    for term in &terms {
+                        score += rank_term(term, &chars)?;
+                    }
+                    // Prefer shallow, short paths among equal matches.
+                    score -= entry.path.matches('/').count() as i64 * 2;
+                    score -= chars.len() as i64 / 8;
+                    if entry.is_dir {
+                        score -= 5;
+                    }
+                }
+                score += recency_bonus(entry.modified, now);
+                Some((score, entry))
+            })
+            .collect();
+        ranked.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| a.is_dir.cmp(&b.is_dir))
+                .then_with(|| Reverse(a.modified).cmp(&Reverse(b.modified)))
+                .then_with(|| a.path.len().cmp(&b.path.len()))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+
+        FilePage {
+            total: ranked.len(),
+            files: ranked
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .map(|(_, entry)| entry.path.clone())
+                .collect(),
+            offset,
+            truncated: self.truncated,
+        }
+    }
+}
+
+fn is_subsequence(pattern: &[char], text: &str) -> bool {
+    let mut chars = text.chars();
+    pattern.iter().all(|p| chars.any(|c| c == *p))
+}
+
+fn recency_bonus(modified: Option<SystemTime>, now: SystemTime) -> i64 {
+    let Some(age) = modified.and_then(|time| now.duration_since(time).ok()) else {
+        return 0;
+    };
+    match age.as_secs() {
+        0..=3_599 => 30,
+        3_600..=86_399 => 20,
+        86_400..=604_799 => 10,
+        604_800..=2_591_999 => 5,
+        _ => 0,
+    }
+}
+
+/// Best score of one query term against a path: matched across the whole
+/// path, or inside the basename with a bonus.
+fn rank_term(term: &[char], chars: &[char]) -> Option<i64> {
+    let name_start = chars
+        .iter()
+        .rposition(|c| *c == '/')
+        .map(|pos| pos + 1)
+        .unwrap_or(0);
+    let name = &chars[name_start..];
+    let path_score = fuzzy_score(term, chars)?;
+    let name_score = fuzzy_score(term, name).map(|score| score + BONUS_BASENAME);
+
+    let name_lower: String = name.iter().collect::<String>().to_lowercase();
+    let stem = name_lower
+        .rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or(&name_lower);
+    let term: String = term.iter().collect();
+    let exact = if name_lower == term || stem == term {
+        BONUS_EXACT_NAME
+    } else if name_lower.starts_with(&term) {
+        BONUS_NAME_PREFIX
+    } else {
+        0
+    };
+    Some(path_score.max(name_score.unwrap_or(i64::MIN)) + exact)
+}
+
+fn lower(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn boundary_bonus(chars: &[char], i: usize) -> i64 {
+    let Some(&prev) = i.checked_sub(1).and_then(|p| chars.get(p)) else {
+        return BONUS_SEPARATOR;
+    };
+    let current = chars[i];
+    if prev == '/' || prev == '\\' {
+        BONUS_SEPARATOR
+    } else if !prev.is_alphanumeric() {
+        BONUS_BOUNDARY
+    } else if (prev.is_lowercase() && current.is_uppercase())
+        || (!prev.is_numeric() && current.is_numeric())
+    {
+        BONUS_CAMEL
+    } else {
+        0
+    }
+}
+
+/// fzf-style score of `pattern` (lowercase) against `chars`: find the
+/// shortest window holding the pattern in order, then reward matches at
+/// word boundaries and in runs, and charge for gaps.
+fn fuzzy_score(pattern: &[char], chars: &[char]) -> Option<i64> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let mut index = 0;
+    let mut end = None;
+    for (i, c) in chars.iter().enumerate() {
+        if lower(*c) == pattern[index] {
+            index += 1;
+            if index == pattern.len() {
+                end = Some(i);
+                break;
+            }
+        }
+    }
+    let end = end?;
+    // Walk back from the end to the latest start of a full match.
+    let mut index = pattern.len();
+    let mut start = 0;
+    for i in (0..=end).rev() {
+        if lower(chars[i]) == pattern[index - 1] {
+            index -= 1;
+            if index == 0 {
+                start = i;
+                break;
+            }
+        }
+    }
+
+    let mut score = 0;
+    let mut index = 0;
+    let mut in_gap = false;
+    let mut run_bonus = 0;
+    let mut in_run = false;
+    for i in start..=end {
+        if index < pattern.len() && lower(chars[i]) == pattern[index] {
+            let mut bonus = boundary_bonus(chars, i);
+            if in_run {
+                bonus = bonus.max(run_bonus).max(BONUS_CONSECUTIVE);
+            } else {
+                run_bonus = bonus;
+            }
+            if index == 0 {
+                bonus *= 2;
+            }
+            score += SCORE_MATCH + bonus;
+            index += 1;
+            in_run = true;
+            in_gap = false;
+        } else {
+            score += if in_gap { GAP_EXTENSION } else { GAP_START };
+            in_run = false;
+            in_gap = true;
+        }
+    }
+    Some(score)
+}
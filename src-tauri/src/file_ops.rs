@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::watcher::{ChangeBatch, Renamed};
+
+/// Something deleted from a work dir, kept until restored or purged.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    pub work_dir: String,
+    /// Original location, relative to `work_dir`.
+    pub path: String,
+    pub is_dir: bool,
+    pub deleted_at: i64,
+}
+
+/// A path checked to lie inside a work dir.
+pub struct WorkPath {
+    pub full: PathBuf,
+    /// Relative to the work dir, with `/` separators.
+    pub rel: String,
+}
+
+fn trash_root() -> PathBuf {
+    crate::kimi_share_dir().join("trash")
+}
+
+/// Resolve `path`, relative to `work_dir` or absolute inside it. The path
+/// need not exist, but everything above it that does must resolve inside
+/// the work dir. The last component is not followed, so a symlink can be
+/// renamed or deleted whatever it points at.
+pub fn resolve(work_dir: &str, path: &str) -> Result<WorkPath, String> {
+    let root = Path::new(work_dir);
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve work dir: {}", e))?;
+    let requested = Path::new(path);
+    let rel_path = if requested.is_absolute() {
+        requested
+            .strip_prefix(root)
+            .or_else(|_| requested.strip_prefix(&canonical_root))
+            .map_err(|_| "Path is outside working directory".to_string())?
+    } else {
+        requested
+    };
+    let mut rel_parts = Vec::new();
+    for component in rel_path.components() {
+        match component {
+            Component::Normal(part) => rel_parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => return Err(format!("Invalid path: {}", path)),
+        }
+    }
+    if rel_parts.is_empty() {
+        return Err("Path must name an entry inside the working directory".to_string());
+    }
+
+    let full = rel_parts
+        .iter()
+        .fold(canonical_root.clone(), |full, part| full.join(part));
+    let mut existing = full.parent();
+    while let Some(dir) = existing {
+        if dir.exists() {
+            let canonical = dir
+                .canonicalize()
+                .map_err(|e| format!("Failed to resolve path: {}", e))?;
+            if !canonical.starts_with(&canonical_root) {
+                return Err("Path is outside working directory".to_string());
+            }
+            break;
+        }
+        existing = dir.parent();
+    }
+    Ok(WorkPath {
+        full,
+        rel: rel_parts.join("/"),
+    })
+}
+
+/// Identifies a file's content, so a save can tell whether the file changed
+/// since it was read.
+pub fn content_version(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// `content_version` of a file, hashed without loading it whole.
+pub fn file_version(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Write a file via a temporary sibling and rename, keeping the original
+/// file's permissions. Each write gets its own temporary file, so concurrent
+/// saves of the same file cannot clobber each other's.
+pub fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Invalid file path"))?;
+    let temp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    ));
+    let write = || {
+        fs::write(&temp, content)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+fn exists(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+fn create_parent(path: &Path) -> Result<(), String> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e)),
+        None => Ok(()),
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.file_type().is_symlink() {
+        let target = fs::read_link(from)?;
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, to);
+        #[cfg(windows)]
+        return if from.is_dir() {
+            std::os::windows::fs::symlink_dir(target, to)
+        } else {
+            std::os::windows::fs::symlink_file(target, to)
+        };
+    }
+    if metadata.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+fn remove_any(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Move a file or directory, copying when it crosses filesystems.
+fn move_path(from: &Path, to: &Path) -> Result<(), String> {
+    let failed = |e: io::Error| {
+        format!(
+            "Failed to move {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        )
+    };
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == ErrorKind::CrossesDevices => {
+            if let Err(error) = copy_recursive(from, to) {
+                let _ = remove_any(to);
+                return Err(failed(error));
+            }
+            remove_any(from).map_err(failed)
+        }
+        Err(error) => Err(failed(error)),
+    }
+}
+
+/// Create a new file, and any missing folders above it. Fails if the path
+/// is taken.
+pub fn create_file(work_dir: &str, path: &str, content: &str) -> Result<ChangeBatch, String> {
+    let target = resolve(work_dir, path)?;
+    create_parent(&target.full)?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&target.full)
+        .map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => format!("{} already exists", target.rel),
+            _ => format!("Failed to create file: {}", e),
+        })?;
+    file.write_all(content.as_bytes())
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(ChangeBatch {
+        created: vec![target.rel],
+        ..Default::default()
+    })
+}
+
+/// Create a new folder, and any missing folders above it.
+pub fn create_dir(work_dir: &str, path: &str) -> Result<ChangeBatch, String> {
+    let target = resolve(work_dir, path)?;
+    create_parent(&target.full)?;
+    fs::create_dir(&target.full).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => format!("{} already exists", target.rel),
+        _ => format!("Failed to create folder: {}", e),
+    })?;
+    Ok(ChangeBatch {
+        created: vec![target.rel],
+        ..Default::default()
+    })
+}
+
+/// Rename or move a file or folder within the work dir. The destination
+/// must not exist.
+pub fn rename(work_dir: &str, from: &str, to: &str) -> Result<ChangeBatch, String> {
+    let source = resolve(work_dir, from)?;
+    let target = resolve(work_dir, to)?;
+    if !exists(&source.full) {
+        return Err(format!("{} does not exist", source.rel));
+    }
+    if source.full == target.full {
+        return Ok(ChangeBatch::default());
+    }
+    if target.full.starts_with(&source.full) {
+        return Err(format!("Cannot move {} into itself", source.rel));
+    }
+    // A case-only rename on a case-insensitive filesystem sees the source
+    // as the destination.
+    let same_file = source.rel.eq_ignore_ascii_case(&target.rel)
+        && fs::canonicalize(&target.full).ok() == fs::canonicalize(&source.full).ok();
+    if exists(&target.full) && !same_file {
+        return Err(format!("{} already exists", target.rel));
+    }
+    create_parent(&target.full)?;
+    move_path(&source.full, &target.full)?;
+    Ok(ChangeBatch {
+        renamed: vec![Renamed {
+            from: source.rel,
+            to: target.rel,
+        }],
+        ..Default::default()
+    })
+}
+
+fn trash_dir(id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || id.contains(['/', '\\']) || id == "." || id == ".." {
+        return Err("Invalid trash id".to_string());
+    }
+    Ok(trash_root().join(id))
+}
+
+fn read_entry(dir: &Path) -> Option<TrashEntry> {
+    let raw = fs::read_to_string(dir.join("entry.json")).ok()?;
+    serde_json::from_str(&raw).ok()
+}
+
+/// Move a file or folder to the app's trash so it can be restored.
+pub fn trash(work_dir: &str, path: &str) -> Result<(TrashEntry, ChangeBatch), String> {
+    let source = resolve(work_dir, path)?;
+    let metadata =
+        fs::symlink_metadata(&source.full).map_err(|_| format!("{} does not exist", source.rel))?;
+    let entry = TrashEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        work_dir: work_dir.to_string(),
+        path: source.rel.clone(),
+        is_dir: metadata.is_dir(),
+        deleted_at: chrono::Utc::now().timestamp(),
+    };
+    let dir = trash_dir(&entry.id)?;
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create directory {}: {}", dir.display(), e))?;
+    let raw = serde_json::to_string_pretty(&entry)
+        .map_err(|e| format!("Failed to encode trash entry: {}", e))?;
+    fs::write(dir.join("entry.json"), raw)
+        .map_err(|e| format!("Failed to write trash entry: {}", e))?;
+    if let Err(error) = move_path(&source.full, &dir.join("item")) {
+        let _ = fs::remove_dir_all(&dir);
+        return Err(error);
+    }
+    let changes = ChangeBatch {
+        deleted: vec![source.rel],
+        ..Default::default()
+    };
+    Ok((entry, changes))
+}
+
+/// Trashed entries, newest first, optionally only those from `work_dir`.
+pub fn trash_list(work_dir: Option<&str>) -> Vec<TrashEntry> {
+    let Ok(read_dir) = fs::read_dir(trash_root()) else {
+        return Vec::new();
+    };
+    let mut entries: Vec<TrashEntry> = read_dir
+        .flatten()
+        .filter_map(|dir| read_entry(&dir.path()))
+        .filter(|entry| work_dir.is_none_or(|work_dir| entry.work_dir == work_dir))
+        .collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+    entries
+}
+
+/// Put a trashed entry back where it was. Fails if that path is taken.
+pub fn restore(id: &str) -> Result<(TrashEntry, ChangeBatch), String> {
+    let dir = trash_dir(id)?;
+    let entry = read_entry(&dir).ok_or_else(|| "Trash entry not found".to_string())?;
+    let target = resolve(&entry.work_dir, &entry.path)?;
+    if exists(&target.full) {
+        return Err(format!("{} already exists", target.rel));
+    }
+    create_parent(&target.full)?;
+    move_path(&dir.join("item"), &target.full)?;
+    let _ = fs::remove_dir_all(&dir);
+    let changes = ChangeBatch {
+        created: vec![target.rel],
+        ..Default::default()
+    };
+    Ok((entry, changes))
+}
+
+/// Permanently delete one trashed entry, or all of them.
+pub fn purge(id: Option<&str>) -> Result<(), String> {
+    let dir = match id {
+        Some(id) => trash_dir(id)?,
+        None => trash_root(),
+    };
+    match fs::remove_dir_all(&dir) {
+        Err(error) if error.kind() != ErrorKind::NotFound => {
+            Err(format!("Failed to empty trash: {}", error))
+        }
+        _ => Ok(()),
+    }
+}
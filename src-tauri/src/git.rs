@@ -0,0 +1,1119 @@
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+
+use crate::file_ops;
+use crate::tools::{self, truncate_output, ToolOutput};
+
+const GIT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_LOG_COUNT: u64 = 20;
+const MAX_LOG_COUNT: u64 = 200;
+
+/// Operations of the `Git` tool that only inspect the repository.
+const READ_ONLY_OPERATIONS: &[&str] = &["status", "diff", "log", "show", "blame"];
+
+fn str_arg<'a>(args: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+    args.get(key)
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+}
+
+fn bool_arg(args: &serde_json::Value, key: &str) -> bool {
+    args.get(key).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+/// Paths a `Git` call names, from `paths` and `path`.
+pub fn paths_arg(args: &serde_json::Value) -> Vec<String> {
+    let mut paths: Vec<String> = args
+        .get("paths")
+        .and_then(|v| v.as_array())
+        .map(|list| {
+            list.iter()
+                .filter_map(|v| v.as_str())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(path) = str_arg(args, "path") {
+        paths.push(path.to_string());
+    }
+    paths
+}
+
+fn operation(args: &serde_json::Value) -> &str {
+    str_arg(args, "operation").unwrap_or("")
+}
+
+/// The sub-action of `branch` and `stash`, which default to listing.
+fn action(args: &serde_json::Value) -> &str {
+    str_arg(args, "action").unwrap_or("list")
+}
+
+/// Whether a `Git` call leaves the repository untouched.
+pub fn is_read_only(args: &serde_json::Value) -> bool {
+    let operation = operation(args);
+    READ_ONLY_OPERATIONS.contains(&operation)
+        || (matches!(operation, "branch" | "stash") && action(args) == "list")
+}
+
+/// What permission rules match against, such as `commit` or `stash pop`.
+pub fn rule_subject(args: &serde_json::Value) -> String {
+    match operation(args) {
+        operation @ ("branch" | "stash") => format!("{operation} {}", action(args)),
+        operation => operation.to_string(),
+    }
+}
+
+/// A path given to the tool, checked against the workspace boundary like the
+/// file tools' paths and passed to git as an absolute pathspec.
+fn pathspec(work_dir: &str, path: &str) -> Result<String, String> {
+    tools::resolve_path(work_dir, path, false).map(|path| path.to_string_lossy().to_string())
+}
+
+fn pathspecs(work_dir: &str, args: &serde_json::Value) -> Result<Vec<String>, String> {
+    paths_arg(args)
+        .iter()
+        .map(|path| pathspec(work_dir, path))
+        .collect()
+}
+
+/// Files in the working tree a `Git` call may rewrite, so they can be
+/// checkpointed before it runs like the targets of the edit tools.
+pub async fn edit_targets(work_dir: &str, args: &serde_json::Value) -> Vec<PathBuf> {
+    let names = match (operation(args), action(args)) {
+        ("branch", "switch") => {
+            let target = if bool_arg(args, "create") {
+                str_arg(args, "start_point")
+            } else {
+                str_arg(args, "name")
+            };
+            match target.map(check_ref) {
+                Some(Ok(target)) => {
+                    changed_names(
+                        work_dir,
+                        &["diff", "--name-only", "--no-renames", "-z", "HEAD", target],
+                    )
+                    .await
+                }
+                _ => Vec::new(),
+            }
+        }
+        ("stash", "pop" | "apply") => {
+            let index = args.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+            let entry = format!("stash@{{{index}}}");
+            let base = format!("{entry}^1");
+            let untracked = format!("{entry}^3");
+            let mut names = changed_names(
+                work_dir,
+                &["diff", "--name-only", "--no-renames", "-z", &base, &entry],
+            )
+            .await;
+            // Present only when the stash includes untracked files.
+            names.extend(
+                changed_names(
+                    work_dir,
+                    &[
+                        "ls-tree",
+                        "-r",
+                        "-z",
+                        "--name-only",
+                        "--full-name",
+                        &untracked,
+                    ],
+                )
+                .await,
+            );
+            names
+        }
+        _ => return Vec::new(),
+    };
+    let Ok(top) = git(work_dir, &["rev-parse", "--show-toplevel"]).await else {
+        return Vec::new();
+    };
+    let top = Path::new(top.trim());
+    names.iter().map(|name| top.join(name)).collect()
+}
+
+/// Repository-relative paths listed by a `-z` git command, or none if it
+/// fails.
+async fn changed_names(work_dir: &str, args: &[&str]) -> Vec<String> {
+    git(work_dir, args)
+        .await
+        .map(|output| {
+            output
+                .split('\0')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Refs are passed as arguments, so they must not look like options.
+fn check_ref(reference: &str) -> Result<&str, String> {
+    if reference.starts_with('-') {
+        return Err(format!("Invalid ref: {reference}"));
+    }
+    Ok(reference)
+}
+
+/// Run git and return its raw output whatever the exit status. `input` is
+/// written to stdin.
+async fn git_output(
+    work_dir: &str,
+    args: &[&str],
+    input: Option<&str>,
+) -> Result<std::process::Output, String> {
+    let mut cmd = Command::new("git");
+    cmd.args(["-c", "color.ui=never", "-c", "core.quotepath=off"])
+        .args(args)
+        .current_dir(work_dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_PAGER", "cat")
+        .env("GIT_EDITOR", "true")
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let run = async {
+        let mut child = cmd
+            .spawn()
+            .map_err(|err| format!("Failed to run git: {err}"))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin
+                .write_all(input.as_bytes())
+                .await
+                .map_err(|err| format!("Failed to write to git: {err}"))?;
+        }
+        child
+            .wait_with_output()
+            .await
+            .map_err(|err| format!("Failed to run git: {err}"))
+    };
+    timeout(Duration::from_secs(GIT_TIMEOUT_SECS), run)
+        .await
+        .map_err(|_| format!("git timed out after {GIT_TIMEOUT_SECS} seconds"))?
+}
+
+async fn git_with_input(
+    work_dir: &str,
+    args: &[&str],
+    input: Option<&str>,
+) -> Result<String, String> {
+    let output = git_output(work_dir, args, input).await?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let message = if stderr.trim().is_empty() {
+            stdout.trim()
+        } else {
+            stderr.trim()
+        };
+        // Skip global options to name the subcommand.
+        let subcommand = args.iter().find(|arg| !arg.starts_with('-')).unwrap_or(&"");
+        Err(format!("git {subcommand} failed: {message}"))
+    }
+}
+
+pub async fn git(work_dir: &str, args: &[&str]) -> Result<String, String> {
+    git_with_input(work_dir, args, None).await
+}
+
+/// One changed path from `git status --porcelain=v2`.
+#[derive(Clone, Debug)]
+pub struct StatusEntry {
+    pub path: String,
+    /// Source path of a rename or copy.
+    pub orig_path: Option<String>,
+    /// Staged state (`X` in porcelain), `.` when unchanged.
+    pub index: char,
+    /// Unstaged state (`Y` in porcelain), `.` when unchanged.
+    pub worktree: char,
+    pub conflicted: bool,
+    pub untracked: bool,
+    pub ignored: bool,
+    /// What changed inside a submodule, such as `new commits`.
+    pub submodule: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RepoStatus {
+    /// `None` on a detached HEAD.
+    pub branch: Option<String>,
+    pub head: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: u32,
+    pub behind: u32,
+    pub entries: Vec<StatusEntry>,
+}
+
+/// Parse `git status --porcelain=v2 --branch -z` output.
+pub fn parse_status(raw: &str) -> RepoStatus {
+    let mut status = RepoStatus::default();
+    let mut records = raw.split('\0').filter(|record| !record.is_empty());
+    while let Some(record) = records.next() {
+        if let Some(header) = record.strip_prefix("# ") {
+            let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+            match key {
+                "branch.oid" if value != "(initial)" => status.head = Some(value.to_string()),
+                "branch.head" if value != "(detached)" => status.branch = Some(value.to_string()),
+                "branch.upstream" => status.upstream = Some(value.to_string()),
+                "branch.ab" => {
+                    for part in value.split_whitespace() {
+                        if let Some(count) = part.strip_prefix('+') {
+                            status.ahead = count.parse().unwrap_or(0);
+                        } else if let Some(count) = part.strip_prefix('-') {
+                            status.behind = count.parse().unwrap_or(0);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let (kind, rest) = record.split_at(1);
+        let rest = rest.trim_start();
+        match kind {
+            "?" | "!" => status.entries.push(StatusEntry {
+                path: rest.to_string(),
+                orig_path: None,
+                index: kind.chars().next().unwrap_or('?'),
+                worktree: kind.chars().next().unwrap_or('?'),
+                conflicted: false,
+                untracked: kind == "?",
+                ignored: kind == "!",
+                submodule: None,
+            }),
+            "1" | "2" | "u" => {
+                // Fields before the path: XY sub mH mI mW hH hI for ordinary
+                // entries, plus a score for renames, and three modes and
+                // hashes for unmerged ones.
+                let fields = match kind {
+                    "1" => 7,
+                    "2" => 8,
+                    _ => 9,
+                };
+                let mut parts = rest.splitn(fields + 1, ' ');
+                let xy: Vec<char> = parts.next().unwrap_or("..").chars().collect();
+                let submodule = parts.next().and_then(submodule_state);
+                let path = parts.nth(fields - 2).unwrap_or("").to_string();
+                let orig_path = if kind == "2" {
+                    records.next().map(str::to_string)
+                } else {
+                    None
+                };
+                status.entries.push(StatusEntry {
+                    path,
+                    orig_path,
+                    index: xy.first().copied().unwrap_or('.'),
+                    worktree: xy.get(1).copied().unwrap_or('.'),
+                    conflicted: kind == "u",
+                    untracked: false,
+                    ignored: false,
+                    submodule,
+                });
+            }
+            _ => {}
+        }
+    }
+    status
+}
+
+/// Decode the `S<c><m><u>` field of a submodule entry; `N...` marks an
+/// ordinary file.
+fn submodule_state(field: &str) -> Option<String> {
+    let flags: Vec<char> = field.strip_prefix('S')?.chars().collect();
+    let changes: Vec<&str> = [
+        (0, 'C', "new commits"),
+        (1, 'M', "modified content"),
+        (2, 'U', "untracked content"),
+    ]
+    .iter()
+    .filter(|(pos, flag, _)| flags.get(*pos) == Some(flag))
+    .map(|(_, _, label)| *label)
+    .collect();
+    Some(if changes.is_empty() {
+        "unchanged".to_string()
+    } else {
+        changes.join(", ")
+    })
+}
+
+pub async fn status(work_dir: &str) -> Result<RepoStatus, String> {
+    git(
+        work_dir,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "-z",
+            "--untracked-files=all",
+        ],
+    )
+    .await
+    .map(|raw| parse_status(&raw))
+}
+
+/// Status of the part of the repository below `dir`, including ignored
+/// paths, with paths made relative to `dir`. `None` when `dir` is not inside
+/// a work tree. Linked worktrees and submodules, whose `.git` is a file,
+/// are found the same way as ordinary checkouts.
+pub async fn tree_status(dir: &str) -> Result<Option<RepoStatus>, String> {
+    let Ok(prefix) = git(dir, &["rev-parse", "--show-prefix"]).await else {
+        return Ok(None);
+    };
+    let prefix = prefix.trim_end_matches(['\n', '\r']);
+    let raw = git(
+        dir,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "-z",
+            "--ignored",
+            "--",
+            ".",
+        ],
+    )
+    .await?;
+    let mut status = parse_status(&raw);
+    let relative = |path: &str| path.strip_prefix(prefix).map(str::to_string);
+    status
+        .entries
+        .retain_mut(|entry| match relative(&entry.path) {
+            Some(path) => {
+                entry.path = path;
+                // A rename from outside `dir` keeps its repository path.
+                entry.orig_path = entry
+                    .orig_path
+                    .as_deref()
+                    .map(|orig| relative(orig).unwrap_or_else(|| orig.to_string()));
+                true
+            }
+            None => false,
+        });
+    Ok(Some(status))
+}
+
+fn describe_change(code: char) -> &'static str {
+    match code {
+        'M' => "modified",
+        'T' => "type changed",
+        'A' => "added",
+        'D' => "deleted",
+        'R' => "renamed",
+        'C' => "copied",
+        _ => "changed",
+    }
+}
+
+fn format_status(status: &RepoStatus) -> String {
+    let mut out = String::new();
+    match (&status.branch, &status.head) {
+        (Some(branch), _) => out.push_str(&format!("On branch {branch}")),
+        (None, Some(head)) => out.push_str(&format!("HEAD detached at {}", short(head))),
+        (None, None) => out.push_str("No commits yet"),
+    }
+    if let Some(upstream) = &status.upstream {
+        out.push_str(&format!(
+            " (upstream {upstream}, ahead {}, behind {})",
+            status.ahead, status.behind
+        ));
+    }
+    out.push('\n');
+
+    let describe = |entry: &StatusEntry, code: char| match &entry.orig_path {
+        Some(orig) => format!("  {}: {orig} -> {}\n", describe_change(code), entry.path),
+        None => format!("  {}: {}\n", describe_change(code), entry.path),
+    };
+    let mut conflicts = String::new();
+    let mut staged = String::new();
+    let mut unstaged = String::new();
+    let mut untracked = String::new();
+    for entry in &status.entries {
+        if entry.conflicted {
+            conflicts.push_str(&format!("  {}\n", entry.path));
+        } else if entry.untracked {
+            untracked.push_str(&format!("  {}\n", entry.path));
+        } else {
+            if entry.index != '.' {
+                staged.push_str(&describe(entry, entry.index));
+            }
+            if entry.worktree != '.' {
+                unstaged.push_str(&describe(entry, entry.worktree));
+            }
+        }
+    }
+    for (title, section) in [
+        ("Conflicts", conflicts),
+        ("Staged", staged),
+        ("Unstaged", unstaged),
+        ("Untracked", untracked),
+    ] {
+        if !section.is_empty() {
+            out.push_str(&format!("{title}:\n{section}"));
+        }
+    }
+    if status.entries.is_empty() {
+        out.push_str("Working tree clean\n");
+    }
+    out
+}
+
+fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(8)]
+}
+
+/// Compact blame: consecutive lines from the same commit share one header.
+fn format_blame(raw: &str) -> String {
+    struct Commit {
+        author: String,
+        date: String,
+        summary: String,
+    }
+    let mut commits: std::collections::HashMap<String, Commit> = Default::default();
+    let mut out = String::new();
+    let mut current = String::new();
+    let mut last_shown = String::new();
+    let mut line_number = 0;
+    for line in raw.lines() {
+        if let Some(code) = line.strip_prefix('\t') {
+            if current != last_shown {
+                if let Some(commit) = commits.get(&current) {
+                    out.push_str(&format!(
+                        "{} {} {}: {}\n",
+                        short(&current),
+                        commit.date,
+                        commit.author,
+                        commit.summary
+                    ));
+                }
+                last_shown = current.clone();
+            }
+            out.push_str(&format!("{line_number:>6}| {code}\n"));
+            continue;
+        }
+        let mut parts = line.split(' ');
+        let first = parts.next().unwrap_or("");
+        if first.len() == 40 && first.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            current = first.to_string();
+            line_number = parts.nth(1).and_then(|n| n.parse().ok()).unwrap_or(0);
+            commits.entry(current.clone()).or_insert(Commit {
+                author: String::new(),
+                date: String::new(),
+                summary: String::new(),
+            });
+        } else if let Some(commit) = commits.get_mut(&current) {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "author" => commit.author = value.to_string(),
+                "author-time" => {
+                    commit.date = value
+                        .parse::<i64>()
+                        .ok()
+                        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+                        .map(|time| time.format("%Y-%m-%d").to_string())
+                        .unwrap_or_default();
+                }
+                "summary" => commit.summary = value.to_string(),
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+/// Run one `Git` tool operation in the workspace.
+pub async fn run(work_dir: &str, args: &serde_json::Value) -> ToolOutput {
+    let result = match operation(args) {
+        "status" => status(work_dir).await.map(|status| {
+            let summary = if status.entries.is_empty() {
+                "Working tree is clean.".to_string()
+            } else {
+                format!("{} changed path(s).", status.entries.len())
+            };
+            (summary, format_status(&status))
+        }),
+        "diff" => diff(work_dir, args).await,
+        "log" => log(work_dir, args).await,
+        "show" => show(work_dir, args).await,
+        "blame" => blame(work_dir, args).await,
+        "commit" => commit(work_dir, args).await,
+        "branch" => branch(work_dir, args).await,
+        "stash" => stash(work_dir, args).await,
+        "" => Err("Missing operation".to_string()),
+        other => Err(format!("Unknown git operation: {other}")),
+    };
+    match result {
+        Ok((summary, output)) => {
+            let (output, truncated) = truncate_output(&output);
+            ToolOutput {
+                ok: true,
+                summary: if truncated {
+                    format!("{summary} Output is truncated to fit in the message.")
+                } else {
+                    summary
+                },
+                output,
+            }
+        }
+        Err(summary) => ToolOutput {
+            ok: false,
+            summary,
+            output: String::new(),
+        },
+    }
+}
+
+async fn diff(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let mut command = vec!["diff", "--no-ext-diff", "-M"];
+    if bool_arg(args, "staged") {
+        command.push("--cached");
+    }
+    if bool_arg(args, "stat") {
+        command.push("--stat");
+    }
+    if let Some(reference) = str_arg(args, "ref") {
+        command.push(check_ref(reference)?);
+    }
+    let paths = pathspecs(work_dir, args)?;
+    command.push("--");
+    command.extend(paths.iter().map(String::as_str));
+
+    let output = git(work_dir, &command).await?;
+    let scope = match (bool_arg(args, "staged"), str_arg(args, "ref")) {
+        (true, Some(reference)) => format!("staged changes against {reference}"),
+        (true, None) => "staged changes".to_string(),
+        (false, Some(reference)) => format!("working tree against {reference}"),
+        (false, None) => "unstaged changes".to_string(),
+    };
+    if output.trim().is_empty() {
+        return Ok((format!("No differences in {scope}."), String::new()));
+    }
+    let files = output
+        .lines()
+        .filter(|line| line.starts_with("diff --git "))
+        .count();
+    let summary = if files > 0 {
+        format!("Diff of {scope} across {files} file(s).")
+    } else {
+        format!("Diff stat of {scope}.")
+    };
+    Ok((summary, output))
+}
+
+async fn log(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let count = args
+        .get("max_count")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(DEFAULT_LOG_COUNT)
+        .clamp(1, MAX_LOG_COUNT)
+        .to_string();
+    let mut command = vec![
+        "log",
+        "--date=short",
+        "--format=%h %ad %an: %s%d",
+        "-n",
+        &count,
+    ];
+    if let Some(reference) = str_arg(args, "ref") {
+        command.push(check_ref(reference)?);
+    }
+    let paths = pathspecs(work_dir, args)?;
+    command.push("--");
+    command.extend(paths.iter().map(String::as_str));
+
+    let output = git(work_dir, &command).await?;
+    let commits = output.lines().count();
+    Ok((format!("Showing {commits} commit(s)."), output))
+}
+
+async fn show(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let reference = check_ref(str_arg(args, "ref").unwrap_or("HEAD"))?;
+    if let Some(path) = str_arg(args, "path") {
+        // `rev:./path` is relative to the work dir, where git runs.
+        let resolved = tools::resolve_path(work_dir, path, false)?;
+        let base = Path::new(work_dir)
+            .canonicalize()
+            .map_err(|err| format!("Failed to resolve {work_dir}: {err}"))?;
+        let relative = resolved
+            .strip_prefix(&base)
+            .map_err(|_| format!("{path} is not in the repository at {work_dir}"))?;
+        let spec = format!("{reference}:./{}", relative.to_string_lossy());
+        let output = git(work_dir, &["show", &spec]).await?;
+        return Ok((format!("Contents of {path} at {reference}."), output));
+    }
+    let mut command = vec![
+        "show",
+        "--no-ext-diff",
+        "-M",
+        "--date=short",
+        "--format=commit %H%nAuthor: %an <%ae>%nDate: %ad%n%n%B",
+    ];
+    command.push(if bool_arg(args, "stat") {
+        "--stat"
+    } else {
+        "--patch-with-stat"
+    });
+    command.push(reference);
+    let output = git(work_dir, &command).await?;
+    Ok((format!("Showing {reference}."), output))
+}
+
+async fn blame(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let path = str_arg(args, "path").ok_or_else(|| "Missing path".to_string())?;
+    let spec = pathspec(work_dir, path)?;
+    let mut command = vec!["blame", "--porcelain"];
+    let start = args.get("start_line").and_then(|v| v.as_u64());
+    let end = args.get("end_line").and_then(|v| v.as_u64());
+    let range = match (start, end) {
+        (Some(start), Some(end)) => Some(format!("{},{}", start.max(1), end.max(start))),
+        (Some(start), None) => Some(format!("{},", start.max(1))),
+        (None, Some(end)) => Some(format!("1,{}", end.max(1))),
+        (None, None) => None,
+    };
+    if let Some(range) = &range {
+        command.push("-L");
+        command.push(range);
+    }
+    if let Some(reference) = str_arg(args, "ref") {
+        command.push(check_ref(reference)?);
+    }
+    command.push("--");
+    command.push(&spec);
+    let output = git(work_dir, &command).await?;
+    Ok((format!("Blame for {path}."), format_blame(&output)))
+}
+
+async fn commit(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let message = str_arg(args, "message").ok_or_else(|| "Missing commit message".to_string())?;
+    let paths = pathspecs(work_dir, args)?;
+    if !paths.is_empty() {
+        let mut add = vec!["add", "--"];
+        add.extend(paths.iter().map(String::as_str));
+        git(work_dir, &add).await?;
+    }
+    let mut command = vec!["commit", "-m", message];
+    if bool_arg(args, "all") {
+        command.push("--all");
+    }
+    let output = git(work_dir, &command).await?;
+    let head = git(work_dir, &["log", "-1", "--format=%h %s"]).await?;
+    Ok((format!("Committed {}.", head.trim()), output))
+}
+
+async fn branch(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let name = str_arg(args, "name").map(check_ref).transpose()?;
+    let start_point = str_arg(args, "start_point").map(check_ref).transpose()?;
+    let require_name = || name.ok_or_else(|| "Missing branch name".to_string());
+    match action(args) {
+        "list" => {
+            let mut command = vec![
+                "branch",
+                "--list",
+                "--format=%(HEAD) %(refname:short) %(upstream:short) %(upstream:track)",
+            ];
+            if bool_arg(args, "all") {
+                command.push("--all");
+            }
+            let output = git(work_dir, &command).await?;
+            let output: String = output
+                .lines()
+                .map(|line| format!("{}\n", line.trim_end()))
+                .collect();
+            Ok((format!("{} branch(es).", output.lines().count()), output))
+        }
+        "create" => {
+            let name = require_name()?;
+            let mut command = vec!["branch", name];
+            command.extend(start_point);
+            git(work_dir, &command).await?;
+            Ok((format!("Created branch {name}."), String::new()))
+        }
+        "switch" => {
+            let name = require_name()?;
+            let mut command = vec!["switch"];
+            if bool_arg(args, "create") {
+                command.push("-c");
+            }
+            command.push(name);
+            command.extend(start_point);
+            let output = git(work_dir, &command).await?;
+            Ok((format!("Switched to branch {name}."), output))
+        }
+        "delete" => {
+            let name = require_name()?;
+            let output = git(work_dir, &["branch", "-d", name]).await?;
+            Ok((format!("Deleted branch {name}."), output))
+        }
+        other => Err(format!("Unknown branch action: {other}")),
+    }
+}
+
+async fn stash(work_dir: &str, args: &serde_json::Value) -> Result<(String, String), String> {
+    let entry = args
+        .get("index")
+        .and_then(|v| v.as_u64())
+        .map(|index| format!("stash@{{{index}}}"));
+    match action(args) {
+        "list" => {
+            let output = git(work_dir, &["stash", "list", "--format=%gd %cr: %gs"]).await?;
+            Ok((format!("{} stash entries.", output.lines().count()), output))
+        }
+        "push" => {
+            let mut command = vec!["stash", "push"];
+            if bool_arg(args, "include_untracked") {
+                command.push("--include-untracked");
+            }
+            if let Some(message) = str_arg(args, "message") {
+                command.push("-m");
+                command.push(message);
+            }
+            let paths = pathspecs(work_dir, args)?;
+            if !paths.is_empty() {
+                command.push("--");
+                command.extend(paths.iter().map(String::as_str));
+            }
+            let output = git(work_dir, &command).await?;
+            Ok(("Stashed changes.".to_string(), output))
+        }
+        action @ ("pop" | "apply" | "drop") => {
+            let mut command = vec!["stash", action];
+            command.extend(entry.as_deref());
+            let output = git(work_dir, &command).await?;
+            let target = entry.as_deref().unwrap_or("the latest stash");
+            let verb = match action {
+                "pop" => "Popped",
+                "apply" => "Applied",
+                _ => "Dropped",
+            };
+            Ok((format!("{verb} {target}."), output))
+        }
+        other => Err(format!("Unknown stash action: {other}")),
+    }
+}
+
+// GUI commands. Paths are relative to the work dir and matched literally.
+
+/// A file's diff split into hunks, so each can be staged on its own.
+#[derive(Clone, Serialize)]
+pub struct GitFileDiff {
+    pub path: String,
+    /// `unstaged` (index to working tree), `staged` (HEAD to index) or
+    /// `head` (HEAD to working tree).
+    pub mode: String,
+    /// The `diff --git` header up to the first hunk.
+    pub header: String,
+    pub hunks: Vec<String>,
+    pub binary: bool,
+    pub untracked: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct GitBranch {
+    pub name: String,
+    pub current: bool,
+    pub upstream: Option<String>,
+    /// Such as `[ahead 1, behind 2]`; empty when in sync.
+    pub track: String,
+}
+
+async fn has_head(work_dir: &str) -> bool {
+    git(work_dir, &["rev-parse", "--verify", "--quiet", "HEAD"])
+        .await
+        .is_ok()
+}
+
+async fn is_untracked(work_dir: &str, path: &str) -> Result<bool, String> {
+    let raw = git(
+        work_dir,
+        &[
+            "--literal-pathspecs",
+            "status",
+            "--porcelain=v2",
+            "-z",
+            "--untracked-files=all",
+            "--",
+            path,
+        ],
+    )
+    .await?;
+    Ok(parse_status(&raw)
+        .entries
+        .iter()
+        .any(|entry| entry.untracked && entry.path == path))
+}
+
+async fn file_diff(work_dir: &str, path: &str, mode: &str) -> Result<GitFileDiff, String> {
+    let mut untracked = false;
+    let raw = match mode {
+        "unstaged" | "head" if is_untracked(work_dir, path).await? => {
+            // Untracked files only show up when diffed against nothing; the
+            // exit status is 1 whenever they differ.
+            untracked = true;
+            let output = git_output(
+                work_dir,
+                &[
+                    "diff",
+                    "--no-ext-diff",
+                    "--no-index",
+                    "--",
+                    "/dev/null",
+                    path,
+                ],
+                None,
+            )
+            .await?;
+            if output.status.code().is_some_and(|code| code > 1) {
+                return Err(format!(
+                    "git diff failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ));
+            }
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+        "unstaged" => {
+            git(
+                work_dir,
+                &["--literal-pathspecs", "diff", "--no-ext-diff", "--", path],
+            )
+            .await?
+        }
+        "staged" => {
+            git(
+                work_dir,
+                &[
+                    "--literal-pathspecs",
+                    "diff",
+                    "--no-ext-diff",
+                    "--cached",
+                    "--",
+                    path,
+                ],
+            )
+            .await?
+        }
+        "head" => {
+            git(
+                work_dir,
+                &[
+                    "--literal-pathspecs",
+                    "diff",
+                    "--no-ext-diff",
+                    "HEAD",
+                    "--",
+                    path,
+                ],
+            )
+            .await?
+        }
+        other => return Err(format!("Unknown diff mode: {other}")),
+    };
+
+    let mut header = String::new();
+    let mut hunks: Vec<String> = Vec::new();
+    for line in raw.split_inclusive('\n') {
+        if line.starts_with("@@") {
+            hunks.push(String::new());
+        }
+        match hunks.last_mut() {
+            Some(hunk) => hunk.push_str(line),
+            None => header.push_str(line),
+        }
+    }
+    Ok(GitFileDiff {
+        path: path.to_string(),
+        mode: mode.to_string(),
+        binary: hunks.is_empty() && header.lines().any(|line| line.starts_with("Binary files ")),
+        header,
+        hunks,
+        untracked,
+    })
+}
+
+#[tauri::command]
+pub async fn git_file_diff(
+    work_dir: String,
+    path: String,
+    mode: Option<String>,
+) -> Result<GitFileDiff, String> {
+    file_diff(&work_dir, &path, mode.as_deref().unwrap_or("unstaged")).await
+}
+
+/// An empty pathspec list would make git act on the whole repository.
+fn require_paths(paths: &[String]) -> Result<(), String> {
+    if paths.iter().all(|path| path.trim().is_empty()) {
+        return Err("No paths given".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn git_stage(work_dir: String, paths: Vec<String>) -> Result<(), String> {
+    require_paths(&paths)?;
+    let mut command = vec!["--literal-pathspecs", "add", "--all", "--"];
+    command.extend(paths.iter().map(String::as_str));
+    git(&work_dir, &command).await.map(|_| ())
+}
+
+#[tauri::command]
+pub async fn git_unstage(work_dir: String, paths: Vec<String>) -> Result<(), String> {
+    require_paths(&paths)?;
+    // Before the first commit there is no HEAD to restore the index from.
+    let mut command = if has_head(&work_dir).await {
+        vec!["--literal-pathspecs", "restore", "--staged", "--"]
+    } else {
+        vec!["--literal-pathspecs", "rm", "--cached", "-r", "-q", "--"]
+    };
+    command.extend(paths.iter().map(String::as_str));
+    git(&work_dir, &command).await.map(|_| ())
+}
+
+/// Throw away working tree changes: tracked files go back to their staged
+/// content and untracked files are moved to the app's trash, which is
+/// returned so they can be restored.
+#[tauri::command]
+pub async fn git_discard(
+    work_dir: String,
+    paths: Vec<String>,
+) -> Result<Vec<file_ops::TrashEntry>, String> {
+    require_paths(&paths)?;
+    let mut command = vec![
+        "--literal-pathspecs",
+        "status",
+        "--porcelain=v2",
+        "-z",
+        "--untracked-files=all",
+        "--",
+    ];
+    command.extend(paths.iter().map(String::as_str));
+    let status = parse_status(&git(&work_dir, &command).await?);
+
+    // Status paths are relative to the top of the repository.
+    let top = git(&work_dir, &["rev-parse", "--show-toplevel"]).await?;
+    let top = Path::new(top.trim_end_matches(['\n', '\r']));
+
+    let (untracked, tracked): (Vec<&StatusEntry>, Vec<&StatusEntry>) =
+        status.entries.iter().partition(|entry| entry.untracked);
+    let tracked: Vec<&str> = tracked
+        .iter()
+        .filter(|entry| entry.worktree != '.')
+        .map(|entry| entry.path.as_str())
+        .collect();
+    if !tracked.is_empty() {
+        let mut command = vec!["--literal-pathspecs", "restore", "--worktree", "--"];
+        command.extend(tracked);
+        git(&top.to_string_lossy(), &command).await?;
+    }
+    let mut trashed = Vec::new();
+    for entry in untracked {
+        let full = top.join(&entry.path);
+        let (trash_entry, _) = file_ops::trash(&work_dir, &full.to_string_lossy())?;
+        trashed.push(trash_entry);
+    }
+    Ok(trashed)
+}
+
+/// Stage, unstage or discard one hunk of the diff `git_file_diff` returned.
+/// `stage` and `discard` index the unstaged diff, `unstage` the staged one.
+#[tauri::command]
+pub async fn git_apply_hunk(
+    work_dir: String,
+    path: String,
+    hunk: usize,
+    action: String,
+) -> Result<(), String> {
+    let (mode, args): (&str, &[&str]) = match action.as_str() {
+        "stage" => ("unstaged", &["apply", "--cached", "--recount", "-"]),
+        "unstage" => (
+            "staged",
+            &["apply", "--cached", "--reverse", "--recount", "-"],
+        ),
+        "discard" => ("unstaged", &["apply", "--reverse", "--recount", "-"]),
+        other => return Err(format!("Unknown hunk action: {other}")),
+    };
+    let diff = file_diff(&work_dir, &path, mode).await?;
+    let body = diff
+        .hunks
+        .get(hunk)
+        .ok_or_else(|| format!("Hunk {hunk} not found in {path}; refresh the diff"))?;
+    let patch = format!("{}{body}", diff.header);
+    git_with_input(&work_dir, args, Some(&patch))
+        .await
+        .map(|_| ())
+}
+
+/// Commit the index and return the new commit as `<short hash> <subject>`.
+#[tauri::command]
+pub async fn git_commit(
+    work_dir: String,
+    message: String,
+    amend: Option<bool>,
+) -> Result<String, String> {
+    if message.trim().is_empty() {
+        return Err("Commit message is empty".to_string());
+    }
+    let mut command = vec!["commit", "-q", "--cleanup=strip", "-m", message.as_str()];
+    if amend.unwrap_or(false) {
+        command.push("--amend");
+    }
+    git(&work_dir, &command).await?;
+    let head = git(&work_dir, &["log", "-1", "--format=%h %s"]).await?;
+    Ok(head.trim().to_string())
+}
+
+#[tauri::command]
+pub async fn git_branches(work_dir: String) -> Result<Vec<GitBranch>, String> {
+    let output = git(
+        &work_dir,
+        &[
+            "for-each-ref",
+            "--format=%(HEAD)%00%(refname:short)%00%(upstream:short)%00%(upstream:track)",
+            "refs/heads",
+        ],
+    )
+    .await?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\0');
+            let current = fields.next()? == "*";
+            let name = fields.next()?.to_string();
+            let upstream = fields.next().filter(|v| !v.is_empty()).map(str::to_string);
+            let track = fields.next().unwrap_or("").to_string();
+            Some(GitBranch {
+                name,
+                current,
+                upstream,
+                track,
+            })
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn git_switch_branch(
+    work_dir: String,
+    name: String,
+    create: Option<bool>,
+) -> Result<(), String> {
+    let name = check_ref(name.trim())?;
+    let mut command = vec!["switch", "-q"];
+    if create.unwrap_or(false) {
+        command.push("-c");
+    }
+    command.push(name);
+    git(&work_dir, &command).await.map(|_| ())
+}
@@ -0,0 +1,171 @@
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+
+/// Elements that never carry readable page content.
+const NOISE_SELECTOR: &str = "script, style, noscript, template, svg, canvas, iframe, object, \
+     embed, form, button, select, input, textarea, nav, aside, dialog, [hidden], \
+     [aria-hidden=true], [role=navigation], [role=banner], [role=contentinfo], \
+     [role=complementary], [role=dialog]";
+
+/// Containers that usually hold the main text of a page.
+const CONTENT_SELECTOR: &str = "article, main, [role=main], #content, #main-content, \
+     .post-content, .entry-content, .article-body, .markdown-body";
+
+/// Column the Markdown is wrapped at.
+const TEXT_WIDTH: usize = 120;
+
+/// A web page reduced to its main content.
+pub struct Page {
+    pub title: Option<String>,
+    pub markdown: String,
+}
+
+/// Parse an HTML document, strip navigation and other page chrome, and
+/// convert the main content to Markdown. Relative links are resolved
+/// against `base`.
+pub fn to_markdown(html: &str, base: Option<&Url>) -> Page {
+    let mut document = Html::parse_document(html);
+    let title = page_title(&document);
+    let base = document_base(&document, base);
+
+    let mut chrome: Vec<_> = select(&document, NOISE_SELECTOR)
+        .map(|element| element.id())
+        .collect();
+    // Page-level headers and footers are chrome; those inside an article
+    // usually hold its byline or title.
+    chrome.extend(
+        select(&document, "header, footer")
+            .filter(|element| {
+                !element
+                    .ancestors()
+                    .filter_map(ElementRef::wrap)
+                    .any(|ancestor| matches!(ancestor.value().name(), "article" | "main"))
+            })
+            .map(|element| element.id()),
+    );
+    for id in chrome {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+
+    let content = main_content(&document).html();
+    let markdown = html2text::config::plain()
+        .string_from_read(content.as_bytes(), TEXT_WIDTH)
+        .unwrap_or_default();
+    Page {
+        title,
+        markdown: resolve_links(&tidy(&markdown), base.as_ref()),
+    }
+}
+
+/// Elements matching a selector list; an invalid list matches nothing.
+fn select<'a>(document: &'a Html, selectors: &str) -> impl Iterator<Item = ElementRef<'a>> {
+    let selector = Selector::parse(selectors).ok();
+    let found: Vec<ElementRef<'a>> = match &selector {
+        Some(selector) => document.select(selector).collect(),
+        None => Vec::new(),
+    };
+    found.into_iter()
+}
+
+fn page_title(document: &Html) -> Option<String> {
+    let from_title = || {
+        select(document, "title")
+            .next()
+            .map(|title| collapse_whitespace(&title.text().collect::<String>()))
+    };
+    let from_meta = || {
+        select(document, "meta[property='og:title']")
+            .next()
+            .and_then(|meta| meta.value().attr("content").map(collapse_whitespace))
+    };
+    let from_heading = || {
+        select(document, "h1")
+            .next()
+            .map(|heading| collapse_whitespace(&heading.text().collect::<String>()))
+    };
+    from_title()
+        .filter(|title| !title.is_empty())
+        .or_else(|| from_meta().filter(|title| !title.is_empty()))
+        .or_else(|| from_heading().filter(|title| !title.is_empty()))
+}
+
+/// Honour `<base href>` when the page sets one.
+fn document_base(document: &Html, base: Option<&Url>) -> Option<Url> {
+    let declared = select(document, "base[href]").next().and_then(|node| {
+        let href = node.value().attr("href")?;
+        match base {
+            Some(base) => base.join(href).ok(),
+            None => Url::parse(href).ok(),
+        }
+    });
+    declared.or_else(|| base.cloned())
+}
+
+/// The largest content container, unless it holds only a small part of the
+/// page (as on index pages with many short articles), in which case the
+/// whole body is used.
+fn main_content(document: &Html) -> ElementRef<'_> {
+    let body = select(document, "body")
+        .next()
+        .unwrap_or_else(|| document.root_element());
+    let body_len = text_len(body);
+
+    let best = select(document, CONTENT_SELECTOR)
+        .map(|element| (element, text_len(element)))
+        .max_by_key(|(_, len)| *len);
+    match best {
+        Some((element, len)) if len * 3 >= body_len => element,
+        _ => body,
+    }
+}
+
+fn text_len(element: ElementRef) -> usize {
+    element
+        .text()
+        .flat_map(str::split_whitespace)
+        .map(|word| word.len() + 1)
+        .sum()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Resolve the targets of the link footnotes, such as `[1]: /docs`, against
+/// the page's URL.
+fn resolve_links(markdown: &str, base: Option<&Url>) -> String {
+    let Some(base) = base else {
+        return markdown.to_string();
+    };
+    markdown
+        .lines()
+        .map(|line| {
+            let footnote = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.split_once("]: "))
+                .filter(|(number, _)| number.chars().all(|ch| ch.is_ascii_digit()));
+            match footnote.and_then(|(number, target)| Some((number, base.join(target).ok()?))) {
+                Some((number, url)) => format!("[{number}]: {url}"),
+                None => line.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Trim trailing spaces and collapse runs of blank lines.
+fn tidy(text: &str) -> String {
+    let mut out: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && out.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        out.push(line);
+    }
+    while out.last().is_some_and(|line| line.is_empty()) {
+        out.pop();
+    }
+    out.join("\n")
+}
@@ -1,10 +1,19 @@
 use serde::Serialize;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tauri::Emitter;
 use uuid::Uuid;
 
+use crate::checkpoint;
+use crate::diff::FileDiff;
+use crate::git;
+use crate::notebook;
 use crate::oauth::{common_headers, ensure_fresh_token};
+use crate::patch;
+use crate::permissions::{self, Decision, PermissionMode};
+use crate::repo_map::RepoMap;
 use crate::tools;
+use crate::workspace;
 use crate::AppState;
 
 #[derive(Clone, Serialize)]
@@ -13,8 +22,24 @@ pub struct StreamEvent {
     pub data: serde_json::Value,
 }
 
+/// The user's answer to a `tool_approval` event. When `remember_rule` is set
+/// on an approval, the rule is saved as an allow rule in `remember_scope`.
+#[derive(Debug, Default)]
+pub struct ApprovalResponse {
+    pub approved: bool,
+    pub remember_rule: Option<String>,
+    pub remember_scope: Option<String>,
+}
+
 const MAX_TOOL_STEPS: usize = 20;
 
+/// Token budget for the primary root's repo map; other roots get half.
+const REPO_MAP_TOKENS: usize = 2_000;
+
+const PLAN_MODE_PROMPT: &str = "Plan mode is active. Investigate the request with read-only tools only; \
+do not modify files or run commands that change state. When you understand what needs to be done, \
+call SubmitPlan with a summary and ordered steps. Do not start implementing until the plan is approved.";
+
 fn emit_stream_event(window: &tauri::Window, event_target: &str, event: StreamEvent) {
     let _ = window.emit(event_target, event);
 }
@@ -25,66 +50,30 @@ fn api_base_url() -> String {
         .unwrap_or_else(|_| "https://api.kimi.com/coding/v1".to_string())
 }
 
-/// Generate a detailed directory listing like `ls -la`
-fn list_directory(work_dir: &str) -> String {
-    let work_path = Path::new(work_dir);
-    let mut entries: Vec<(String, bool, u64)> = Vec::new();
-
-    if let Ok(dir_entries) = std::fs::read_dir(work_path) {
-        for entry in dir_entries.flatten() {
-            let name = entry.file_name().to_string_lossy().to_string();
-            // Skip hidden files and common build directories
-            if name.starts_with('.')
-                || name == "target"
-                || name == "node_modules"
-                || name == "dist"
-                || name == "build"
-            {
-                continue;
-            }
-
-            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
-            let size = if let Ok(metadata) = entry.metadata() {
-                metadata.len()
-            } else {
-                0
-            };
-
-            entries.push((name, is_dir, size));
-        }
+/// The repo map of a workspace root, built on first use and kept until the
+/// root changes.
+async fn repo_map(state: &AppState, root: &str) -> Option<Arc<RepoMap>> {
+    let cached = state
+        .repo_maps
+        .lock()
+        .ok()?
+        .get(root)
+        .filter(|map| map.is_fresh())
+        .cloned();
+    if cached.is_some() {
+        return cached;
     }
-
-    // Sort: directories first, then files
-    entries.sort_by(|a, b| match (a.1, b.1) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.0.cmp(&b.0),
-    });
-
-    let mut output = String::new();
-    output.push_str(&format!("total {}\n", entries.len()));
-
-    for (name, is_dir, size) in entries {
-        let size_str = if is_dir {
-            "-".to_string()
-        } else if size < 1024 {
-            format!("{}", size)
-        } else if size < 1024 * 1024 {
-            format!("{:.1}K", size as f64 / 1024.0)
-        } else {
-            format!("{:.1}M", size as f64 / (1024.0 * 1024.0))
-        };
-
-        let type_char = if is_dir { "d" } else { "-" };
-        let permissions = if is_dir { "rwxr-xr-x" } else { "rw-r--r--" };
-
-        output.push_str(&format!(
-            "{}{}  1 user  group  {:>8} Jan  1 00:00 {}\n",
-            type_char, permissions, size_str, name
-        ));
-    }
-
-    output
+    let path = PathBuf::from(root);
+    let map = tokio::task::spawn_blocking(move || RepoMap::build(&path))
+        .await
+        .map(Arc::new)
+        .ok()?;
+    state
+        .repo_maps
+        .lock()
+        .ok()?
+        .insert(root.to_string(), map.clone());
+    Some(map)
 }
 
 /// Read AGENTS.md if it exists
@@ -102,15 +91,22 @@ fn load_agents_md(work_dir: &str) -> Option<String> {
     None
 }
 
-fn generate_system_prompt(work_dir: &str, extra: Option<&str>) -> String {
+/// `maps` holds the repo map of each workspace root, in `roots` order.
+fn generate_system_prompt(
+    work_dir: &str,
+    roots: &[workspace::WorkspaceRoot],
+    maps: &[Option<Arc<RepoMap>>],
+    extra: Option<&str>,
+) -> String {
     let mut prompt = String::new();
 
-    // Add directory listing
-    let ls_output = list_directory(work_dir);
-    prompt.push_str(&format!(
-        "Current working directory: {}\n\nDirectory listing:\n{}\n",
-        work_dir, ls_output
-    ));
+    prompt.push_str(&format!("Current working directory: {}\n", work_dir));
+    if let Some(map) = &maps[0] {
+        prompt.push_str(&format!(
+            "\nRepository map:\n{}",
+            map.render(REPO_MAP_TOKENS)
+        ));
+    }
 
     // Add AGENTS.md if exists
     if let Some(agents_md) = load_agents_md(work_dir) {
@@ -119,6 +115,28 @@ fn generate_system_prompt(work_dir: &str, extra: Option<&str>) -> String {
         prompt.push('\n');
     }
 
+    // Other workspace roots, addressed as `name:relative/path`
+    if roots.len() > 1 {
+        prompt.push_str(&format!(
+            "\nThis workspace has several roots. Paths without a root name are relative to the working directory ({}). Address files in the other roots as `name:relative/path`, e.g. `{}:README.md`.\n",
+            roots[0].name, roots[1].name
+        ));
+        for (root, map) in roots.iter().zip(maps).skip(1) {
+            prompt.push_str(&format!("\nRoot `{}`: {}\n", root.name, root.path));
+            if let Some(map) = map {
+                prompt.push_str(&format!(
+                    "\nRepository map:\n{}",
+                    map.render(REPO_MAP_TOKENS / 2)
+                ));
+            }
+            if let Some(agents_md) = load_agents_md(&root.path) {
+                prompt.push_str(&format!("\nAGENTS.md ({}):\n", root.name));
+                prompt.push_str(&agents_md);
+                prompt.push('\n');
+            }
+        }
+    }
+
     if let Some(extra) = extra {
         let extra = extra.trim();
         if !extra.is_empty() {
@@ -131,6 +149,28 @@ fn generate_system_prompt(work_dir: &str, extra: Option<&str>) -> String {
     prompt
 }
 
+fn available_tools(
+    agent_browser_available: bool,
+    permission_mode: PermissionMode,
+) -> Vec<serde_json::Value> {
+    let mut tools_def = tools::tool_definitions();
+    tools_def.retain(|tool| {
+        let name = tool
+            .get("function")
+            .and_then(|value| value.get("name"))
+            .and_then(|value| value.as_str())
+            .unwrap_or_default();
+        if agent_browser_available && (name == "SearchWeb" || name == "FetchURL") {
+            return false;
+        }
+        !(permission_mode == PermissionMode::Plan && permissions::is_mutating_tool(name))
+    });
+    if permission_mode == PermissionMode::Plan {
+        tools_def.push(tools::submit_plan_definition());
+    }
+    tools_def
+}
+
 fn parse_user_input(input: &str) -> String {
     // For now, just return the input as-is
     // Future: parse @file, $skill, etc. and load content
@@ -198,18 +238,28 @@ pub async fn stream_chat(
     let client = reqwest::Client::new();
 
     // Build system prompt with directory context
-    let system_prompt = generate_system_prompt(&work_dir, extra_system_prompt.as_deref());
-    let mut tools_def = tools::tool_definitions();
-    if agent_browser_available {
-        tools_def.retain(|tool| {
-            let name = tool
-                .get("function")
-                .and_then(|value| value.get("name"))
-                .and_then(|value| value.as_str())
-                .unwrap_or_default();
-            name != "SearchWeb" && name != "FetchURL"
-        });
+    let roots = workspace::roots(&work_dir);
+    let mut maps = Vec::with_capacity(roots.len());
+    for root in &roots {
+        maps.push(repo_map(&state, &root.path).await);
     }
+    let system_prompt =
+        generate_system_prompt(&work_dir, &roots, &maps, extra_system_prompt.as_deref());
+    let mut permission_mode = state
+        .permission_modes
+        .lock()
+        .map_err(|_| "Permission mode store poisoned".to_string())?
+        .get(&session_id)
+        .copied()
+        .unwrap_or_default();
+    let system_prompt = if permission_mode == PermissionMode::Plan {
+        format!("{system_prompt}\n{PLAN_MODE_PROMPT}\n")
+    } else {
+        system_prompt
+    };
+    let mut tools_def = available_tools(agent_browser_available, permission_mode);
+    // File edits made during this turn are checkpointed under this id.
+    let turn_id = Uuid::new_v4().to_string();
     let mut messages = vec![
         serde_json::json!({
             "role": "system",
@@ -334,6 +384,7 @@ pub async fn stream_chat(
                     .cloned()
                     .unwrap_or_default();
 
+                let mut images = Vec::new();
                 for tool_call in calls {
                     if cancel_rx.try_recv().is_ok() {
                         emit_stream_event(
@@ -371,7 +422,141 @@ pub async fn stream_chat(
                         tool_call_id = Uuid::new_v4().to_string();
                     }
 
-                    let approved = if needs_approval(&name) && !auto_approve {
+                    let label = tool_label(&name, &args_value);
+
+                    if name == "SubmitPlan" {
+                        let output = if permission_mode != PermissionMode::Plan {
+                            tools::ToolOutput {
+                                ok: false,
+                                summary: "SubmitPlan is only available in plan mode.".to_string(),
+                                output: String::new(),
+                            }
+                        } else {
+                            // Plans always need an explicit decision, even in yolo mode.
+                            let response = match request_approval(
+                                &window,
+                                &state,
+                                event_target,
+                                &session_id,
+                                &tool_call_id,
+                                &name,
+                                &args_value,
+                                None,
+                                None,
+                                &mut cancel_rx,
+                            )
+                            .await
+                            {
+                                Ok(response) => response,
+                                Err(_) => {
+                                    emit_stream_event(
+                                        &window,
+                                        event_target,
+                                        StreamEvent {
+                                            event: "cancelled".to_string(),
+                                            data: serde_json::json!({
+                                                "session_id": session_id,
+                                            }),
+                                        },
+                                    );
+                                    return Ok(());
+                                }
+                            };
+                            if response.approved {
+                                permission_mode = PermissionMode::Default;
+                                set_permission_mode(
+                                    &window,
+                                    &state,
+                                    event_target,
+                                    &session_id,
+                                    permission_mode,
+                                );
+                                tools_def =
+                                    available_tools(agent_browser_available, permission_mode);
+                                tools::ToolOutput {
+                                    ok: true,
+                                    summary: "Plan approved. Plan mode has ended; carry out the plan now.".to_string(),
+                                    output: String::new(),
+                                }
+                            } else {
+                                tools::ToolOutput {
+                                    ok: false,
+                                    summary: "User rejected the plan. Revise it or ask what should change.".to_string(),
+                                    output: String::new(),
+                                }
+                            }
+                        };
+                        emit_tool_status(
+                            &window,
+                            event_target,
+                            &session_id,
+                            &tool_call_id,
+                            "end",
+                            &name,
+                            &label,
+                            Some(output.ok),
+                            Some(output.summary.clone()),
+                            None,
+                        );
+                        push_tool_result(
+                            &window,
+                            event_target,
+                            &session_id,
+                            &tool_call_id,
+                            &name,
+                            &output,
+                            &mut messages,
+                        );
+                        continue;
+                    }
+
+                    let default_decision = if needs_approval(&name, &args_value) {
+                        Decision::Ask
+                    } else {
+                        Decision::Allow
+                    };
+                    let evaluation =
+                        permissions::evaluate(&work_dir, &name, &args_value, default_decision);
+                    let rule = evaluation.rule.as_deref();
+
+                    // Calls refused up front are reported back to the model
+                    // without asking the user.
+                    let mut preview = None;
+                    let plan_refusal = if permission_mode == PermissionMode::Plan {
+                        permissions::plan_mode_refusal(&name, &args_value)
+                    } else {
+                        None
+                    };
+                    let refusal = if plan_refusal.is_some() {
+                        plan_refusal
+                    } else if evaluation.decision == Decision::Deny && evaluation.protected {
+                        Some(format!(
+                            "Access denied: the path is protected by {}.",
+                            rule.unwrap_or_default()
+                        ))
+                    } else if evaluation.decision == Decision::Deny {
+                        Some(format!(
+                            "Tool call denied by permission rule {}.",
+                            rule.unwrap_or_default()
+                        ))
+                    } else {
+                        match edit_preview(&name, &args_value, &work_dir) {
+                            Some(Ok(diff)) => {
+                                preview = Some(diff);
+                                None
+                            }
+                            Some(Err(reason)) => Some(format!("Edit cannot be applied: {reason}")),
+                            None => None,
+                        }
+                    };
+
+                    // Yolo mode skips default prompts, but explicit ask rules and protected
+                    // paths still apply.
+                    let must_ask = evaluation.decision == Decision::Ask
+                        && (evaluation.rule.is_some() || !auto_approve);
+                    let approved = if refusal.is_some() {
+                        false
+                    } else if must_ask {
                         match request_approval(
                             &window,
                             &state,
@@ -380,11 +565,40 @@ pub async fn stream_chat(
                             &tool_call_id,
                             &name,
                             &args_value,
+                            preview.as_ref(),
+                            if evaluation.protected {
+                                None
+                            } else {
+                                permissions::suggest_rule(&name, &args_value, &work_dir)
+                            },
                             &mut cancel_rx,
                         )
                         .await
                         {
-                            Ok(value) => value,
+                            Ok(response) => {
+                                if let (true, Some(rule)) =
+                                    (response.approved, response.remember_rule.as_deref())
+                                {
+                                    let scope =
+                                        response.remember_scope.as_deref().unwrap_or("workspace");
+                                    if let Err(message) =
+                                        permissions::add_allow_rule(&work_dir, scope, rule)
+                                    {
+                                        emit_stream_event(
+                                            &window,
+                                            event_target,
+                                            StreamEvent {
+                                                event: "rule_error".to_string(),
+                                                data: serde_json::json!({
+                                                    "session_id": session_id,
+                                                    "message": format!("Could not remember {rule}: {message}"),
+                                                }),
+                                            },
+                                        );
+                                    }
+                                }
+                                response.approved
+                            }
                             Err(_) => {
                                 emit_stream_event(
                                     &window,
@@ -403,32 +617,71 @@ pub async fn stream_chat(
                         true
                     };
 
-                    let label = tool_label(&name, &args_value);
-                    let output = if approved {
+                    let mut output = if let Some(summary) = refusal {
                         emit_tool_status(
                             &window,
                             event_target,
                             &session_id,
                             &tool_call_id,
-                            "start",
+                            "end",
                             &name,
                             &label,
-                            None,
-                            None,
+                            Some(false),
+                            Some(summary.clone()),
+                            rule,
                         );
 
-                        let tool_output = execute_tool(
+                        tools::ToolOutput {
+                            ok: false,
+                            summary,
+                            output: String::new(),
+                        }
+                    } else if approved {
+                        emit_tool_status(
                             &window,
-                            &state,
+                            event_target,
                             &session_id,
                             &tool_call_id,
+                            "start",
                             &name,
-                            &args_value,
+                            &label,
+                            None,
+                            None,
+                            rule,
+                        );
+
+                        let checkpoint = checkpoint::snapshot(
+                            &session_id,
+                            &turn_id,
                             &work_dir,
-                            config_path.as_deref(),
-                            agent_browser_available,
-                        )
-                        .await;
+                            &user_message,
+                            &name,
+                            &tool_call_id,
+                            &edit_targets(&name, &args_value, &work_dir).await,
+                        );
+                        let tool_output = if let Err(err) = checkpoint {
+                            tools::ToolOutput {
+                                ok: false,
+                                summary: format!(
+                                    "Failed to checkpoint files before editing: {err}"
+                                ),
+                                output: String::new(),
+                            }
+                        } else {
+                            execute_tool(
+                                &window,
+                                &state,
+                                &session_id,
+                                &tool_call_id,
+                                &name,
+                                &args_value,
+                                &work_dir,
+                                config_path.as_deref(),
+                                agent_browser_available,
+                                permission_mode,
+                            )
+                            .await
+                        };
 
                         emit_tool_status(
                             &window,
@@ -440,6 +693,7 @@ pub async fn stream_chat(
                             &label,
                             Some(tool_output.ok),
                             Some(tool_output.summary.clone()),
+                            rule,
                         );
 
                         tool_output
@@ -454,6 +708,7 @@ pub async fn stream_chat(
                             &label,
                             Some(false),
                             Some("User rejected tool request.".to_string()),
+                            rule,
                         );
 
                         tools::ToolOutput {
@@ -463,33 +718,34 @@ pub async fn stream_chat(
                         }
                     };
 
-                    emit_stream_event(
+                    if let Some(image) =
+                        image_attachment(&name, &args_value, &work_dir, &model, &mut output)
+                    {
+                        images.push(image);
+                    }
+
+                    push_tool_result(
                         &window,
                         event_target,
-                        StreamEvent {
-                            event: "tool_result".to_string(),
-                            data: serde_json::json!({
-                                "session_id": session_id,
-                                "tool_call_id": tool_call_id,
-                                "name": name,
-                                "ok": output.ok,
-                                "summary": output.summary,
-                                "output": output.output,
-                            }),
-                        },
+                        &session_id,
+                        &tool_call_id,
+                        &name,
+                        &output,
+                        &mut messages,
                     );
+                }
 
-                    let tool_content = serde_json::json!({
-                        "ok": output.ok,
-                        "summary": output.summary,
-                        "output": output.output,
-                    })
-                    .to_string();
-
+                // Images cannot be part of tool messages, so they follow the
+                // tool results as a user message.
+                if !images.is_empty() {
+                    let mut parts = vec![serde_json::json!({
+                        "type": "text",
+                        "text": "Images read by ReadFile:",
+                    })];
+                    parts.extend(images);
                     messages.push(serde_json::json!({
-                        "role": "tool",
-                        "tool_call_id": tool_call_id,
-                        "content": tool_content,
+                        "role": "user",
+                        "content": parts,
                     }));
                 }
 
@@ -593,8 +849,204 @@ pub async fn llm_fetch_models(
     Ok(models)
 }
 
-fn needs_approval(tool_name: &str) -> bool {
-    matches!(tool_name, "Shell" | "WriteFile" | "StrReplaceFile")
+/// Emit the `tool_result` event and append the tool message to the history.
+fn push_tool_result(
+    window: &tauri::Window,
+    event_target: &str,
+    session_id: &str,
+    tool_call_id: &str,
+    name: &str,
+    output: &tools::ToolOutput,
+    messages: &mut Vec<serde_json::Value>,
+) {
+    emit_stream_event(
+        window,
+        event_target,
+        StreamEvent {
+            event: "tool_result".to_string(),
+            data: serde_json::json!({
+                "session_id": session_id,
+                "tool_call_id": tool_call_id,
+                "name": name,
+                "ok": output.ok,
+                "summary": output.summary,
+                "output": output.output,
+            }),
+        },
+    );
+
+    let tool_content = serde_json::json!({
+        "ok": output.ok,
+        "summary": output.summary,
+        "output": output.output,
+    })
+    .to_string();
+
+    messages.push(serde_json::json!({
+        "role": "tool",
+        "tool_call_id": tool_call_id,
+        "content": tool_content,
+    }));
+}
+
+fn parse_edits(args: &serde_json::Value) -> Vec<tools::ReplaceEdit> {
+    let mut edits = Vec::new();
+    if let Some(edit_value) = args.get("edit") {
+        if edit_value.is_array() {
+            if let Ok(list) = serde_json::from_value::<Vec<tools::ReplaceEdit>>(edit_value.clone())
+            {
+                edits = list;
+            }
+        } else if let Ok(edit) = serde_json::from_value::<tools::ReplaceEdit>(edit_value.clone()) {
+            edits.push(edit);
+        }
+    }
+    edits
+}
+
+fn parse_match_mode(args: &serde_json::Value) -> Result<tools::MatchMode, String> {
+    match args.get("match_mode").and_then(|v| v.as_str()) {
+        None => Ok(tools::MatchMode::Exact),
+        Some(value) => {
+            tools::MatchMode::parse(value).ok_or_else(|| format!("Invalid match_mode: {value}"))
+        }
+    }
+}
+
+/// Diff of the change a file-editing tool call would make, if it is one.
+fn edit_preview(
+    name: &str,
+    args: &serde_json::Value,
+    work_dir: &str,
+) -> Option<Result<FileDiff, String>> {
+    match name {
+        "WriteFile" => {
+            let path = args.get("path").and_then(|v| v.as_str())?;
+            let content = args.get("content").and_then(|v| v.as_str())?;
+            let mode = args
+                .get("mode")
+                .and_then(|v| v.as_str())
+                .unwrap_or("overwrite");
+            Some(tools::preview_write_file(work_dir, path, content, mode))
+        }
+        "StrReplaceFile" => {
+            let path = args.get("path").and_then(|v| v.as_str())?;
+            let edits = parse_edits(args);
+            if edits.is_empty() {
+                return None;
+            }
+            Some(
+                parse_match_mode(args)
+                    .and_then(|mode| tools::preview_str_replace_file(work_dir, path, &edits, mode)),
+            )
+        }
+        "ApplyPatch" => {
+            Some(patch::parse(args).and_then(|changes| patch::preview(work_dir, &changes)))
+        }
+        "EditNotebook" => {
+            let path = args.get("path").and_then(|v| v.as_str())?;
+            Some(
+                notebook::parse_edits(args)
+                    .and_then(|edits| notebook::preview_edit_notebook(work_dir, path, &edits)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Whether a model accepts image content parts.
+fn model_supports_vision(model: &str) -> bool {
+    let model = model.to_lowercase();
+    model.contains("vision") || model.contains("-vl") || model.starts_with("kimi-latest")
+}
+
+/// For a successful `ReadFile` of a PNG or JPEG, note in the result whether
+/// the image can be shown and return the content part that shows it.
+fn image_attachment(
+    name: &str,
+    args: &serde_json::Value,
+    work_dir: &str,
+    model: &str,
+    output: &mut tools::ToolOutput,
+) -> Option<serde_json::Value> {
+    if name != "ReadFile" || !output.ok {
+        return None;
+    }
+    let path = args.get("path").and_then(|v| v.as_str())?;
+    match tools::image_data_url(work_dir, path) {
+        Ok(Some(url)) if model_supports_vision(model) => {
+            output
+                .summary
+                .push_str(" The image is attached in the next message.");
+            Some(serde_json::json!({
+                "type": "image_url",
+                "image_url": { "url": url },
+            }))
+        }
+        Ok(Some(_)) => {
+            output
+                .summary
+                .push_str(" The current model cannot view images.");
+            None
+        }
+        Ok(None) => None,
+        Err(err) => {
+            output.summary.push_str(&format!(" {err}"));
+            None
+        }
+    }
+}
+
+/// Record the session's permission mode and tell the UI about the change.
+fn set_permission_mode(
+    window: &tauri::Window,
+    state: &tauri::State<'_, AppState>,
+    event_target: &str,
+    session_id: &str,
+    mode: PermissionMode,
+) {
+    if let Ok(mut modes) = state.permission_modes.lock() {
+        modes.insert(session_id.to_string(), mode);
+    }
+    emit_stream_event(
+        window,
+        event_target,
+        StreamEvent {
+            event: "permission_mode".to_string(),
+            data: serde_json::json!({
+                "session_id": session_id,
+                "mode": mode.as_str(),
+            }),
+        },
+    );
+}
+
+/// Files a tool call would modify, for checkpointing before it runs.
+async fn edit_targets(name: &str, args: &serde_json::Value, work_dir: &str) -> Vec<PathBuf> {
+    if name == "Git" {
+        return git::edit_targets(work_dir, args).await;
+    }
+    if !permissions::is_mutating_tool(name) {
+        return Vec::new();
+    }
+    if name == "ApplyPatch" {
+        return patch::parse(args)
+            .and_then(|changes| patch::target_paths(work_dir, &changes))
+            .unwrap_or_default();
+    }
+    args.get("path")
+        .and_then(|v| v.as_str())
+        .and_then(|path| tools::resolve_path(work_dir, path, false).ok())
+        .into_iter()
+        .collect()
+}
+
+fn needs_approval(tool_name: &str, args: &serde_json::Value) -> bool {
+    match tool_name {
+        "Shell" | "WriteFile" | "StrReplaceFile" | "ApplyPatch" | "EditNotebook" => true,
+        "Git" => !git::is_read_only(args),
+        _ => false,
+    }
 }
 
 fn emit_tool_status(
@@ -607,6 +1059,7 @@ fn emit_tool_status(
     label: &str,
     ok: Option<bool>,
     summary: Option<String>,
+    rule: Option<&str>,
 ) {
     emit_stream_event(
         window,
@@ -621,6 +1074,7 @@ fn emit_tool_status(
                 "label": label,
                 "ok": ok,
                 "summary": summary,
+                "rule": rule,
             }),
         },
     );
@@ -648,6 +1102,18 @@ fn tool_label(name: &str, args: &serde_json::Value) -> String {
             .and_then(|v| v.as_str())
             .map(|p| format!("正在修改 {}", p))
             .unwrap_or_else(|| "正在修改文件".to_string()),
+        "ApplyPatch" => "正在应用补丁".to_string(),
+        "Git" => format!("正在执行 git {}", git::rule_subject(args)),
+        "ReadNotebook" => args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .map(|p| format!("正在读取笔记本 {}", p))
+            .unwrap_or_else(|| "正在读取笔记本".to_string()),
+        "EditNotebook" => args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .map(|p| format!("正在编辑笔记本 {}", p))
+            .unwrap_or_else(|| "正在编辑笔记本".to_string()),
         "SearchWeb" => args
             .get("query")
             .and_then(|v| v.as_str())
@@ -658,6 +1124,7 @@ fn tool_label(name: &str, args: &serde_json::Value) -> String {
             .and_then(|v| v.as_str())
             .map(|u| format!("正在抓取 {}", u))
             .unwrap_or_else(|| "正在抓取网页".to_string()),
+        "SubmitPlan" => "正在提交计划".to_string(),
         _ => format!("正在执行 {}", name),
     }
 }
@@ -670,8 +1137,10 @@ async fn request_approval(
     tool_call_id: &str,
     name: &str,
     args: &serde_json::Value,
+    preview: Option<&FileDiff>,
+    suggested_rule: Option<String>,
     cancel_rx: &mut tokio::sync::oneshot::Receiver<()>,
-) -> Result<bool, String> {
+) -> Result<ApprovalResponse, String> {
     let request_id = format!("{}:{}", session_id, tool_call_id);
     let (tx, rx) = tokio::sync::oneshot::channel();
 
@@ -693,6 +1162,8 @@ async fn request_approval(
                 "request_id": request_id,
                 "name": name,
                 "args": args,
+                "preview": preview,
+                "suggested_rule": suggested_rule,
             }),
         },
     );
@@ -707,7 +1178,7 @@ async fn request_approval(
             return Err("Cancelled".to_string());
         }
         result = rx => {
-            result.unwrap_or(false)
+            result.unwrap_or_default()
         }
     };
 
@@ -724,7 +1195,18 @@ async fn execute_tool(
     work_dir: &str,
     config_path: Option<&str>,
     agent_browser_available: bool,
+    permission_mode: PermissionMode,
 ) -> tools::ToolOutput {
+    if permission_mode == PermissionMode::Plan {
+        if let Some(summary) = permissions::plan_mode_refusal(name, args) {
+            return tools::ToolOutput {
+                ok: false,
+                summary,
+                output: String::new(),
+            };
+        }
+    }
+
     match name {
         "ReadFile" => {
             let path = match args.get("path").and_then(|v| v.as_str()) {
@@ -742,7 +1224,8 @@ async fn execute_tool(
                 .and_then(|v| v.as_u64())
                 .unwrap_or(1) as usize;
             let n_lines = args.get("n_lines").and_then(|v| v.as_u64()).unwrap_or(1000) as usize;
-            tools::read_file(work_dir, path, line_offset, n_lines)
+            let encoding = args.get("encoding").and_then(|v| v.as_str());
+            tools::read_file(work_dir, path, line_offset, n_lines, encoding)
         }
         "Shell" => {
             let command = match args.get("command").and_then(|v| v.as_str()) {
@@ -797,21 +1280,7 @@ async fn execute_tool(
                 }
             };
 
-            let mut edits = Vec::new();
-            if let Some(edit_value) = args.get("edit") {
-                if edit_value.is_array() {
-                    if let Ok(list) =
-                        serde_json::from_value::<Vec<tools::ReplaceEdit>>(edit_value.clone())
-                    {
-                        edits = list;
-                    }
-                } else if let Ok(edit) =
-                    serde_json::from_value::<tools::ReplaceEdit>(edit_value.clone())
-                {
-                    edits.push(edit);
-                }
-            }
-
+            let edits = parse_edits(args);
             if edits.is_empty() {
                 return tools::ToolOutput {
                     ok: false,
@@ -820,8 +1289,51 @@ async fn execute_tool(
                 };
             }
 
-            tools::str_replace_file(work_dir, path, edits)
+            let mode = match parse_match_mode(args) {
+                Ok(mode) => mode,
+                Err(err) => {
+                    return tools::ToolOutput {
+                        ok: false,
+                        summary: err,
+                        output: String::new(),
+                    }
+                }
+            };
+
+            tools::str_replace_file(work_dir, path, edits, mode)
+        }
+        "ReadNotebook" | "EditNotebook" => {
+            let path = match args.get("path").and_then(|v| v.as_str()) {
+                Some(p) => p,
+                None => {
+                    return tools::ToolOutput {
+                        ok: false,
+                        summary: "Missing path".to_string(),
+                        output: String::new(),
+                    }
+                }
+            };
+            if name == "ReadNotebook" {
+                return notebook::read_notebook(work_dir, path);
+            }
+            match notebook::parse_edits(args) {
+                Ok(edits) => notebook::edit_notebook(work_dir, path, &edits),
+                Err(err) => tools::ToolOutput {
+                    ok: false,
+                    summary: err,
+                    output: String::new(),
+                },
+            }
         }
+        "Git" => git::run(work_dir, args).await,
+        "ApplyPatch" => match patch::parse(args) {
+            Ok(changes) => patch::apply(work_dir, &changes),
+            Err(err) => tools::ToolOutput {
+                ok: false,
+                summary: err,
+                output: String::new(),
+            },
+        },
         "SearchWeb" => {
             if agent_browser_available {
                 return tools::ToolOutput {
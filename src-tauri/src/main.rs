@@ -1,17 +1,36 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod checkpoint;
+mod content;
+mod diff;
+mod dir_tree;
+mod fetch;
+mod file_index;
+mod file_ops;
+mod git;
+mod html;
 mod llm;
+mod notebook;
 mod oauth;
+mod patch;
+mod pdf;
+mod permissions;
+mod repo_map;
+mod search;
 mod session;
+mod text_search;
 mod tools;
+mod watcher;
+mod workspace;
+mod worktree;
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use tauri::{Emitter, Manager};
 //
 
@@ -47,6 +66,8 @@ struct GuiSettings {
     yolo: Option<bool>,
     pinned_sessions: Vec<String>,
     pinned_cowork_tasks: Vec<String>,
+    /// Glob patterns hidden from the file tree; `None` uses the defaults.
+    hidden_files: Option<Vec<String>>,
 }
 
 #[derive(Clone, Serialize)]
@@ -265,7 +286,16 @@ struct AppState {
     sessions: Mutex<HashMap<u64, SessionHandle>>,
     next_id: AtomicU64,
     session_manager: Mutex<SessionManager>,
-    approvals: Mutex<HashMap<String, tokio::sync::oneshot::Sender<bool>>>,
+    approvals: Mutex<HashMap<String, tokio::sync::oneshot::Sender<llm::ApprovalResponse>>>,
+    permission_modes: Mutex<HashMap<String, permissions::PermissionMode>>,
+    file_indexes: Mutex<HashMap<String, Arc<file_index::FileIndex>>>,
+    repo_maps: Mutex<HashMap<String, Arc<repo_map::RepoMap>>>,
+    /// Ignore rules of each file tree root, kept until the root changes or
+    /// its tree is reloaded.
+    root_ignores: Mutex<HashMap<String, Arc<file_index::IgnoreStack>>>,
+    /// Per workspace, the watcher of each of its roots.
+    watchers: Mutex<HashMap<String, HashMap<String, watcher::WatchHandle>>>,
+    searches: Mutex<HashMap<String, Arc<AtomicBool>>>,
 }
 
 struct SessionHandle {
@@ -279,6 +309,12 @@ impl Default for AppState {
             next_id: AtomicU64::new(1),
             session_manager: Mutex::new(SessionManager::new()),
             approvals: Mutex::new(HashMap::new()),
+            permission_modes: Mutex::new(HashMap::new()),
+            file_indexes: Mutex::new(HashMap::new()),
+            repo_maps: Mutex::new(HashMap::new()),
+            root_ignores: Mutex::new(HashMap::new()),
+            watchers: Mutex::new(HashMap::new()),
+            searches: Mutex::new(HashMap::new()),
         }
     }
 }
@@ -287,7 +323,8 @@ fn home_dir() -> PathBuf {
     dirs::home_dir().unwrap_or_else(|| PathBuf::from("."))
 }
 
-fn kimi_share_dir() -> PathBuf {
+/// Where the app keeps its own settings and data.
+pub(crate) fn kimi_share_dir() -> PathBuf {
     home_dir().join(".kimicodegui")
 }
 
@@ -1145,17 +1182,39 @@ fn session_save_message(
 }
 
 #[tauri::command]
-fn session_delete(
+async fn session_delete(
     state: tauri::State<'_, AppState>,
     work_dir: String,
     session_id: String,
 ) -> Result<(), String> {
-    let mut manager = state
-        .session_manager
-        .lock()
-        .map_err(|_| "Session manager poisoned".to_string())?;
-    manager.delete_session(&work_dir, &session_id)?;
-    Ok(())
+    {
+        let mut manager = state
+            .session_manager
+            .lock()
+            .map_err(|_| "Session manager poisoned".to_string())?;
+        manager.delete_session(&work_dir, &session_id)?;
+    }
+    checkpoint::delete_session(&session_id)?;
+    if let Ok(mut modes) = state.permission_modes.lock() {
+        modes.remove(&session_id);
+    }
+    worktree::delete_session(&session_id).await
+}
+
+/// Where a session's agent works: its git worktree when `worktree` asks for
+/// one, or is unset and the session already has one, otherwise `work_dir`
+/// itself.
+async fn session_work_dir(
+    session_id: &str,
+    work_dir: String,
+    worktree: Option<bool>,
+) -> Result<String, String> {
+    let use_worktree = worktree.unwrap_or_else(|| worktree::load(session_id).is_some());
+    if use_worktree {
+        Ok(worktree::prepare(session_id, &work_dir).await?.work_dir)
+    } else {
+        Ok(work_dir)
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -1165,6 +1224,7 @@ pub struct CoworkStreamEvent {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn cowork_stream(
     window: tauri::Window,
     state: tauri::State<'_, AppState>,
@@ -1173,7 +1233,10 @@ async fn cowork_stream(
     folder: String,
     model: String,
     system_prompt: String,
+    worktree: Option<bool>,
 ) -> Result<(), String> {
+    let folder = session_work_dir(&session_id, folder, worktree).await?;
+
     // Load auth config
     let auth_config = load_auth_config();
 
@@ -1270,11 +1333,22 @@ async fn chat_stream(
     session_id: String,
     message: String,
     settings: Option<GuiSettings>,
+    permission_mode: Option<String>,
+    worktree: Option<bool>,
 ) -> Result<(), String> {
     use crate::session::Message as SessionMessage;
 
     let settings = settings.unwrap_or_default();
 
+    if let Some(mode) = permission_mode {
+        let mode = permissions::PermissionMode::parse(&mode)?;
+        state
+            .permission_modes
+            .lock()
+            .map_err(|_| "Permission mode store poisoned".to_string())?
+            .insert(session_id.clone(), mode);
+    }
+
     let model = settings
         .model
         .filter(|m| !m.is_empty())
@@ -1318,6 +1392,8 @@ async fn chat_stream(
         let _ = manager.add_message(&session_id, user_msg);
     }
 
+    let work_dir = session_work_dir(&session_id, work_dir, worktree).await?;
+
     let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
 
     {
@@ -1377,13 +1453,19 @@ fn tool_approval_respond(
     state: tauri::State<'_, AppState>,
     request_id: String,
     approved: bool,
+    remember_rule: Option<String>,
+    remember_scope: Option<String>,
 ) -> Result<(), String> {
     let mut approvals = state
         .approvals
         .lock()
         .map_err(|_| "Approval store poisoned".to_string())?;
     if let Some(tx) = approvals.remove(&request_id) {
-        let _ = tx.send(approved);
+        let _ = tx.send(llm::ApprovalResponse {
+            approved,
+            remember_rule: remember_rule.filter(|rule| !rule.trim().is_empty()),
+            remember_scope,
+        });
         Ok(())
     } else {
         Err("Approval request not found".to_string())
@@ -1404,76 +1486,171 @@ fn cancel_chat(state: tauri::State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
+/// The work dir's file index, rebuilt when stale or when `refresh` is set.
+async fn cached_file_index(
+    state: &AppState,
+    work_dir: String,
+    refresh: bool,
+) -> Result<Arc<file_index::FileIndex>, String> {
+    let cached = state
+        .file_indexes
+        .lock()
+        .map_err(|_| "File index store poisoned".to_string())?
+        .get(&work_dir)
+        .filter(|index| index.is_fresh() && !refresh)
+        .cloned();
+    if let Some(index) = cached {
+        return Ok(index);
+    }
+    // Other workspace roots are listed as `name:path`.
+    let roots = workspace::roots(&work_dir)
+        .into_iter()
+        .enumerate()
+        .map(|(index, root)| {
+            let prefix = if index == 0 {
+                String::new()
+            } else {
+                format!("{}:", root.name)
+            };
+            (prefix, PathBuf::from(root.path))
+        })
+        .collect();
+    let index = tokio::task::spawn_blocking(move || file_index::FileIndex::build_roots(roots))
+        .await
+        .map(Arc::new)
+        .map_err(|e| format!("Failed to index files: {}", e))?;
+    state
+        .file_indexes
+        .lock()
+        .map_err(|_| "File index store poisoned".to_string())?
+        .insert(work_dir, index.clone());
+    Ok(index)
+}
+
+/// Rank the work dir's files against `query` for the `@` picker. The index
+/// honours ignore files and is cached per work dir; pass `refresh` to
+/// rebuild it.
 #[tauri::command]
-fn list_files(work_dir: String, query: Option<String>) -> Result<Vec<String>, String> {
-    let root = Path::new(&work_dir);
-    if !root.exists() {
-        return Ok(Vec::new());
+async fn list_files(
+    state: tauri::State<'_, AppState>,
+    work_dir: String,
+    query: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    refresh: Option<bool>,
+) -> Result<file_index::FilePage, String> {
+    let offset = offset.unwrap_or(0);
+    let root = PathBuf::from(&work_dir);
+    if !root.is_dir() {
+        return Ok(file_index::FilePage {
+            files: Vec::new(),
+            total: 0,
+            offset,
+            truncated: false,
+        });
     }
 
-    let mut files = Vec::new();
-    let query_lower = query.unwrap_or_default().to_lowercase();
-
-    fn is_ignored(name: &str) -> bool {
-        let ignored = [
-            ".git",
-            ".svn",
-            ".hg",
-            ".DS_Store",
-            "node_modules",
-            "target",
-            "dist",
-            "build",
-            ".venv",
-            "venv",
-            "__pycache__",
-            ".pytest_cache",
-            ".idea",
-            ".vscode",
-            ".next",
-            ".nuxt",
-        ];
-        ignored.iter().any(|&i| name == i || name.starts_with('.'))
-    }
-
-    fn walk_dir(path: &Path, root: &Path, files: &mut Vec<String>, query: &str, limit: usize) {
-        if files.len() >= limit {
-            return;
-        }
+    let index = cached_file_index(&state, work_dir, refresh.unwrap_or(false)).await?;
+    let query = query.unwrap_or_default();
+    let limit = limit.unwrap_or(file_index::DEFAULT_PAGE_SIZE);
+    tokio::task::spawn_blocking(move || index.search(&query, offset, limit))
+        .await
+        .map_err(|e| format!("Failed to search files: {}", e))
+}
 
-        if let Ok(entries) = std::fs::read_dir(path) {
-            for entry in entries.flatten() {
-                if files.len() >= limit {
-                    break;
-                }
+/// Payload of the `search://event` event.
+#[derive(Clone, Serialize)]
+struct SearchEvent {
+    search_id: String,
+    #[serde(flatten)]
+    file: text_search::FileMatches,
+}
 
-                let name = entry.file_name().to_string_lossy().to_string();
-                if is_ignored(&name) {
-                    continue;
-                }
+/// Search file contents under the work dir, skipping ignored files. Each
+/// file with matches is emitted as a `search://event` while the search runs;
+/// the summary is returned at the end. Starting a search with the id of one
+/// still running cancels the older one.
+#[tauri::command]
+async fn search_in_files(
+    window: tauri::Window,
+    state: tauri::State<'_, AppState>,
+    search_id: String,
+    work_dir: String,
+    options: text_search::SearchOptions,
+) -> Result<text_search::SearchSummary, String> {
+    let searcher = text_search::Searcher::new(&options)?;
+    let root = PathBuf::from(&work_dir);
+    if !root.is_dir() {
+        return Err("Work dir is not a directory".to_string());
+    }
 
-                let path = entry.path();
-                let rel_path = path.strip_prefix(root).unwrap_or(&path);
-                let rel_str = rel_path.to_string_lossy().to_string();
+    let cancel = Arc::new(AtomicBool::new(false));
+    if let Some(previous) = state
+        .searches
+        .lock()
+        .map_err(|_| "Search store poisoned".to_string())?
+        .insert(search_id.clone(), cancel.clone())
+    {
+        previous.store(true, Ordering::Relaxed);
+    }
+
+    let index = cached_file_index(&state, work_dir, false).await;
+    let flag = cancel.clone();
+    let id = search_id.clone();
+    let result = match index {
+        Ok(index) => tokio::task::spawn_blocking(move || {
+            let mut summary = searcher.run(index.files(), &flag, &mut |file| {
+                let _ = window.emit(
+                    "search://event",
+                    SearchEvent {
+                        search_id: id.clone(),
+                        file,
+                    },
+                );
+            });
+            summary.truncated |= index.is_truncated();
+            summary
+        })
+        .await
+        .map_err(|e| format!("Failed to search files: {}", e)),
+        Err(error) => Err(error),
+    };
 
-                if query.is_empty() || rel_str.to_lowercase().contains(query) {
-                    files.push(rel_str);
-                }
+    let mut searches = state
+        .searches
+        .lock()
+        .map_err(|_| "Search store poisoned".to_string())?;
+    if searches
+        .get(&search_id)
+        .is_some_and(|current| Arc::ptr_eq(current, &cancel))
+    {
+        searches.remove(&search_id);
+    }
+    result
+}
 
-                if path.is_dir() {
-                    walk_dir(&path, root, files, query, limit);
-                }
-            }
-        }
+#[tauri::command]
+fn search_cancel(state: tauri::State<'_, AppState>, search_id: String) -> Result<(), String> {
+    if let Some(cancel) = state
+        .searches
+        .lock()
+        .map_err(|_| "Search store poisoned".to_string())?
+        .remove(&search_id)
+    {
+        cancel.store(true, Ordering::Relaxed);
     }
+    Ok(())
+}
 
-    walk_dir(root, root, &mut files, &query_lower, 50);
-    files.sort();
-    Ok(files)
+#[derive(Clone, Serialize)]
+struct FileContent {
+    content: String,
+    /// Pass back to `write_file` to reject the save if the file changed.
+    version: String,
 }
 
 #[tauri::command]
-fn read_file(work_dir: String, file_path: String) -> Result<String, String> {
+fn read_file(work_dir: String, file_path: String) -> Result<FileContent, String> {
     let root = Path::new(&work_dir);
     let full_path = root.join(&file_path);
 
@@ -1497,7 +1674,102 @@ fn read_file(work_dir: String, file_path: String) -> Result<String, String> {
         return Err("File too large (max 100KB)".to_string());
     }
 
-    std::fs::read_to_string(&canonical).map_err(|e| format!("Failed to read file: {}", e))
+    let bytes = std::fs::read(&canonical).map_err(|e| format!("Failed to read file: {}", e))?;
+    let content = String::from_utf8(bytes)
+        .map_err(|_| "Failed to read file: stream did not contain valid UTF-8".to_string())?;
+    Ok(FileContent {
+        version: file_ops::content_version(content.as_bytes()),
+        content,
+    })
+}
+
+/// Bytes returned by `read_file_range` when no length is given.
+const FILE_CHUNK_BYTES: u64 = 512 * 1024;
+
+#[derive(Clone, Serialize)]
+struct FileChunk {
+    content: String,
+    offset: u64,
+    next_offset: u64,
+    total_size: u64,
+    has_more: bool,
+    /// Version of the whole file for `write_file`, set on the first chunk.
+    version: Option<String>,
+}
+
+/// Read part of a file so large files can be viewed a chunk at a time.
+/// The range is widened or narrowed to whole UTF-8 characters; continue
+/// from `next_offset`.
+#[tauri::command]
+fn read_file_range(
+    work_dir: String,
+    file_path: String,
+    offset: Option<u64>,
+    length: Option<u64>,
+) -> Result<FileChunk, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let root = Path::new(&work_dir);
+    let full_path = root.join(&file_path);
+
+    // Security: ensure the path is within work_dir
+    let canonical = full_path
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve path: {}", e))?;
+    let canonical_root = root
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve work dir: {}", e))?;
+
+    if !canonical.starts_with(&canonical_root) {
+        return Err("Path is outside working directory".to_string());
+    }
+
+    let mut file =
+        std::fs::File::open(&canonical).map_err(|e| format!("Failed to open file: {}", e))?;
+    let total_size = file
+        .metadata()
+        .map_err(|e| format!("Failed to read file metadata: {}", e))?
+        .len();
+    let offset = offset.unwrap_or(0).min(total_size);
+    let length = length.unwrap_or(FILE_CHUNK_BYTES).max(1);
+
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    // Read up to 3 extra bytes so a character split by the range end can be
+    // completed.
+    let mut bytes = Vec::new();
+    file.take(length + 3)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    // Skip continuation bytes of a character that started before `offset`.
+    let start = bytes
+        .iter()
+        .take(3)
+        .take_while(|byte| (**byte & 0xC0) == 0x80)
+        .count();
+    let mut end = bytes.len().min(length as usize).max(start);
+    while end < bytes.len() && (bytes[end] & 0xC0) == 0x80 {
+        end += 1;
+    }
+
+    let next_offset = offset + end as u64;
+    let version = if offset == 0 {
+        Some(
+            file_ops::file_version(&canonical)
+                .map_err(|e| format!("Failed to read file: {}", e))?,
+        )
+    } else {
+        None
+    };
+    Ok(FileChunk {
+        content: String::from_utf8_lossy(&bytes[start..end]).to_string(),
+        offset: offset + start as u64,
+        next_offset,
+        total_size,
+        has_more: next_offset < total_size,
+        version,
+    })
 }
 
 #[tauri::command]
@@ -1510,25 +1782,38 @@ async fn pick_folder(app: tauri::AppHandle) -> Result<Option<String>, String> {
     Ok(folder.map(|p| p.to_string()))
 }
 
-#[derive(Clone, Serialize)]
-struct DirEntry {
-    name: String,
-    path: String,
-    is_dir: bool,
-    children: Option<Vec<DirEntry>>,
-}
-
 #[derive(Clone, Serialize)]
 struct DirTree {
     path: String,
     name: String,
-    entries: Vec<DirEntry>,
+    /// The first page of the root's entries; subdirectories are loaded
+    /// with `list_dir_children`.
+    entries: Vec<dir_tree::DirEntry>,
+    /// Visible entries in the root.
+    total: usize,
     git_status: Vec<GitStatusEntry>,
+    /// `None` outside a git work tree.
+    git_branch: Option<GitBranchInfo>,
+    /// The workspace's other roots, each with its own tree and git status.
+    roots: Vec<DirTree>,
+}
+
+/// Patterns hidden from the file tree, from the GUI settings.
+fn hidden_files() -> dir_tree::HiddenList {
+    let patterns = gui_settings_load(None)
+        .ok()
+        .and_then(|payload| payload.settings.hidden_files)
+        .unwrap_or_else(|| {
+            dir_tree::DEFAULT_HIDDEN
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        });
+    dir_tree::HiddenList::new(&patterns)
 }
 
-#[tauri::command]
-fn list_dir_tree(path: String) -> Result<DirTree, String> {
-    let root = Path::new(&path);
+async fn root_tree(state: &AppState, path: &str, name: String) -> Result<DirTree, String> {
+    let root = PathBuf::from(path);
     if !root.exists() {
         return Err("Path does not exist".to_string());
     }
@@ -1537,165 +1822,412 @@ fn list_dir_tree(path: String) -> Result<DirTree, String> {
         return Err("Path is not a directory".to_string());
     }
 
-    fn is_ignored(name: &str) -> bool {
-        let ignored = [
-            ".git",
-            ".svn",
-            ".hg",
-            ".DS_Store",
-            "node_modules",
-            "target",
-            "dist",
-            "build",
-            ".venv",
-            "venv",
-            "__pycache__",
-            ".pytest_cache",
-            ".idea",
-            ".vscode",
-            ".next",
-            ".nuxt",
-            "Cargo.lock",
-            "package-lock.json",
-            "yarn.lock",
-            "pnpm-lock.yaml",
-        ];
-        ignored.iter().any(|&i| name == i || name.starts_with('.'))
-    }
-
-    fn read_dir_recursive(path: &Path, root: &Path, depth: usize) -> Result<Vec<DirEntry>, String> {
-        if depth > 10 {
-            return Ok(Vec::new()); // Limit depth
-        }
-
-        let mut entries = Vec::new();
-
-        let dir_entries = match std::fs::read_dir(path) {
-            Ok(entries) => entries,
-            Err(_) => return Ok(Vec::new()), // Skip directories we can't read
-        };
-
-        for entry in dir_entries.flatten() {
-            let name = entry.file_name().to_string_lossy().to_string();
-
-            if is_ignored(&name) {
-                continue;
-            }
-
-            let full_path = entry.path();
-            let path_str = full_path.to_string_lossy().to_string();
-            let is_dir = full_path.is_dir();
-
-            let children = if is_dir && depth < 2 {
-                // Only load children for first 2 levels initially
-                Some(read_dir_recursive(&full_path, root, depth + 1)?)
-            } else if is_dir {
-                Some(Vec::new()) // Empty vec to indicate it has children but not loaded yet
-            } else {
-                None
-            };
-
-            entries.push(DirEntry {
-                name,
-                path: path_str,
-                is_dir,
-                children,
-            });
-        }
-
-        // Sort: directories first, then by name
-        entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-            (true, false) => std::cmp::Ordering::Less,
-            (false, true) => std::cmp::Ordering::Greater,
-            _ => a.name.cmp(&b.name),
-        });
-
-        Ok(entries)
+    // Reloading the tree picks up changed ignore files the watcher does not
+    // report, such as those above the root.
+    let listing_root = root.clone();
+    let (listing, ignores) = tokio::task::spawn_blocking(move || {
+        let ignores = Arc::new(file_index::IgnoreStack::for_root(&listing_root));
+        let listing = dir_tree::list_dir(
+            &listing_root,
+            &listing_root,
+            &ignores,
+            &hidden_files(),
+            0,
+            dir_tree::PAGE_SIZE,
+        );
+        (listing, ignores)
+    })
+    .await
+    .map_err(|e| format!("Failed to list directory: {}", e))?;
+    let listing = listing?;
+    if let Ok(mut stacks) = state.root_ignores.lock() {
+        stacks.insert(path.to_string(), ignores);
     }
 
-    let entries = read_dir_recursive(root, root, 0)?;
-
     // Get git status for all files
-    let git_status = get_git_status(root);
+    let (git_status, git_branch) = get_git_status(&root).await;
 
     Ok(DirTree {
-        path: path.clone(),
-        name: root
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("root")
-            .to_string(),
-        entries,
+        path: path.to_string(),
+        name,
+        entries: listing.entries,
+        total: listing.total,
         git_status,
+        git_branch,
+        roots: Vec::new(),
     })
 }
 
+/// The tree of the work dir, with the workspace's other roots alongside.
+#[tauri::command]
+async fn list_dir_tree(state: tauri::State<'_, AppState>, path: String) -> Result<DirTree, String> {
+    let mut roots = workspace::roots(&path).into_iter();
+    let primary = roots
+        .next()
+        .map(|root| root.name)
+        .unwrap_or_else(|| "root".to_string());
+    let mut tree = root_tree(&state, &path, primary).await?;
+    for root in roots {
+        // A root that cannot be listed is left out rather than failing the
+        // whole workspace.
+        if let Ok(root_tree) = root_tree(&state, &root.path, root.name).await {
+            tree.roots.push(root_tree);
+        }
+    }
+    Ok(tree)
+}
+
+/// Expand one directory of the tree rooted at `root`, a page at a time.
+#[tauri::command]
+async fn list_dir_children(
+    state: tauri::State<'_, AppState>,
+    root: String,
+    path: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<dir_tree::DirListing, String> {
+    let cached = state
+        .root_ignores
+        .lock()
+        .ok()
+        .and_then(|stacks| stacks.get(&root).cloned());
+    let listing_root = root.clone();
+    let (listing, ignores) = tokio::task::spawn_blocking(move || {
+        let root = Path::new(&listing_root);
+        let ignores = cached.unwrap_or_else(|| Arc::new(file_index::IgnoreStack::for_root(root)));
+        let listing = dir_tree::list_dir(
+            root,
+            Path::new(&path),
+            &ignores,
+            &hidden_files(),
+            offset.unwrap_or(0),
+            limit.unwrap_or(dir_tree::PAGE_SIZE),
+        );
+        (listing, ignores)
+    })
+    .await
+    .map_err(|e| format!("Failed to list directory: {}", e))?;
+    if let Ok(mut stacks) = state.root_ignores.lock() {
+        stacks.insert(root, ignores);
+    }
+    listing
+}
+
 #[derive(Clone, Serialize)]
 struct GitStatusEntry {
     path: String,
-    status: String, // "modified", "added", "deleted", "untracked", "staged"
+    status: String, // "modified", "added", "deleted", "renamed", "untracked", "staged", "conflicted", "ignored"
+    orig_path: Option<String>,
+    index: char,
+    worktree: char,
+    submodule: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct GitBranchInfo {
+    branch: Option<String>,
+    head: Option<String>,
+    upstream: Option<String>,
+    ahead: u32,
+    behind: u32,
+}
+
+impl From<&git::StatusEntry> for GitStatusEntry {
+    fn from(entry: &git::StatusEntry) -> Self {
+        let status = if entry.conflicted {
+            "conflicted"
+        } else if entry.ignored {
+            "ignored"
+        } else if entry.untracked {
+            "untracked"
+        } else if entry.index == 'D' || entry.worktree == 'D' {
+            "deleted"
+        } else if matches!(entry.index, 'R' | 'C') {
+            "renamed"
+        } else if entry.worktree != '.' {
+            "modified"
+        } else if entry.index == 'A' {
+            "added"
+        } else {
+            "staged"
+        };
+        GitStatusEntry {
+            // Untracked and ignored directories are reported with a trailing slash.
+            path: entry.path.trim_end_matches('/').to_string(),
+            status: status.to_string(),
+            orig_path: entry.orig_path.clone(),
+            index: entry.index,
+            worktree: entry.worktree,
+            submodule: entry.submodule.clone(),
+        }
+    }
+}
+
+async fn get_git_status(root: &Path) -> (Vec<GitStatusEntry>, Option<GitBranchInfo>) {
+    let Ok(Some(status)) = git::tree_status(&root.to_string_lossy()).await else {
+        return (Vec::new(), None);
+    };
+    let entries = status.entries.iter().map(GitStatusEntry::from).collect();
+    let branch = GitBranchInfo {
+        branch: status.branch,
+        head: status.head,
+        upstream: status.upstream,
+        ahead: status.ahead,
+        behind: status.behind,
+    };
+    (entries, Some(branch))
 }
 
-fn get_git_status(root: &Path) -> Vec<GitStatusEntry> {
-    let mut status_entries = Vec::new();
+/// Payload of the `fs://changed` event.
+#[derive(Clone, Serialize)]
+struct FsChangedEvent {
+    work_dir: String,
+    #[serde(flatten)]
+    changes: watcher::ChangeBatch,
+    git_status: Vec<GitStatusEntry>,
+    git_branch: Option<GitBranchInfo>,
+}
 
-    // Check if it's a git repo
-    let git_dir = root.join(".git");
-    if !git_dir.exists() {
-        return status_entries;
+/// Tell the UI about changes under `work_dir`, with its fresh git status.
+async fn emit_fs_changed(app: &tauri::AppHandle, work_dir: &str, changes: watcher::ChangeBatch) {
+    let state = app.state::<AppState>();
+    // Indexes of the watched workspaces holding this root cover it too.
+    let workspaces: Vec<String> = state
+        .watchers
+        .lock()
+        .map(|watchers| {
+            watchers
+                .iter()
+                .filter(|(_, roots)| roots.contains_key(work_dir))
+                .map(|(workspace, _)| workspace.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Ok(mut indexes) = state.file_indexes.lock() {
+        indexes.remove(work_dir);
+        for workspace in &workspaces {
+            indexes.remove(workspace);
+        }
+    }
+    if let Ok(mut maps) = state.repo_maps.lock() {
+        maps.remove(work_dir);
     }
+    if let Ok(mut stacks) = state.root_ignores.lock() {
+        stacks.remove(work_dir);
+    }
+    let (git_status, git_branch) = get_git_status(Path::new(work_dir)).await;
+    let _ = app.emit(
+        "fs://changed",
+        FsChangedEvent {
+            work_dir: work_dir.to_string(),
+            changes,
+            git_status,
+            git_branch,
+        },
+    );
+}
 
-    // Run git status --porcelain
-    let output = std::process::Command::new("git")
-        .args(["-C", root.to_str().unwrap_or("."), "status", "--porcelain"])
-        .output();
+/// Watch every root of the workspace at `work_dir` and emit `fs://changed`
+/// with each root's debounced batches of changes and refreshed git status.
+/// Starting an already watched workspace only starts and stops the watchers
+/// of roots added or removed since. Extra roots that cannot be watched are
+/// skipped.
+#[tauri::command]
+fn watch_start(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    work_dir: String,
+) -> Result<(), String> {
+    let roots: Vec<String> = workspace::roots(&work_dir)
+        .into_iter()
+        .map(|root| root.path)
+        .collect();
+    let mut watchers = state
+        .watchers
+        .lock()
+        .map_err(|_| "Watcher store poisoned".to_string())?;
+    let watched = watchers.entry(work_dir.clone()).or_default();
+    watched.retain(|root, _| roots.contains(root));
+    for root in roots {
+        if watched.contains_key(&root) {
+            continue;
+        }
+        let (handle, mut batches) = match watcher::watch(Path::new(&root)) {
+            Ok(watch) => watch,
+            Err(error) if root == work_dir => return Err(error),
+            Err(_) => continue,
+        };
+        watched.insert(root.clone(), handle);
 
-    if let Ok(output) = output {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        for line in stdout.lines() {
-            if line.len() < 3 {
-                continue;
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            while let Some(changes) = batches.recv().await {
+                emit_fs_changed(&app, &root, changes).await;
             }
-            let status_code = &line[0..2];
-            let file_path = line[3..].to_string();
-
-            let status = match status_code {
-                "M " | "M" => "staged",
-                " M" => "modified",
-                "A " | "A" => "added",
-                "D " | " D" => "deleted",
-                "??" => "untracked",
-                _ => "modified",
-            };
+        });
+    }
+    Ok(())
+}
 
-            status_entries.push(GitStatusEntry {
-                path: file_path,
-                status: status.to_string(),
-            });
+/// Stop watching the roots of the workspace at `work_dir`, or of every
+/// workspace when none is given.
+#[tauri::command]
+fn watch_stop(state: tauri::State<'_, AppState>, work_dir: Option<String>) -> Result<(), String> {
+    let mut watchers = state
+        .watchers
+        .lock()
+        .map_err(|_| "Watcher store poisoned".to_string())?;
+    match work_dir {
+        Some(work_dir) => {
+            watchers.remove(&work_dir);
         }
+        None => watchers.clear(),
     }
+    Ok(())
+}
+
+/// Error from `write_file`. A conflict carries what is on disk now so the UI
+/// can offer to reload or overwrite.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum WriteError {
+    Conflict {
+        message: String,
+        /// `None` when the file was deleted.
+        content: Option<String>,
+        version: Option<String>,
+    },
+    Failed {
+        message: String,
+    },
+}
 
-    status_entries
+impl From<String> for WriteError {
+    fn from(message: String) -> Self {
+        WriteError::Failed { message }
+    }
 }
 
+/// Save a file atomically and return its new version. With
+/// `expected_version`, the save is refused if the file changed since that
+/// version was read.
 #[tauri::command]
-fn write_file(work_dir: String, file_path: String, content: String) -> Result<(), String> {
-    let root = Path::new(&work_dir);
-    let full_path = root.join(&file_path);
+fn write_file(
+    work_dir: String,
+    file_path: String,
+    content: String,
+    expected_version: Option<String>,
+) -> Result<String, WriteError> {
+    let target = file_ops::resolve(&work_dir, &file_path)?;
+    // Write through a symlink to its target, which must also be inside.
+    let path = match target.full.canonicalize() {
+        Ok(canonical) => {
+            let canonical_root = Path::new(&work_dir)
+                .canonicalize()
+                .map_err(|e| format!("Failed to resolve work dir: {}", e))?;
+            if !canonical.starts_with(&canonical_root) {
+                return Err("Path is outside working directory".to_string().into());
+            }
+            canonical
+        }
+        Err(_) => target.full,
+    };
 
-    // Security: ensure the path is within work_dir
-    let canonical = full_path
-        .canonicalize()
-        .map_err(|e| format!("Failed to resolve path: {}", e))?;
-    let canonical_root = root
-        .canonicalize()
-        .map_err(|e| format!("Failed to resolve work dir: {}", e))?;
+    if let Some(expected) = expected_version {
+        match fs::read(&path) {
+            Ok(current) => {
+                let version = file_ops::content_version(&current);
+                if version != expected {
+                    return Err(WriteError::Conflict {
+                        message: format!("{} changed on disk since it was opened", target.rel),
+                        content: Some(String::from_utf8_lossy(&current).to_string()),
+                        version: Some(version),
+                    });
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(WriteError::Conflict {
+                    message: format!("{} was deleted since it was opened", target.rel),
+                    content: None,
+                    version: None,
+                });
+            }
+            Err(e) => return Err(format!("Failed to read file: {}", e).into()),
+        }
+    }
 
-    if !canonical.starts_with(&canonical_root) {
-        return Err("Path is outside working directory".to_string());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("Failed to create directory: {}", e))?;
     }
+    file_ops::write_atomic(&path, content.as_bytes())
+        .map_err(|e| format!("Failed to write file: {}", e))?;
+    Ok(file_ops::content_version(content.as_bytes()))
+}
+
+#[tauri::command]
+async fn file_create(
+    app: tauri::AppHandle,
+    work_dir: String,
+    path: String,
+    content: Option<String>,
+) -> Result<(), String> {
+    let changes = file_ops::create_file(&work_dir, &path, &content.unwrap_or_default())?;
+    emit_fs_changed(&app, &work_dir, changes).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn file_create_dir(
+    app: tauri::AppHandle,
+    work_dir: String,
+    path: String,
+) -> Result<(), String> {
+    let changes = file_ops::create_dir(&work_dir, &path)?;
+    emit_fs_changed(&app, &work_dir, changes).await;
+    Ok(())
+}
 
-    std::fs::write(&canonical, content).map_err(|e| format!("Failed to write file: {}", e))
+/// Rename or move an entry inside the work dir.
+#[tauri::command]
+async fn file_rename(
+    app: tauri::AppHandle,
+    work_dir: String,
+    from: String,
+    to: String,
+) -> Result<(), String> {
+    let changes = file_ops::rename(&work_dir, &from, &to)?;
+    emit_fs_changed(&app, &work_dir, changes).await;
+    Ok(())
+}
+
+/// Move an entry to the app's trash; `trash_restore` puts it back.
+#[tauri::command]
+async fn file_trash(
+    app: tauri::AppHandle,
+    work_dir: String,
+    path: String,
+) -> Result<file_ops::TrashEntry, String> {
+    let (entry, changes) = file_ops::trash(&work_dir, &path)?;
+    emit_fs_changed(&app, &work_dir, changes).await;
+    Ok(entry)
+}
+
+#[tauri::command]
+fn trash_list(work_dir: Option<String>) -> Vec<file_ops::TrashEntry> {
+    file_ops::trash_list(work_dir.as_deref())
+}
+
+#[tauri::command]
+async fn trash_restore(app: tauri::AppHandle, id: String) -> Result<file_ops::TrashEntry, String> {
+    let (entry, changes) = file_ops::restore(&id)?;
+    emit_fs_changed(&app, &entry.work_dir, changes).await;
+    Ok(entry)
+}
+
+/// Permanently delete one trashed entry, or the whole trash.
+#[tauri::command]
+fn trash_purge(id: Option<String>) -> Result<(), String> {
+    file_ops::purge(id.as_deref())
 }
 
 fn main() {
@@ -1733,10 +2265,23 @@ fn main() {
             cowork_stream,
             cancel_chat,
             list_files,
+            search_in_files,
+            search_cancel,
             read_file,
+            read_file_range,
             write_file,
+            file_create,
+            file_create_dir,
+            file_rename,
+            file_trash,
+            trash_list,
+            trash_restore,
+            trash_purge,
             pick_folder,
             list_dir_tree,
+            list_dir_children,
+            watch_start,
+            watch_stop,
             tool_approval_respond,
             // OAuth commands
             oauth::oauth_check_status,
@@ -1746,6 +2291,32 @@ fn main() {
             oauth::oauth_get_user,
             // LLM commands
             llm::llm_fetch_models,
+            // Permission rules
+            permissions::permissions_load,
+            permissions::permissions_save,
+            permissions::permissions_trust_workspace,
+            // Workspace roots
+            workspace::workspace_load,
+            workspace::workspace_add_root,
+            workspace::workspace_remove_root,
+            // Edit checkpoints
+            checkpoint::checkpoint_list,
+            checkpoint::checkpoint_diff,
+            checkpoint::checkpoint_restore,
+            // Git
+            git::git_file_diff,
+            git::git_stage,
+            git::git_unstage,
+            git::git_discard,
+            git::git_apply_hunk,
+            git::git_commit,
+            git::git_branches,
+            git::git_switch_branch,
+            // Session worktrees
+            worktree::worktree_status,
+            worktree::worktree_merge,
+            worktree::worktree_cherry_pick,
+            worktree::worktree_discard,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,476 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::diff::{self, FileDiff};
+use crate::tools::{self, ToolOutput};
+
+const MAX_OUTPUT_LINES: usize = 20;
+const MAX_OUTPUT_CHARS: usize = 2000;
+const CELL_TYPES: [&str; 3] = ["code", "markdown", "raw"];
+
+/// One change to a notebook. Cells are addressed by `cell_id` when given,
+/// otherwise by 0-based `index`.
+#[derive(Debug, Deserialize)]
+pub struct NotebookEdit {
+    pub action: String,
+    #[serde(default)]
+    pub index: Option<usize>,
+    #[serde(default)]
+    pub cell_id: Option<String>,
+    #[serde(default)]
+    pub cell_type: Option<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+pub fn parse_edits(args: &serde_json::Value) -> Result<Vec<NotebookEdit>, String> {
+    let edits = args
+        .get("edits")
+        .cloned()
+        .ok_or_else(|| "Missing edits".to_string())?;
+    let edits: Vec<NotebookEdit> =
+        serde_json::from_value(edits).map_err(|err| format!("Invalid edits: {err}"))?;
+    if edits.is_empty() {
+        return Err("Missing edits".to_string());
+    }
+    Ok(edits)
+}
+
+fn load(work_dir: &str, path: &str) -> Result<(PathBuf, serde_json::Value), String> {
+    let resolved = tools::resolve_path(work_dir, path, true)?;
+    if !resolved.is_file() {
+        return Err("Path is not a file".to_string());
+    }
+    let raw = fs::read_to_string(&resolved).map_err(|err| format!("Failed to read file: {err}"))?;
+    let notebook: serde_json::Value =
+        serde_json::from_str(&raw).map_err(|err| format!("Invalid notebook JSON: {err}"))?;
+    validate(&notebook)?;
+    Ok((resolved, notebook))
+}
+
+/// Check the parts of the nbformat 4 schema that edits can break.
+fn validate(notebook: &serde_json::Value) -> Result<(), String> {
+    let major = notebook.get("nbformat").and_then(|v| v.as_u64());
+    if major != Some(4) {
+        return Err("Only nbformat 4 notebooks are supported".to_string());
+    }
+    if !notebook.get("metadata").is_some_and(|v| v.is_object()) {
+        return Err("Notebook metadata is missing".to_string());
+    }
+    let cells = notebook
+        .get("cells")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| "Notebook has no cells array".to_string())?;
+    let mut ids = Vec::new();
+    for (index, cell) in cells.iter().enumerate() {
+        let cell_type = cell.get("cell_type").and_then(|v| v.as_str()).unwrap_or("");
+        if !CELL_TYPES.contains(&cell_type) {
+            return Err(format!("Cell {index} has invalid cell_type {cell_type:?}"));
+        }
+        let source_ok = match cell.get("source") {
+            Some(serde_json::Value::String(_)) => true,
+            Some(serde_json::Value::Array(lines)) => lines.iter().all(|line| line.is_string()),
+            _ => false,
+        };
+        if !source_ok {
+            return Err(format!("Cell {index} has invalid source"));
+        }
+        if cell_type == "code"
+            && !(cell.get("outputs").is_some_and(|v| v.is_array())
+                && cell.get("execution_count").is_some())
+        {
+            return Err(format!(
+                "Code cell {index} is missing outputs or execution_count"
+            ));
+        }
+        if let Some(id) = cell.get("id").and_then(|v| v.as_str()) {
+            if ids.contains(&id) {
+                return Err(format!("Cell id {id} is used more than once"));
+            }
+            ids.push(id);
+        }
+    }
+    Ok(())
+}
+
+fn source_text(cell: &serde_json::Value) -> String {
+    match cell.get("source") {
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(lines)) => lines
+            .iter()
+            .filter_map(|line| line.as_str())
+            .collect::<String>(),
+        _ => String::new(),
+    }
+}
+
+/// Jupyter stores sources as a list of lines that keep their newlines.
+fn source_value(source: &str) -> serde_json::Value {
+    serde_json::Value::Array(
+        source
+            .split_inclusive('\n')
+            .map(|line| serde_json::Value::String(line.to_string()))
+            .collect(),
+    )
+}
+
+fn truncate_block(text: &str) -> String {
+    let mut lines: Vec<&str> = text.lines().collect();
+    let mut truncated = lines.len() > MAX_OUTPUT_LINES;
+    lines.truncate(MAX_OUTPUT_LINES);
+    let mut block = lines.join("\n");
+    if block.chars().count() > MAX_OUTPUT_CHARS {
+        block = block.chars().take(MAX_OUTPUT_CHARS).collect();
+        truncated = true;
+    }
+    if truncated {
+        block.push_str("\n[...truncated]");
+    }
+    block
+}
+
+fn output_text(output: &serde_json::Value) -> String {
+    let joined = |value: Option<&serde_json::Value>| match value {
+        Some(serde_json::Value::String(text)) => text.clone(),
+        Some(serde_json::Value::Array(lines)) => lines
+            .iter()
+            .filter_map(|line| line.as_str())
+            .collect::<String>(),
+        _ => String::new(),
+    };
+    match output.get("output_type").and_then(|v| v.as_str()) {
+        Some("stream") => joined(output.get("text")),
+        Some("error") => format!(
+            "{}: {}",
+            output
+                .get("ename")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Error"),
+            output.get("evalue").and_then(|v| v.as_str()).unwrap_or("")
+        ),
+        Some("execute_result") | Some("display_data") => {
+            let data = output.get("data");
+            match data.and_then(|d| d.get("text/plain")) {
+                Some(text) => joined(Some(text)),
+                None => {
+                    let kinds: Vec<&str> = data
+                        .and_then(|d| d.as_object())
+                        .map(|d| d.keys().map(String::as_str).collect())
+                        .unwrap_or_default();
+                    format!("[{} output]", kinds.join(", "))
+                }
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+fn cell_header(index: usize, cell: &serde_json::Value) -> String {
+    let cell_type = cell.get("cell_type").and_then(|v| v.as_str()).unwrap_or("");
+    let mut header = format!("[{index}] {cell_type}");
+    if let Some(id) = cell.get("id").and_then(|v| v.as_str()) {
+        header.push_str(&format!(" id={id}"));
+    }
+    if let Some(count) = cell.get("execution_count").and_then(|v| v.as_u64()) {
+        header.push_str(&format!(" execution_count={count}"));
+    }
+    header
+}
+
+/// Cells as plain text, used for diffs of notebook edits.
+fn render_sources(notebook: &serde_json::Value) -> String {
+    let mut text = String::new();
+    let cells = notebook.get("cells").and_then(|v| v.as_array());
+    for (index, cell) in cells.into_iter().flatten().enumerate() {
+        text.push_str(&format!("# %% {}\n", cell_header(index, cell)));
+        let source = source_text(cell);
+        text.push_str(&source);
+        if !source.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+pub fn read_notebook(work_dir: &str, path: &str) -> ToolOutput {
+    let notebook = match load(work_dir, path) {
+        Ok((_, notebook)) => notebook,
+        Err(err) => {
+            return ToolOutput {
+                ok: false,
+                summary: err,
+                output: String::new(),
+            }
+        }
+    };
+
+    let cells = notebook
+        .get("cells")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    let mut output = String::new();
+    for (index, cell) in cells.iter().enumerate() {
+        output.push_str(&cell_header(index, cell));
+        output.push('\n');
+        output.push_str(&source_text(cell));
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+        let outputs = cell
+            .get("outputs")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for cell_output in &outputs {
+            let text = output_text(cell_output);
+            if !text.trim().is_empty() {
+                output.push_str("--- output ---\n");
+                output.push_str(&truncate_block(&text));
+                output.push('\n');
+            }
+        }
+        output.push('\n');
+    }
+
+    let language = notebook
+        .pointer("/metadata/kernelspec/language")
+        .or_else(|| notebook.pointer("/metadata/language_info/name"))
+        .and_then(|v| v.as_str())
+        .map(|language| format!(" ({language})"))
+        .unwrap_or_default();
+    let (output, truncated) = tools::truncate_output(&output);
+    let mut summary = format!("Notebook has {} cell(s){language}.", cells.len());
+    if truncated {
+        summary.push_str(" Output is truncated.");
+    }
+    ToolOutput {
+        ok: true,
+        summary,
+        output,
+    }
+}
+
+fn find_cell(cells: &[serde_json::Value], edit: &NotebookEdit) -> Result<usize, String> {
+    if let Some(id) = &edit.cell_id {
+        return cells
+            .iter()
+            .position(|cell| cell.get("id").and_then(|v| v.as_str()) == Some(id))
+            .ok_or_else(|| format!("No cell with id {id}"));
+    }
+    let index = edit
+        .index
+        .ok_or_else(|| "Either index or cell_id is required".to_string())?;
+    if index >= cells.len() {
+        return Err(format!(
+            "Cell index {index} is out of range (notebook has {} cells)",
+            cells.len()
+        ));
+    }
+    Ok(index)
+}
+
+fn check_cell_type(cell_type: &str) -> Result<(), String> {
+    if CELL_TYPES.contains(&cell_type) {
+        Ok(())
+    } else {
+        Err(format!("Invalid cell_type {cell_type:?}"))
+    }
+}
+
+/// Give a cell the fields its type requires. Code outputs are cleared when
+/// the source changes, since they no longer match it.
+fn set_cell(cell: &mut serde_json::Value, cell_type: &str, source: Option<&str>) {
+    let Some(fields) = cell.as_object_mut() else {
+        return;
+    };
+    fields.insert("cell_type".to_string(), cell_type.into());
+    if let Some(source) = source {
+        fields.insert("source".to_string(), source_value(source));
+    }
+    if cell_type == "code" {
+        if source.is_some() || !fields.contains_key("outputs") {
+            fields.insert("outputs".to_string(), serde_json::json!([]));
+            fields.insert("execution_count".to_string(), serde_json::Value::Null);
+        }
+    } else {
+        fields.remove("outputs");
+        fields.remove("execution_count");
+    }
+    fields
+        .entry("metadata")
+        .or_insert_with(|| serde_json::json!({}));
+}
+
+fn new_cell_id(cells: &[serde_json::Value]) -> String {
+    loop {
+        let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+        if !cells
+            .iter()
+            .any(|cell| cell.get("id").and_then(|v| v.as_str()) == Some(id.as_str()))
+        {
+            return id;
+        }
+    }
+}
+
+/// Apply edits in order, so indexes refer to the notebook as left by the
+/// previous edit.
+fn apply_edits(
+    notebook: &mut serde_json::Value,
+    edits: &[NotebookEdit],
+) -> Result<Vec<String>, String> {
+    // Cell ids are part of the format from nbformat 4.5 on.
+    let uses_ids = notebook
+        .get("nbformat_minor")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0)
+        >= 5;
+    let cells = notebook
+        .get_mut("cells")
+        .and_then(|v| v.as_array_mut())
+        .ok_or_else(|| "Notebook has no cells array".to_string())?;
+    let mut report = Vec::new();
+
+    for (number, edit) in edits.iter().enumerate() {
+        let fail = |err: String| format!("Edit {}: {err}", number + 1);
+        match edit.action.as_str() {
+            "replace" => {
+                let index = find_cell(cells, edit).map_err(fail)?;
+                let cell_type = match &edit.cell_type {
+                    Some(cell_type) => {
+                        check_cell_type(cell_type).map_err(fail)?;
+                        cell_type.clone()
+                    }
+                    None => cells[index]
+                        .get("cell_type")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("code")
+                        .to_string(),
+                };
+                if edit.source.is_none() && edit.cell_type.is_none() {
+                    return Err(fail("replace requires source or cell_type".to_string()));
+                }
+                set_cell(&mut cells[index], &cell_type, edit.source.as_deref());
+                report.push(format!("Replaced cell {index}."));
+            }
+            "insert" => {
+                let source = edit
+                    .source
+                    .as_deref()
+                    .ok_or_else(|| fail("insert requires source".to_string()))?;
+                let cell_type = edit.cell_type.as_deref().unwrap_or("code");
+                check_cell_type(cell_type).map_err(fail)?;
+                // After `cell_id`, or before `index` (which may equal the
+                // number of cells to append).
+                let index = match (&edit.cell_id, edit.index) {
+                    (Some(_), _) => find_cell(cells, edit).map_err(fail)? + 1,
+                    (None, Some(index)) if index <= cells.len() => index,
+                    (None, Some(index)) => {
+                        return Err(fail(format!(
+                            "Cell index {index} is out of range (notebook has {} cells)",
+                            cells.len()
+                        )))
+                    }
+                    (None, None) => cells.len(),
+                };
+                let mut cell = serde_json::json!({ "metadata": {} });
+                if uses_ids {
+                    cell["id"] = new_cell_id(cells).into();
+                }
+                set_cell(&mut cell, cell_type, Some(source));
+                cells.insert(index, cell);
+                report.push(format!("Inserted {cell_type} cell at {index}."));
+            }
+            "delete" => {
+                let index = find_cell(cells, edit).map_err(fail)?;
+                cells.remove(index);
+                report.push(format!("Deleted cell {index}."));
+            }
+            other => return Err(fail(format!("Unknown action {other}"))),
+        }
+    }
+    Ok(report)
+}
+
+/// Serialize like Jupyter does: sorted keys, one-space indent, trailing
+/// newline.
+fn to_notebook_json(notebook: &serde_json::Value) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b" ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut bytes, formatter);
+    notebook
+        .serialize(&mut serializer)
+        .map_err(|err| format!("Failed to encode notebook: {err}"))?;
+    let mut text =
+        String::from_utf8(bytes).map_err(|err| format!("Failed to encode notebook: {err}"))?;
+    text.push('\n');
+    Ok(text)
+}
+
+struct EditedNotebook {
+    path: PathBuf,
+    original: serde_json::Value,
+    notebook: serde_json::Value,
+    report: Vec<String>,
+}
+
+fn edited(work_dir: &str, path: &str, edits: &[NotebookEdit]) -> Result<EditedNotebook, String> {
+    let (resolved, original) = load(work_dir, path)?;
+    let mut notebook = original.clone();
+    let report = apply_edits(&mut notebook, edits)?;
+    validate(&notebook).map_err(|err| format!("Edited notebook is invalid: {err}"))?;
+    Ok(EditedNotebook {
+        path: resolved,
+        original,
+        notebook,
+        report,
+    })
+}
+
+/// Diff of the cell sources an edit would change.
+pub fn preview_edit_notebook(
+    work_dir: &str,
+    path: &str,
+    edits: &[NotebookEdit],
+) -> Result<FileDiff, String> {
+    let edited = edited(work_dir, path, edits)?;
+    Ok(tools::finish_preview(diff::unified_diff(
+        path,
+        Some(&render_sources(&edited.original)),
+        &render_sources(&edited.notebook),
+    )))
+}
+
+pub fn edit_notebook(work_dir: &str, path: &str, edits: &[NotebookEdit]) -> ToolOutput {
+    let result = edited(work_dir, path, edits).and_then(|edited| {
+        let text = to_notebook_json(&edited.notebook)?;
+        fs::write(&edited.path, text).map_err(|err| format!("Failed to write file: {err}"))?;
+        Ok(edited)
+    });
+
+    match result {
+        Ok(edited) => {
+            let cells = edited
+                .notebook
+                .get("cells")
+                .and_then(|v| v.as_array())
+                .map(Vec::len)
+                .unwrap_or(0);
+            ToolOutput {
+                ok: true,
+                summary: format!(
+                    "Notebook successfully edited. Applied {} edit(s); it now has {cells} cell(s).",
+                    edits.len()
+                ),
+                output: edited.report.join("\n"),
+            }
+        }
+        Err(err) => ToolOutput {
+            ok: false,
+            summary: err,
+            output: String::new(),
+        },
+    }
+}
@@ -0,0 +1,787 @@
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::diff::{self, FileDiff};
+use crate::file_ops::write_atomic;
+use crate::tools::{self, ToolOutput};
+
+/// Context lines that may be dropped from either end of a hunk that does not
+/// match as written, like `patch --fuzz=2`.
+const MAX_FUZZ: usize = 2;
+
+#[derive(Debug, Clone)]
+enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Hunk {
+    /// 1-based start line in the original file, 0 when the header has none.
+    old_start: usize,
+    lines: Vec<HunkLine>,
+    old_missing_newline: bool,
+    new_missing_newline: bool,
+}
+
+/// One file-level change of a patch.
+#[derive(Debug, Clone)]
+pub enum FilePatch {
+    Add {
+        path: String,
+        content: String,
+    },
+    Delete {
+        path: String,
+    },
+    Update {
+        path: String,
+        move_to: Option<String>,
+        hunks: Vec<Hunk>,
+    },
+}
+
+impl FilePatch {
+    fn paths(&self) -> Vec<&str> {
+        match self {
+            FilePatch::Add { path, .. } | FilePatch::Delete { path } => vec![path],
+            FilePatch::Update { path, move_to, .. } => {
+                let mut paths = vec![path.as_str()];
+                paths.extend(move_to.as_deref());
+                paths
+            }
+        }
+    }
+}
+
+/// Structured form of a change, as accepted in the `operations` argument.
+#[derive(Debug, Deserialize)]
+struct Operation {
+    action: String,
+    path: String,
+    #[serde(default)]
+    to: Option<String>,
+    #[serde(default)]
+    content: Option<String>,
+    #[serde(default)]
+    diff: Option<String>,
+}
+
+/// A validated change with the exact bytes before and after it.
+struct PlannedFile {
+    path: PathBuf,
+    display: String,
+    before: Option<Vec<u8>>,
+    after: Option<String>,
+}
+
+/// Parse the `patch` or `operations` argument of an `ApplyPatch` call.
+pub fn parse(args: &serde_json::Value) -> Result<Vec<FilePatch>, String> {
+    let operations = args.get("operations").filter(|value| !value.is_null());
+    let patch = args.get("patch").and_then(|value| value.as_str());
+    let changes = match (operations, patch) {
+        (Some(_), Some(_)) => {
+            return Err("Provide either patch or operations, not both".to_string());
+        }
+        (Some(operations), None) => {
+            let operations: Vec<Operation> = serde_json::from_value(operations.clone())
+                .map_err(|err| format!("Invalid operations: {err}"))?;
+            operations
+                .into_iter()
+                .map(parse_operation)
+                .collect::<Result<Vec<_>, _>>()?
+        }
+        (None, Some(patch)) => parse_unified(patch)?,
+        (None, None) => return Err("Missing patch or operations".to_string()),
+    };
+    if changes.is_empty() {
+        return Err("Patch contains no file changes".to_string());
+    }
+    Ok(changes)
+}
+
+/// Every path named by the patch, as written.
+pub fn touched_paths(changes: &[FilePatch]) -> Vec<String> {
+    changes
+        .iter()
+        .flat_map(|change| change.paths())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Resolved paths the patch would create, modify or delete.
+pub fn target_paths(work_dir: &str, changes: &[FilePatch]) -> Result<Vec<PathBuf>, String> {
+    Ok(plan(work_dir, changes)?
+        .into_iter()
+        .map(|file| file.path)
+        .collect())
+}
+
+/// Combined diff of every file the patch changes, failing if any part of it
+/// would not apply.
+pub fn preview(work_dir: &str, changes: &[FilePatch]) -> Result<FileDiff, String> {
+    let planned = plan(work_dir, changes)?;
+    let mut combined = FileDiff {
+        path: match planned.as_slice() {
+            [file] => file.display.clone(),
+            files => format!("{} files", files.len()),
+        },
+        new_file: planned.iter().all(|file| file.before.is_none()),
+        diff: String::new(),
+        added: 0,
+        removed: 0,
+    };
+    for file in &planned {
+        let before = file
+            .before
+            .as_ref()
+            .map(|bytes| String::from_utf8_lossy(bytes).to_string());
+        let file_diff = diff::change_diff(&file.display, before.as_deref(), file.after.as_deref());
+        combined.diff.push_str(&file_diff.diff);
+        combined.added += file_diff.added;
+        combined.removed += file_diff.removed;
+    }
+    Ok(tools::finish_preview(combined))
+}
+
+/// Apply all changes, or none: every hunk is validated before anything is
+/// written, and files already written are restored if a later write fails.
+pub fn apply(work_dir: &str, changes: &[FilePatch]) -> ToolOutput {
+    let planned = match plan(work_dir, changes) {
+        Ok(planned) => planned,
+        Err(err) => {
+            return ToolOutput {
+                ok: false,
+                summary: format!("Patch was not applied: {err}"),
+                output: String::new(),
+            }
+        }
+    };
+
+    let mut written: Vec<&PlannedFile> = Vec::new();
+    let mut created_dirs = Vec::new();
+    for file in &planned {
+        if let Err(err) = write_planned(file, &mut created_dirs) {
+            let failed = rollback(&written, &created_dirs);
+            let mut summary = format!(
+                "Failed to apply patch to {}: {err}. All changes were rolled back.",
+                file.display
+            );
+            if !failed.is_empty() {
+                summary = format!(
+                    "Failed to apply patch to {}: {err}. Rollback failed for: {}",
+                    file.display,
+                    failed.join(", ")
+                );
+            }
+            return ToolOutput {
+                ok: false,
+                summary,
+                output: String::new(),
+            };
+        }
+        written.push(file);
+    }
+
+    let mut output = String::new();
+    let (mut added, mut deleted, mut modified) = (0usize, 0usize, 0usize);
+    for file in &planned {
+        let marker = match (&file.before, &file.after) {
+            (None, _) => {
+                added += 1;
+                'A'
+            }
+            (_, None) => {
+                deleted += 1;
+                'D'
+            }
+            _ => {
+                modified += 1;
+                'M'
+            }
+        };
+        output.push_str(&format!("{marker} {}\n", file.display));
+    }
+
+    ToolOutput {
+        ok: true,
+        summary: format!(
+            "Patch applied to {} file(s): {modified} modified, {added} added, {deleted} deleted.",
+            planned.len()
+        ),
+        output,
+    }
+}
+
+fn parse_operation(operation: Operation) -> Result<FilePatch, String> {
+    let Operation {
+        action,
+        path,
+        to,
+        content,
+        diff,
+    } = operation;
+    let hunks = |diff: &str| -> Result<Vec<Hunk>, String> {
+        let lines: Vec<&str> = diff.lines().collect();
+        parse_hunks(&lines).map_err(|err| format!("{path}: {err}"))
+    };
+    match action.as_str() {
+        "add" => {
+            let content = content.ok_or_else(|| format!("{path}: add requires content"))?;
+            Ok(FilePatch::Add { path, content })
+        }
+        "delete" => Ok(FilePatch::Delete { path }),
+        "rename" => {
+            let to = to.ok_or_else(|| format!("{path}: rename requires to"))?;
+            let hunks = match diff.as_deref() {
+                Some(diff) => hunks(diff)?,
+                None => Vec::new(),
+            };
+            Ok(FilePatch::Update {
+                path,
+                move_to: Some(to),
+                hunks,
+            })
+        }
+        "update" => {
+            let diff = diff.ok_or_else(|| format!("{path}: update requires diff"))?;
+            let hunks = hunks(&diff)?;
+            if hunks.is_empty() {
+                return Err(format!("{path}: diff contains no hunks"));
+            }
+            Ok(FilePatch::Update {
+                path,
+                move_to: to,
+                hunks,
+            })
+        }
+        other => Err(format!("{path}: unknown action {other}")),
+    }
+}
+
+/// Headers of one file section of a unified diff.
+#[derive(Default)]
+struct Section {
+    started: bool,
+    has_headers: bool,
+    old: Option<String>,
+    new: Option<String>,
+    new_file: bool,
+    deleted: bool,
+    hunks: Vec<Hunk>,
+}
+
+impl Section {
+    fn into_change(self) -> Result<Option<FilePatch>, String> {
+        if !self.started {
+            return Ok(None);
+        }
+        if self.new_file {
+            let path = self
+                .new
+                .ok_or_else(|| "Added file has no path".to_string())?;
+            let mut content = String::new();
+            let mut missing_newline = false;
+            for hunk in &self.hunks {
+                for line in &hunk.lines {
+                    match line {
+                        HunkLine::Add(text) => {
+                            content.push_str(text);
+                            content.push('\n');
+                        }
+                        _ => return Err(format!("{path}: added file hunk removes lines")),
+                    }
+                }
+                missing_newline = hunk.new_missing_newline;
+            }
+            if missing_newline {
+                content.pop();
+            }
+            return Ok(Some(FilePatch::Add { path, content }));
+        }
+        if self.deleted {
+            let path = self
+                .old
+                .ok_or_else(|| "Deleted file has no path".to_string())?;
+            return Ok(Some(FilePatch::Delete { path }));
+        }
+
+        let path = self
+            .old
+            .clone()
+            .or_else(|| self.new.clone())
+            .ok_or_else(|| "File section has no path".to_string())?;
+        let move_to = self.new.filter(|new| *new != path);
+        if self.hunks.is_empty() && move_to.is_none() {
+            // Mode changes and similar metadata-only sections.
+            return Ok(None);
+        }
+        Ok(Some(FilePatch::Update {
+            path,
+            move_to,
+            hunks: self.hunks,
+        }))
+    }
+}
+
+/// Path from a `---`/`+++` header, or `None` for `/dev/null`.
+fn header_path(raw: &str, prefix: &str) -> Option<String> {
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path.strip_prefix(prefix).unwrap_or(path);
+    Some(path.to_string())
+}
+
+fn is_file_header(lines: &[&str], index: usize) -> bool {
+    lines[index].starts_with("diff --git ")
+        || (lines[index].starts_with("--- ")
+            && lines
+                .get(index + 1)
+                .is_some_and(|next| next.starts_with("+++ ")))
+}
+
+fn parse_unified(patch: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = patch.lines().collect();
+    let mut changes = Vec::new();
+    let mut section = Section::default();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            changes.extend(std::mem::take(&mut section).into_change()?);
+            section.started = true;
+            if let Some((old, new)) = rest.split_once(" b/") {
+                section.old = Some(old.strip_prefix("a/").unwrap_or(old).to_string());
+                section.new = Some(new.to_string());
+            }
+            index += 1;
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("--- ") {
+            if is_file_header(&lines, index) {
+                // A `---` header without `diff --git` starts a new section,
+                // unless it follows the git header of the current one.
+                if section.has_headers || !section.hunks.is_empty() {
+                    changes.extend(std::mem::take(&mut section).into_change()?);
+                }
+                section.started = true;
+                section.has_headers = true;
+                let new = &lines[index + 1][4..];
+                section.old = header_path(rest, "a/");
+                section.new = header_path(new, "b/");
+                section.new_file |= section.old.is_none();
+                section.deleted |= section.new.is_none();
+                index += 2;
+                continue;
+            }
+        }
+        if line.starts_with("@@") {
+            let start = index;
+            index += 1;
+            while index < lines.len() && !is_file_header(&lines, index) {
+                index += 1;
+            }
+            if !section.started {
+                return Err("Hunk found before any file header".to_string());
+            }
+            section.hunks.extend(parse_hunks(&lines[start..index])?);
+            continue;
+        }
+        if section.started {
+            if let Some(from) = line.strip_prefix("rename from ") {
+                section.old = Some(from.trim().to_string());
+            } else if let Some(to) = line.strip_prefix("rename to ") {
+                section.new = Some(to.trim().to_string());
+            } else if line.starts_with("new file mode") {
+                section.new_file = true;
+            } else if line.starts_with("deleted file mode") {
+                section.deleted = true;
+            }
+        }
+        index += 1;
+    }
+    changes.extend(section.into_change()?);
+    Ok(changes)
+}
+
+fn parse_hunk_header(line: &str) -> Option<usize> {
+    let rest = line.strip_prefix("@@")?;
+    let old_start = rest
+        .trim_start()
+        .strip_prefix('-')
+        .and_then(|range| range.split([',', ' ']).next())
+        .and_then(|start| start.parse().ok())
+        .unwrap_or(0);
+    Some(old_start)
+}
+
+/// Parse `@@` hunks. Lines before the first hunk header are ignored, and
+/// header line counts are not trusted since hand-written hunks often get them
+/// wrong.
+fn parse_hunks(lines: &[&str]) -> Result<Vec<Hunk>, String> {
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+
+    for line in lines {
+        if let Some(old_start) = parse_hunk_header(line) {
+            hunks.extend(current.take());
+            current = Some(Hunk {
+                old_start,
+                ..Hunk::default()
+            });
+            continue;
+        }
+        let Some(hunk) = current.as_mut() else {
+            continue;
+        };
+        if let Some(text) = line.strip_prefix('+') {
+            hunk.lines.push(HunkLine::Add(text.to_string()));
+        } else if let Some(text) = line.strip_prefix('-') {
+            hunk.lines.push(HunkLine::Remove(text.to_string()));
+        } else if let Some(text) = line.strip_prefix(' ') {
+            hunk.lines.push(HunkLine::Context(text.to_string()));
+        } else if line.is_empty() {
+            hunk.lines.push(HunkLine::Context(String::new()));
+        } else if line.starts_with('\\') {
+            match hunk.lines.last() {
+                Some(HunkLine::Add(_)) => hunk.new_missing_newline = true,
+                Some(HunkLine::Remove(_)) => hunk.old_missing_newline = true,
+                Some(HunkLine::Context(_)) => {
+                    hunk.old_missing_newline = true;
+                    hunk.new_missing_newline = true;
+                }
+                None => {}
+            }
+        } else {
+            return Err(format!("Unexpected line in hunk: {line}"));
+        }
+    }
+    hunks.extend(current);
+
+    for (index, hunk) in hunks.iter_mut().enumerate() {
+        // Blank lines trailing a hunk are usually separators, not context.
+        while matches!(hunk.lines.last(), Some(HunkLine::Context(text)) if text.is_empty()) {
+            hunk.lines.pop();
+        }
+        if !hunk
+            .lines
+            .iter()
+            .any(|line| !matches!(line, HunkLine::Context(_)))
+        {
+            return Err(format!("Hunk {} contains no changes", index + 1));
+        }
+    }
+    Ok(hunks)
+}
+
+fn plan(work_dir: &str, changes: &[FilePatch]) -> Result<Vec<PlannedFile>, String> {
+    let mut planned: Vec<PlannedFile> = Vec::new();
+    let mut push = |file: PlannedFile| -> Result<(), String> {
+        if planned.iter().any(|other| other.path == file.path) {
+            return Err(format!(
+                "{} is changed more than once in the patch",
+                file.display
+            ));
+        }
+        planned.push(file);
+        Ok(())
+    };
+    let resolve = |path: &str| -> Result<PathBuf, String> {
+        tools::resolve_path(work_dir, path, false).map_err(|err| format!("{path}: {err}"))
+    };
+    let read = |path: &str, target: &Path| -> Result<Vec<u8>, String> {
+        if !target.is_file() {
+            return Err(format!("{path}: file not found"));
+        }
+        fs::read(target).map_err(|err| format!("{path}: failed to read file: {err}"))
+    };
+
+    for change in changes {
+        match change {
+            FilePatch::Add { path, content } => {
+                let target = resolve(path)?;
+                if target.exists() {
+                    return Err(format!("{path}: file already exists"));
+                }
+                push(PlannedFile {
+                    path: target,
+                    display: path.clone(),
+                    before: None,
+                    after: Some(content.clone()),
+                })?;
+            }
+            FilePatch::Delete { path } => {
+                let target = resolve(path)?;
+                let before = read(path, &target)?;
+                push(PlannedFile {
+                    path: target,
+                    display: path.clone(),
+                    before: Some(before),
+                    after: None,
+                })?;
+            }
+            FilePatch::Update {
+                path,
+                move_to,
+                hunks,
+            } => {
+                let source = resolve(path)?;
+                let before = read(path, &source)?;
+                let original = String::from_utf8(before.clone())
+                    .map_err(|_| format!("{path}: file is not valid UTF-8"))?;
+                let updated = if hunks.is_empty() {
+                    original
+                } else {
+                    apply_hunks(&original, hunks).map_err(|err| format!("{path}: {err}"))?
+                };
+                match move_to {
+                    Some(destination) => {
+                        let target = resolve(destination)?;
+                        if target.exists() {
+                            return Err(format!("{destination}: file already exists"));
+                        }
+                        push(PlannedFile {
+                            path: source,
+                            display: path.clone(),
+                            before: Some(before),
+                            after: None,
+                        })?;
+                        push(PlannedFile {
+                            path: target,
+                            display: destination.clone(),
+                            before: None,
+                            after: Some(updated),
+                        })?;
+                    }
+                    None => {
+                        push(PlannedFile {
+                            path: source,
+                            display: path.clone(),
+                            before: Some(before),
+                            after: Some(updated),
+                        })?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(planned)
+}
+
+fn lines_match(actual: &str, expected: &str, loose: bool) -> bool {
+    if loose {
+        actual.trim_end() == expected.trim_end()
+    } else {
+        actual == expected
+    }
+}
+
+/// Find where `old` occurs in `lines` at or after `min_index`, preferring the
+/// position closest to `expected`.
+fn find_block(
+    lines: &[String],
+    old: &[&str],
+    expected: usize,
+    min_index: usize,
+    loose: bool,
+) -> Option<usize> {
+    let last = lines.len().checked_sub(old.len())?;
+    if min_index > last {
+        return None;
+    }
+    let expected = expected.clamp(min_index, last);
+    let matches_at = |start: usize| {
+        old.iter()
+            .enumerate()
+            .all(|(offset, line)| lines_match(&lines[start + offset], line, loose))
+    };
+    for distance in 0..=last - min_index {
+        if let Some(start) = expected
+            .checked_add(distance)
+            .filter(|start| *start <= last)
+        {
+            if matches_at(start) {
+                return Some(start);
+            }
+        }
+        if let Some(start) = expected
+            .checked_sub(distance)
+            .filter(|start| *start >= min_index && distance > 0)
+        {
+            if matches_at(start) {
+                return Some(start);
+            }
+        }
+    }
+    None
+}
+
+/// Locate a hunk, first as written and then with up to `MAX_FUZZ` context
+/// lines dropped from each end. Returns the start line and the number of
+/// hunk lines dropped from the front and back. A pure insert is never placed
+/// by its line number alone.
+fn locate_hunk(
+    lines: &[String],
+    hunk: &Hunk,
+    expected: usize,
+    min_index: usize,
+) -> Option<(usize, usize, usize)> {
+    let leading = hunk
+        .lines
+        .iter()
+        .take_while(|line| matches!(line, HunkLine::Context(_)))
+        .count();
+    let trailing = hunk
+        .lines
+        .iter()
+        .rev()
+        .take_while(|line| matches!(line, HunkLine::Context(_)))
+        .count();
+
+    let mut tried = Vec::new();
+    for fuzz in 0..=MAX_FUZZ {
+        let front = fuzz.min(leading);
+        let back = fuzz.min(trailing);
+        if tried.contains(&(front, back)) {
+            continue;
+        }
+        tried.push((front, back));
+
+        let body = &hunk.lines[front..hunk.lines.len() - back];
+        let old: Vec<&str> = body
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect();
+        if old.is_empty() {
+            // Nothing is left to check the position against, which is only
+            // fine when filling an empty file. Anywhere else an insert needs
+            // at least one matching context line.
+            if lines.is_empty() {
+                return Some((expected.clamp(min_index, lines.len()), front, back));
+            }
+            continue;
+        }
+        let expected = expected + front;
+        for loose in [false, true] {
+            if let Some(start) = find_block(lines, &old, expected, min_index, loose) {
+                return Some((start, front, back));
+            }
+        }
+    }
+    None
+}
+
+fn apply_hunks(original: &str, hunks: &[Hunk]) -> Result<String, String> {
+    let line_break = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut trailing_newline = original.is_empty() || original.ends_with('\n');
+    let mut lines: Vec<String> = original.lines().map(str::to_string).collect();
+    let mut offset: isize = 0;
+    let mut min_index = 0usize;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        let expected = if hunk.old_start == 0 {
+            min_index
+        } else {
+            (hunk.old_start as isize - 1 + offset).max(0) as usize
+        };
+        let (start, front, back) =
+            locate_hunk(&lines, hunk, expected, min_index).ok_or_else(|| match hunk.old_start {
+                0 => format!("hunk {} does not match the file", index + 1),
+                line => format!(
+                    "hunk {} (original line {line}) does not match the file",
+                    index + 1
+                ),
+            })?;
+
+        let body = &hunk.lines[front..hunk.lines.len() - back];
+        let mut replacement = Vec::new();
+        let mut cursor = start;
+        for line in body {
+            match line {
+                HunkLine::Context(_) => {
+                    // Keep the file's own text when matched loosely.
+                    replacement.push(lines[cursor].clone());
+                    cursor += 1;
+                }
+                HunkLine::Remove(_) => cursor += 1,
+                HunkLine::Add(text) => replacement.push(text.clone()),
+            }
+        }
+        let old_len = cursor - start;
+        let new_len = replacement.len();
+        let at_end = cursor == lines.len();
+        lines.splice(start..cursor, replacement);
+
+        if at_end {
+            if hunk.new_missing_newline {
+                trailing_newline = false;
+            } else if hunk.old_missing_newline {
+                trailing_newline = true;
+            }
+        }
+        let growth = new_len as isize - old_len as isize;
+        offset = if hunk.old_start == 0 {
+            offset + growth
+        } else {
+            start as isize - (hunk.old_start as isize - 1 + front as isize) + growth
+        };
+        min_index = start + new_len;
+    }
+
+    let mut updated = lines.join(line_break);
+    if trailing_newline && !lines.is_empty() {
+        updated.push_str(line_break);
+    }
+    Ok(updated)
+}
+
+fn write_planned(file: &PlannedFile, created_dirs: &mut Vec<PathBuf>) -> io::Result<()> {
+    let Some(content) = &file.after else {
+        return fs::remove_file(&file.path);
+    };
+    if let Some(parent) = file.path.parent() {
+        let mut missing: Vec<PathBuf> = parent
+            .ancestors()
+            .take_while(|dir| !dir.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        if !missing.is_empty() {
+            fs::create_dir_all(parent)?;
+            missing.reverse();
+            created_dirs.extend(missing);
+        }
+    }
+    write_atomic(&file.path, content.as_bytes())
+}
+
+/// Undo written files in reverse order. Returns the files that could not be
+/// restored.
+fn rollback(written: &[&PlannedFile], created_dirs: &[PathBuf]) -> Vec<String> {
+    let mut failed = Vec::new();
+    for file in written.iter().rev() {
+        let result = match &file.before {
+            Some(before) => write_atomic(&file.path, before),
+            None => fs::remove_file(&file.path),
+        };
+        if result.is_err() {
+            failed.push(file.display.clone());
+        }
+    }
+    for dir in created_dirs.iter().rev() {
+        let _ = fs::remove_dir(dir);
+    }
+    failed
+}
@@ -0,0 +1,63 @@
+use lopdf::{Document, LoadOptions};
+
+/// Decompressed streams larger than this are not read.
+const MAX_STREAM_BYTES: usize = 16 * 1024 * 1024;
+
+/// Minimum number of letters or digits for the extraction to count as text.
+const MIN_TEXT_CHARS: usize = 20;
+
+/// Text of every page of a PDF in page order, decoded through each font's
+/// encoding or ToUnicode map. Text in fonts that cannot be decoded is
+/// skipped, and scanned pages come out empty, so a document with too little
+/// text is reported as an error.
+pub fn extract_text(bytes: &[u8]) -> Result<String, String> {
+    if !bytes.starts_with(b"%PDF-") {
+        return Err("Not a PDF document".to_string());
+    }
+    let options = LoadOptions {
+        max_decompressed_size: Some(MAX_STREAM_BYTES),
+        ..Default::default()
+    };
+    // Documents encrypted with an empty user password open as usual.
+    let document =
+        Document::load_mem_with_options(bytes, options).map_err(|error| match error {
+            lopdf::Error::InvalidPassword => "The PDF is encrypted".to_string(),
+            error => format!("Failed to parse the PDF: {error}"),
+        })?;
+
+    let mut text = String::new();
+    for page in document.get_pages().keys() {
+        let page_text: String = document
+            .extract_text_chunks_with_limit(&[*page], MAX_STREAM_BYTES)
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
+        if !page_text.trim().is_empty() {
+            text.push_str(page_text.trim_end());
+            text.push_str("\n\n");
+        }
+    }
+
+    let text = tidy(&text);
+    if text.chars().filter(|ch| ch.is_alphanumeric()).count() < MIN_TEXT_CHARS {
+        return Err(
+            "No extractable text found; the PDF may be scanned or use fonts without a text mapping"
+                .to_string(),
+        );
+    }
+    Ok(text)
+}
+
+fn tidy(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
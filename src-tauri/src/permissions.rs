@@ -0,0 +1,999 @@
+use globset::GlobBuilder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::file_ops;
+use crate::git;
+use crate::patch;
+use crate::workspace;
+use crate::worktree;
+
+const PERMISSIONS_FILE: &str = "permissions.json";
+
+/// Protected paths used when the global permissions file does not set any.
+const DEFAULT_PROTECTED_ASK: &[&str] = &[".git/**", ".env*", ".kimicodegui/permissions.json"];
+const DEFAULT_PROTECTED_DENY: &[&str] = &[
+    "~/.ssh",
+    "~/.kimicodegui/credentials",
+    "~/.kimicodegui/permissions.json",
+];
+
+/// Allow/ask/deny rules such as `Shell(git status*)` or `WriteFile(src/**)`.
+/// A bare tool name (`SearchWeb`) matches every call of that tool.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionRules {
+    pub allow: Vec<String>,
+    pub ask: Vec<String>,
+    pub deny: Vec<String>,
+    /// Directories outside the workspace that file tools may access. `~`
+    /// expands to the home directory; relative roots are resolved against
+    /// the workspace. Only read from the global file.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed_roots: Vec<String>,
+    /// Replaces the built-in protected paths when set in the global file;
+    /// a workspace file can only add to them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protected: Option<ProtectedPaths>,
+    /// Only read from the global file: workspace permission files the user
+    /// trusted, by path, with the content version they trusted.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub trusted_workspaces: BTreeMap<String, String>,
+}
+
+/// Sensitive paths that file tools may never touch (`deny`) or only touch
+/// after an explicit approval, even in yolo mode (`ask`).
+///
+/// Patterns starting with `~` or `/` match absolute paths, patterns with a
+/// `/` match workspace-relative paths and bare patterns such as `.env*`
+/// match a file or directory name at any depth. A pattern also covers
+/// everything below the paths it matches.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProtectedPaths {
+    pub ask: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl ProtectedPaths {
+    fn defaults() -> Self {
+        ProtectedPaths {
+            ask: DEFAULT_PROTECTED_ASK
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            deny: DEFAULT_PROTECTED_DENY
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+pub struct PermissionsPayload {
+    pub global_path: String,
+    pub global: PermissionRules,
+    pub workspace_path: Option<String>,
+    pub workspace: Option<PermissionRules>,
+    /// Content version of the workspace file, passed back to trust it.
+    pub workspace_version: Option<String>,
+    /// Whether the workspace file's allow rules are in effect.
+    pub workspace_trusted: bool,
+}
+
+/// Session-level permission mode. In `Plan` mode the agent may only
+/// investigate and must finish by submitting a plan for approval.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionMode {
+    #[default]
+    Default,
+    Plan,
+}
+
+impl PermissionMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim() {
+            "" | "default" => Ok(PermissionMode::Default),
+            "plan" => Ok(PermissionMode::Plan),
+            other => Err(format!("Unknown permission mode: {other}")),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PermissionMode::Default => "default",
+            PermissionMode::Plan => "plan",
+        }
+    }
+}
+
+/// Commands that only inspect the workspace and may run in plan mode.
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "basename", "cat", "cut", "date", "df", "diff", "dirname", "du", "echo", "file", "find",
+    "grep", "head", "ls", "pwd", "realpath", "rg", "sort", "stat", "tail", "tree", "uname", "uniq",
+    "wc", "which", "whoami",
+];
+
+const READ_ONLY_GIT_SUBCOMMANDS: &[&str] = &[
+    "blame",
+    "diff",
+    "grep",
+    "log",
+    "ls-files",
+    "rev-parse",
+    "show",
+    "status",
+];
+
+/// Commands never offered as an "always allow" prefix: interpreters and
+/// wrappers run arbitrary code, and the rest destroy data.
+const NO_SUGGESTION_COMMANDS: &[&str] = &[
+    "bash", "sh", "zsh", "fish", "dash", "ksh", "pwsh", "python", "python3", "node", "deno", "bun",
+    "npx", "perl", "ruby", "php", "lua", "env", "sudo", "doas", "eval", "exec", "xargs", "nohup",
+    "timeout", "watch", "rm", "rmdir", "dd", "mkfs", "shred", "truncate", "chmod", "chown", "mv",
+];
+
+const FIND_WRITE_FLAGS: &[&str] = &[
+    "-delete", "-exec", "-execdir", "-fls", "-fprint", "-fprint0", "-fprintf", "-ok", "-okdir",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    Ask,
+    Deny,
+}
+
+#[derive(Clone, Debug)]
+pub struct Evaluation {
+    pub decision: Decision,
+    /// The rule that produced the decision, `None` when the default applied.
+    pub rule: Option<String>,
+    /// Set when a protected path decided the call; such calls cannot be
+    /// covered by an allow rule.
+    pub protected: bool,
+}
+
+fn global_permissions_path() -> PathBuf {
+    crate::kimi_share_dir().join(PERMISSIONS_FILE)
+}
+
+fn workspace_permissions_path(work_dir: &str) -> PathBuf {
+    Path::new(&worktree::project_dir(work_dir))
+        .join(".kimicodegui")
+        .join(PERMISSIONS_FILE)
+}
+
+fn scope_path(scope: &str, work_dir: Option<&str>) -> Result<PathBuf, String> {
+    match scope {
+        "global" => Ok(global_permissions_path()),
+        "workspace" => work_dir
+            .filter(|dir| !dir.trim().is_empty())
+            .map(workspace_permissions_path)
+            .ok_or_else(|| "Work dir is required for workspace rules".to_string()),
+        other => Err(format!("Unknown permission scope: {other}")),
+    }
+}
+
+/// The rules in a permissions file, with the version of its content.
+fn read_rules(path: &Path) -> Result<Option<(PermissionRules, String)>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw =
+        fs::read_to_string(path).map_err(|error| format!("Failed to read {path:?}: {error}"))?;
+    let rules = serde_json::from_str(&raw)
+        .map_err(|error| format!("Invalid permissions JSON in {path:?}: {error}"))?;
+    Ok(Some((rules, file_ops::content_version(raw.as_bytes()))))
+}
+
+fn load_rules(path: &Path) -> Result<Option<PermissionRules>, String> {
+    Ok(read_rules(path)?.map(|(rules, _)| rules))
+}
+
+/// Write a permissions file and return the version of what was written.
+fn save_rules(path: &Path, rules: &PermissionRules) -> Result<String, String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create directory {parent:?}: {error}"))?;
+    }
+    let raw = serde_json::to_string_pretty(rules)
+        .map_err(|error| format!("Failed to encode permissions: {error}"))?;
+    fs::write(path, &raw).map_err(|error| format!("Failed to write {path:?}: {error}"))?;
+    Ok(file_ops::content_version(raw.as_bytes()))
+}
+
+fn trust_key(path: &Path) -> String {
+    path_text(&canonicalize_existing(path))
+}
+
+/// Whether the user trusted this exact content of a workspace permissions
+/// file.
+fn is_trusted(path: &Path, version: &str) -> bool {
+    load_rules(&global_permissions_path())
+        .ok()
+        .flatten()
+        .is_some_and(|global| {
+            global
+                .trusted_workspaces
+                .get(&trust_key(path))
+                .map(String::as_str)
+                == Some(version)
+        })
+}
+
+fn trust_workspace_file(path: &Path, version: &str) -> Result<(), String> {
+    let global_path = global_permissions_path();
+    let mut global = load_rules(&global_path)?.unwrap_or_default();
+    global
+        .trusted_workspaces
+        .insert(trust_key(path), version.to_string());
+    save_rules(&global_path, &global).map(|_| ())
+}
+
+/// Workspace rules first, then global ones; unreadable files are skipped.
+/// The workspace file comes with the repository, so its allow rules only
+/// apply once the user has trusted its current content.
+fn rule_sets(work_dir: &str) -> Vec<PermissionRules> {
+    let mut sets = Vec::new();
+    let workspace_path = workspace_permissions_path(work_dir);
+    if let Ok(Some((mut rules, version))) = read_rules(&workspace_path) {
+        if !is_trusted(&workspace_path, &version) {
+            rules.allow.clear();
+        }
+        sets.push(rules);
+    }
+    if let Ok(Some(rules)) = load_rules(&global_permissions_path()) {
+        sets.push(rules);
+    }
+    sets
+}
+
+/// Protected paths from the global file (or the defaults) plus any the
+/// workspace adds. An unreadable global file falls back to the defaults.
+fn protected_paths(work_dir: &str) -> ProtectedPaths {
+    let mut protected = load_rules(&global_permissions_path())
+        .ok()
+        .flatten()
+        .and_then(|rules| rules.protected)
+        .unwrap_or_else(ProtectedPaths::defaults);
+    if let Some(extra) = load_rules(&workspace_permissions_path(work_dir))
+        .ok()
+        .flatten()
+        .and_then(|rules| rules.protected)
+    {
+        protected.ask.extend(extra.ask);
+        protected.deny.extend(extra.deny);
+    }
+    protected
+}
+
+/// Turn a tool path into an absolute one without touching the disk: `~`
+/// expands to the home directory, relative paths are joined to the work dir
+/// and `.`/`..` components are folded.
+fn absolute_path(work_dir: &str, path: &str) -> PathBuf {
+    let home_relative = match path {
+        "~" => Some(""),
+        _ => path.strip_prefix("~/").or_else(|| path.strip_prefix("~\\")),
+    };
+    let joined = match (home_relative, dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => match workspace::split_root_path(work_dir, path) {
+            Some((root, rest)) => root.join(rest),
+            None => Path::new(work_dir).join(path),
+        },
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Canonicalize the deepest existing ancestor of `path` so that symlinks
+/// cannot hide where a new file would end up.
+fn canonicalize_existing(path: &Path) -> PathBuf {
+    for ancestor in path.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            let rest = path.strip_prefix(ancestor).unwrap_or(Path::new(""));
+            return if rest.as_os_str().is_empty() {
+                canonical
+            } else {
+                canonical.join(rest)
+            };
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Resolve a tool path the way file tools see it: absolute, with `~`
+/// expanded and symlinks in the existing part resolved.
+pub fn normalize_path(work_dir: &str, path: &str) -> PathBuf {
+    canonicalize_existing(&absolute_path(work_dir, path))
+}
+
+fn canonical_work_dir(work_dir: &str) -> PathBuf {
+    Path::new(work_dir)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(work_dir))
+}
+
+/// The workspace's roots plus the extra roots from the global permissions
+/// file. A workspace file could widen the boundary from the repository
+/// itself, so its `allowed_roots` are ignored.
+fn allowed_roots(work_dir: &str) -> Vec<PathBuf> {
+    let mut roots = vec![canonical_work_dir(work_dir)];
+    roots.extend(
+        workspace::roots(work_dir)
+            .iter()
+            .skip(1)
+            .map(|root| canonical_work_dir(&root.path)),
+    );
+    let global = load_rules(&global_permissions_path())
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    for root in global.allowed_roots {
+        if !root.trim().is_empty() {
+            roots.push(normalize_path(work_dir, root.trim()));
+        }
+    }
+    roots
+}
+
+/// Refuse paths outside the workspace unless they are under an allowed root.
+pub fn check_boundary(work_dir: &str, path: &Path) -> Result<(), String> {
+    if allowed_roots(work_dir)
+        .iter()
+        .any(|root| path.starts_with(root))
+    {
+        return Ok(());
+    }
+    Err(format!(
+        "Path {} is outside the working directory. Add a parent directory to allowed_roots in {} to grant access.",
+        path.display(),
+        global_permissions_path().display()
+    ))
+}
+
+fn path_text(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if matches!(ch, '*' | '?' | '[' | ']' | '{' | '}' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn protected_matches(pattern: &str, path: &Path, work_root: &Path) -> bool {
+    let pattern = pattern.trim().to_string();
+    let relative = path.strip_prefix(work_root).ok();
+    let (pattern, subject) = if let Some(rest) = pattern.strip_prefix('~') {
+        let Some(home) = dirs::home_dir() else {
+            return false;
+        };
+        let home = home.canonicalize().unwrap_or(home);
+        (
+            format!("{}{rest}", escape_glob(&path_text(&home))),
+            path_text(path),
+        )
+    } else if Path::new(&pattern).is_absolute() || pattern.starts_with('/') {
+        (pattern, path_text(path))
+    } else if pattern.contains('/') {
+        let Some(relative) = relative else {
+            return false;
+        };
+        (pattern, path_text(relative))
+    } else if pattern.is_empty() {
+        return false;
+    } else {
+        // Bare names only look below the workspace root, so a workspace that
+        // itself lives under e.g. `.env-projects/` is not protected wholesale.
+        return relative
+            .unwrap_or(path)
+            .components()
+            .any(|component| match component {
+                Component::Normal(name) => glob_matches(&pattern, &name.to_string_lossy(), true),
+                _ => false,
+            });
+    };
+    let pattern = pattern.trim_end_matches('/');
+    glob_matches(pattern, &subject, true)
+        || glob_matches(&format!("{pattern}/**"), &subject, true)
+        || pattern
+            .strip_suffix("/**")
+            .is_some_and(|parent| glob_matches(parent, &subject, true))
+}
+
+/// Paths a file or `Git` tool call reads or writes, as given by the model.
+fn tool_paths(tool_name: &str, args: &serde_json::Value) -> Vec<String> {
+    if tool_name == "Git" {
+        return git::paths_arg(args);
+    }
+    if tool_name == "ApplyPatch" {
+        return patch::parse(args)
+            .map(|changes| patch::touched_paths(&changes))
+            .unwrap_or_default();
+    }
+    if !is_path_tool(tool_name) {
+        return Vec::new();
+    }
+    args.get("path")
+        .and_then(|v| v.as_str())
+        .map(|path| vec![path.trim().to_string()])
+        .unwrap_or_default()
+}
+
+/// The strongest protected-path match for a tool call: deny patterns are
+/// checked before ask patterns.
+fn protected_decision(
+    work_dir: &str,
+    tool_name: &str,
+    args: &serde_json::Value,
+) -> Option<(Decision, String)> {
+    let paths: Vec<PathBuf> = tool_paths(tool_name, args)
+        .iter()
+        .filter(|path| !path.is_empty())
+        .map(|path| normalize_path(work_dir, path))
+        .collect();
+    if paths.is_empty() {
+        return None;
+    }
+    let protected = protected_paths(work_dir);
+    // Relative patterns apply inside whichever workspace root holds the path.
+    let roots: Vec<PathBuf> = workspace::roots(work_dir)
+        .iter()
+        .map(|root| canonical_work_dir(&root.path))
+        .collect();
+    let work_root = |path: &Path| {
+        roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .unwrap_or(&roots[0])
+            .clone()
+    };
+    for (decision, patterns) in [
+        (Decision::Deny, &protected.deny),
+        (Decision::Ask, &protected.ask),
+    ] {
+        for pattern in patterns {
+            if paths
+                .iter()
+                .any(|path| protected_matches(pattern, path, &work_root(path)))
+            {
+                return Some((decision, format!("Protected({})", pattern.trim())));
+            }
+        }
+    }
+    None
+}
+
+fn parse_rule(rule: &str) -> Option<(&str, Option<&str>)> {
+    let rule = rule.trim();
+    if rule.is_empty() {
+        return None;
+    }
+    match rule.find('(') {
+        Some(open) if rule.ends_with(')') => {
+            let tool = rule[..open].trim();
+            let pattern = rule[open + 1..rule.len() - 1].trim();
+            Some((tool, Some(pattern)))
+        }
+        Some(_) => None,
+        None => Some((rule, None)),
+    }
+}
+
+fn is_path_tool(tool_name: &str) -> bool {
+    matches!(
+        tool_name,
+        "ReadFile"
+            | "WriteFile"
+            | "StrReplaceFile"
+            | "ApplyPatch"
+            | "ReadNotebook"
+            | "EditNotebook"
+    )
+}
+
+fn glob_matches(pattern: &str, subject: &str, path_like: bool) -> bool {
+    GlobBuilder::new(pattern)
+        .literal_separator(path_like)
+        .backslash_escape(true)
+        .build()
+        .map(|glob| glob.compile_matcher().is_match(subject))
+        .unwrap_or(false)
+}
+
+/// Split a shell command on control operators (`&&`, `||`, `;`, `|`, `&`,
+/// newlines). Returns `None` when the command uses command substitution,
+/// since its effects cannot be judged from the text.
+pub fn shell_segments(command: &str) -> Option<Vec<String>> {
+    if command.contains("$(") || command.contains('`') {
+        return None;
+    }
+
+    let chars: Vec<char> = command.chars().collect();
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let split = match c {
+            ';' | '\n' | '|' => true,
+            // Keep redirections such as `2>&1` and `&>` inside the segment.
+            '&' => {
+                let prev = if i > 0 { Some(chars[i - 1]) } else { None };
+                !matches!(prev, Some('>') | Some('<')) && next != Some('>')
+            }
+            _ => false,
+        };
+        if split {
+            if matches!((c, next), ('&', Some('&')) | ('|', Some('|'))) {
+                i += 1;
+            }
+            segments.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+        i += 1;
+    }
+    segments.push(current);
+
+    Some(
+        segments
+            .into_iter()
+            .map(|segment| segment.trim().to_string())
+            .filter(|segment| !segment.is_empty())
+            .collect(),
+    )
+}
+
+/// Drop redirections that neither read nor write files.
+fn strip_harmless_redirections(segment: &str) -> String {
+    segment
+        .replace("2>&1", "")
+        .replace("2>/dev/null", "")
+        .replace(">/dev/null", "")
+        .replace("> /dev/null", "")
+}
+
+/// Whether a shell segment redirects to or from a file, or uses process
+/// substitution (`<(…)`, `>(…)`).
+fn has_redirection(segment: &str) -> bool {
+    strip_harmless_redirections(segment).contains(['<', '>'])
+}
+
+fn is_read_only_segment(segment: &str) -> bool {
+    if has_redirection(segment) {
+        return false;
+    }
+    let Ok(words) = shell_words::split(&strip_harmless_redirections(segment)) else {
+        return false;
+    };
+    let Some((program, args)) = words.split_first() else {
+        return false;
+    };
+    let has_option = |prefixes: &[&str]| {
+        args.iter()
+            .any(|arg| prefixes.iter().any(|prefix| arg.starts_with(prefix)))
+    };
+    // Short option clusters such as `-uo` can hide an output flag.
+    let has_short_option = |option: char| {
+        args.iter().any(|arg| {
+            arg.len() > 1 && arg.starts_with('-') && !arg.starts_with("--") && arg.contains(option)
+        })
+    };
+    match program.as_str() {
+        "git" => {
+            // `-c core.fsmonitor=…`, `-c core.pager=…` and `grep -O<cmd>` run
+            // commands of the caller's choosing.
+            if has_option(&[
+                "-c",
+                "--config-env",
+                "--exec-path",
+                "--output",
+                "-O",
+                "--open-files-in-pager",
+            ]) {
+                return false;
+            }
+            let mut rest = args.iter();
+            while let Some(word) = rest.next() {
+                if word == "-C" {
+                    rest.next();
+                    continue;
+                }
+                if word.starts_with('-') {
+                    continue;
+                }
+                return READ_ONLY_GIT_SUBCOMMANDS.contains(&word.as_str());
+            }
+            false
+        }
+        "find" => !args
+            .iter()
+            .any(|arg| FIND_WRITE_FLAGS.contains(&arg.as_str())),
+        // `--pre` runs a preprocessor on every file.
+        "rg" => !has_option(&["--pre"]),
+        "sort" => !has_option(&["--output", "--compress-program"]) && !has_short_option('o'),
+        "tree" => !has_option(&["-o"]),
+        "file" => !has_option(&["--compile"]) && !has_short_option('C'),
+        "date" => !has_option(&["--set"]) && !has_short_option('s'),
+        // A second operand is the output file.
+        "uniq" => args.iter().filter(|arg| !arg.starts_with('-')).count() <= 1,
+        other => READ_ONLY_COMMANDS.contains(&other),
+    }
+}
+
+/// Whether every part of a shell command is on the read-only allowlist.
+pub fn is_read_only_command(command: &str) -> bool {
+    match shell_segments(command) {
+        Some(segments) if !segments.is_empty() => {
+            segments.iter().all(|segment| is_read_only_segment(segment))
+        }
+        _ => false,
+    }
+}
+
+/// Tools that modify the workspace and are hidden in plan mode.
+pub fn is_mutating_tool(tool_name: &str) -> bool {
+    matches!(
+        tool_name,
+        "WriteFile" | "StrReplaceFile" | "ApplyPatch" | "EditNotebook"
+    )
+}
+
+/// Why a tool call is refused in plan mode, if it is.
+pub fn plan_mode_refusal(tool_name: &str, args: &serde_json::Value) -> Option<String> {
+    if tool_name == "Git" && !git::is_read_only(args) {
+        return Some("Only read-only git operations are allowed in plan mode.".to_string());
+    }
+    if is_mutating_tool(tool_name) {
+        return Some(format!(
+            "{tool_name} is not available in plan mode. Investigate with read-only tools and call SubmitPlan when ready."
+        ));
+    }
+    if tool_name == "Shell" {
+        let command = args.get("command").and_then(|v| v.as_str()).unwrap_or("");
+        if !is_read_only_command(command) {
+            return Some("Only read-only shell commands are allowed in plan mode.".to_string());
+        }
+    }
+    None
+}
+
+/// The value a rule pattern is matched against for a given tool call.
+fn rule_subject(tool_name: &str, args: &serde_json::Value, work_dir: &str) -> Option<String> {
+    if tool_name == "Git" {
+        return Some(git::rule_subject(args));
+    }
+    let key = match tool_name {
+        "Shell" => "command",
+        "ReadFile" | "WriteFile" | "StrReplaceFile" | "ReadNotebook" | "EditNotebook" => "path",
+        "SearchWeb" => "query",
+        "FetchURL" => "url",
+        _ => return None,
+    };
+    let value = args.get(key).and_then(|v| v.as_str())?.trim();
+    if is_path_tool(tool_name) {
+        Some(workspace_relative(value, work_dir))
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn workspace_relative(path: &str, work_dir: &str) -> String {
+    let input = Path::new(path);
+    let relative = if input.is_absolute() {
+        input.strip_prefix(work_dir).unwrap_or(input)
+    } else {
+        input
+    };
+    let text = relative.to_string_lossy().replace('\\', "/");
+    text.strip_prefix("./").unwrap_or(&text).to_string()
+}
+
+fn rule_matches(rule: &str, tool_name: &str, subject: Option<&str>) -> bool {
+    let Some((tool, pattern)) = parse_rule(rule) else {
+        return false;
+    };
+    if tool != tool_name {
+        return false;
+    }
+    match (pattern, subject) {
+        (None, _) => true,
+        (Some(pattern), Some(subject)) => glob_matches(pattern, subject, is_path_tool(tool)),
+        (Some(_), None) => false,
+    }
+}
+
+fn find_rule<'a>(
+    mut rules: impl Iterator<Item = &'a String>,
+    tool_name: &str,
+    subject: Option<&str>,
+) -> Option<String> {
+    rules
+        .find(|rule| rule_matches(rule, tool_name, subject))
+        .cloned()
+}
+
+/// Decide how to handle a tool call. Protected paths and deny rules win over
+/// ask rules, which win over allow rules; allow rules never apply to a
+/// protected path. Shell commands are judged per segment: any denied segment
+/// denies the call, and every segment must be allowed for an allow.
+pub fn evaluate(
+    work_dir: &str,
+    tool_name: &str,
+    args: &serde_json::Value,
+    default: Decision,
+) -> Evaluation {
+    let protected = protected_decision(work_dir, tool_name, args);
+    if let Some((Decision::Deny, pattern)) = &protected {
+        return Evaluation {
+            decision: Decision::Deny,
+            rule: Some(pattern.clone()),
+            protected: true,
+        };
+    }
+
+    let sets = rule_sets(work_dir);
+    let subject = rule_subject(tool_name, args, work_dir);
+
+    let subjects: Vec<Option<String>> = if tool_name == "Shell" {
+        match subject.as_deref().map(shell_segments) {
+            Some(Some(segments)) if !segments.is_empty() => {
+                segments.into_iter().map(Some).collect()
+            }
+            // Unparseable commands can still be denied or asked for by a bare
+            // `Shell` rule, but never allowed by a pattern.
+            _ => vec![None],
+        }
+    } else if tool_name == "ApplyPatch" {
+        // Every file touched by the patch has to pass, like shell segments.
+        match patch::parse(args) {
+            Ok(changes) => patch::touched_paths(&changes)
+                .iter()
+                .map(|path| Some(workspace_relative(path, work_dir)))
+                .collect(),
+            Err(_) => vec![None],
+        }
+    } else {
+        vec![subject]
+    };
+
+    for subject in &subjects {
+        if let Some(rule) = find_rule(
+            sets.iter().flat_map(|set| set.deny.iter()),
+            tool_name,
+            subject.as_deref(),
+        ) {
+            return Evaluation {
+                decision: Decision::Deny,
+                rule: Some(rule),
+                protected: false,
+            };
+        }
+    }
+
+    if let Some((_, pattern)) = protected {
+        return Evaluation {
+            decision: Decision::Ask,
+            rule: Some(pattern),
+            protected: true,
+        };
+    }
+
+    for subject in &subjects {
+        if let Some(rule) = find_rule(
+            sets.iter().flat_map(|set| set.ask.iter()),
+            tool_name,
+            subject.as_deref(),
+        ) {
+            return Evaluation {
+                decision: Decision::Ask,
+                rule: Some(rule),
+                protected: false,
+            };
+        }
+    }
+
+    let mut allowed_by = Vec::new();
+    for subject in &subjects {
+        // A prefix rule such as `Shell(git status*)` must not cover what the
+        // segment redirects to.
+        let subject = subject
+            .as_deref()
+            .filter(|segment| tool_name != "Shell" || !has_redirection(segment));
+        match find_rule(
+            sets.iter().flat_map(|set| set.allow.iter()),
+            tool_name,
+            subject,
+        ) {
+            Some(rule) => {
+                if !allowed_by.contains(&rule) {
+                    allowed_by.push(rule);
+                }
+            }
+            None => {
+                allowed_by.clear();
+                break;
+            }
+        }
+    }
+    if !allowed_by.is_empty() {
+        return Evaluation {
+            decision: Decision::Allow,
+            rule: Some(allowed_by.join(", ")),
+            protected: false,
+        };
+    }
+
+    Evaluation {
+        decision: default,
+        rule: None,
+        protected: false,
+    }
+}
+
+/// Propose an allow rule covering calls similar to this one, used for the
+/// "always allow this pattern" option in approval requests.
+pub fn suggest_rule(tool_name: &str, args: &serde_json::Value, work_dir: &str) -> Option<String> {
+    let subject = rule_subject(tool_name, args, work_dir)?;
+    match tool_name {
+        "Shell" => {
+            let segments = shell_segments(&subject)?;
+            if segments.len() != 1 || has_redirection(&segments[0]) {
+                return None;
+            }
+            let words: Vec<&str> = segments[0].split_whitespace().collect();
+            let program = words.first()?.rsplit('/').next()?;
+            if NO_SUGGESTION_COMMANDS.contains(&program) {
+                return None;
+            }
+            let prefix = match words.as_slice() {
+                [first, second, ..] if !second.starts_with('-') => format!("{first} {second}"),
+                [first, ..] => first.to_string(),
+                [] => return None,
+            };
+            Some(format!("Shell({prefix}*)"))
+        }
+        "Git" => Some(format!("Git({subject})")),
+        _ if is_path_tool(tool_name) => {
+            let pattern = match Path::new(&subject).parent() {
+                Some(parent)
+                    if !parent.as_os_str().is_empty() && !Path::new(&subject).is_absolute() =>
+                {
+                    format!("{}/**", parent.to_string_lossy())
+                }
+                _ => subject,
+            };
+            Some(format!("{tool_name}({pattern})"))
+        }
+        _ => Some(tool_name.to_string()),
+    }
+}
+
+/// Append an allow rule to the given scope (`workspace` or `global`). A
+/// workspace file written this way stays trusted, but one holding allow
+/// rules the user has not trusted is left alone.
+pub fn add_allow_rule(work_dir: &str, scope: &str, rule: &str) -> Result<(), String> {
+    if parse_rule(rule).is_none() {
+        return Err(format!("Invalid permission rule: {rule}"));
+    }
+    let path = scope_path(scope, Some(work_dir))?;
+    let existing = read_rules(&path)?;
+    if scope == "workspace" {
+        if let Some((rules, version)) = &existing {
+            if !rules.allow.is_empty() && !is_trusted(&path, version) {
+                return Err(format!(
+                    "{} has allow rules you have not trusted; review and trust them first",
+                    path.display()
+                ));
+            }
+        }
+    }
+    let mut rules = existing.map(|(rules, _)| rules).unwrap_or_default();
+    if !rules.allow.iter().any(|existing| existing == rule) {
+        rules.allow.push(rule.to_string());
+    }
+    let version = save_rules(&path, &rules)?;
+    if scope == "workspace" {
+        trust_workspace_file(&path, &version)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn permissions_load(work_dir: Option<String>) -> Result<PermissionsPayload, String> {
+    let global_path = global_permissions_path();
+    let global = load_rules(&global_path)?.unwrap_or_default();
+    let mut workspace_version = None;
+    let mut workspace_trusted = false;
+    let (workspace_path, workspace) = match work_dir.filter(|dir| !dir.trim().is_empty()) {
+        Some(dir) => {
+            let path = workspace_permissions_path(&dir);
+            let rules = match read_rules(&path)? {
+                Some((rules, version)) => {
+                    workspace_trusted = is_trusted(&path, &version);
+                    workspace_version = Some(version);
+                    rules
+                }
+                None => PermissionRules::default(),
+            };
+            (Some(path.to_string_lossy().to_string()), Some(rules))
+        }
+        None => (None, None),
+    };
+
+    Ok(PermissionsPayload {
+        global_path: global_path.to_string_lossy().to_string(),
+        global,
+        workspace_path,
+        workspace,
+        workspace_version,
+        workspace_trusted,
+    })
+}
+
+/// Trust the workspace permissions file as the user reviewed it. Fails if
+/// it changed since `version` was loaded.
+#[tauri::command]
+pub fn permissions_trust_workspace(work_dir: String, version: String) -> Result<(), String> {
+    let path = workspace_permissions_path(&work_dir);
+    match read_rules(&path)? {
+        Some((_, current)) if current == version => trust_workspace_file(&path, &version),
+        Some(_) => Err("The workspace permissions changed; review them again".to_string()),
+        None => Err(format!("{} does not exist", path.display())),
+    }
+}
+
+#[tauri::command]
+pub fn permissions_save(
+    scope: String,
+    work_dir: Option<String>,
+    mut rules: PermissionRules,
+) -> Result<(), String> {
+    for rule in rules.allow.iter().chain(&rules.ask).chain(&rules.deny) {
+        if parse_rule(rule).is_none() {
+            return Err(format!("Invalid permission rule: {rule}"));
+        }
+    }
+    if let Some(protected) = &rules.protected {
+        for pattern in protected.ask.iter().chain(&protected.deny) {
+            if GlobBuilder::new(pattern.trim()).build().is_err() {
+                return Err(format!("Invalid protected path pattern: {pattern}"));
+            }
+        }
+    }
+    let path = scope_path(&scope, work_dir.as_deref())?;
+    if scope == "workspace" {
+        // Rules saved from the app are the user's own.
+        rules.trusted_workspaces.clear();
+        let version = save_rules(&path, &rules)?;
+        return trust_workspace_file(&path, &version);
+    }
+    rules.trusted_workspaces = load_rules(&path)?
+        .map(|existing| existing.trusted_workspaces)
+        .unwrap_or_default();
+    save_rules(&path, &rules).map(|_| ())
+}
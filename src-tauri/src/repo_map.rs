@@ -0,0 +1,423 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::file_index::IgnoreStack;
+
+/// Directory levels shown below the root.
+const MAX_DEPTH: usize = 4;
+
+/// The walk stops after this many entries and the map says it is partial.
+const MAX_WALKED: usize = 20_000;
+
+/// Entries shown per directory; the rest are counted.
+const MAX_DIR_ENTRIES: usize = 40;
+
+const MAX_FILE_SYMBOLS: usize = 12;
+
+/// Larger files are not scanned for symbols.
+const MAX_SYMBOL_FILE_BYTES: u64 = 256 * 1024;
+
+const MAX_MANIFESTS: usize = 20;
+
+/// A rough average, good enough to keep the map within its budget.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Maps are rebuilt after this long even if no change was reported, in case
+/// nothing watches the root.
+const MAP_TTL: Duration = Duration::from_secs(300);
+
+const MANIFESTS: [&str; 4] = ["Cargo.toml", "package.json", "pyproject.toml", "go.mod"];
+
+struct Node {
+    name: String,
+    is_dir: bool,
+    children: Vec<Node>,
+    /// Files anywhere below a directory.
+    files: usize,
+    /// Whether a directory holds source files or manifests anywhere below.
+    /// Others, like icon folders, are not expanded.
+    code: bool,
+    /// Top-level declarations of a source file, e.g. `fn main`.
+    symbols: Vec<String>,
+}
+
+/// A scanned work dir, rendered into the system prompt on demand.
+pub struct RepoMap {
+    root: Node,
+    /// Languages by number of files, most used first.
+    languages: Vec<(&'static str, usize)>,
+    /// Manifest paths relative to the root, with what they describe.
+    manifests: Vec<(String, String)>,
+    truncated: bool,
+    built_at: Instant,
+}
+
+fn language(name: &str) -> Option<&'static str> {
+    let extension = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match extension.as_str() {
+        "rs" => "Rust",
+        "py" | "pyi" => "Python",
+        "js" | "mjs" | "cjs" | "jsx" => "JavaScript",
+        "ts" | "mts" | "cts" | "tsx" => "TypeScript",
+        "go" => "Go",
+        "java" => "Java",
+        "kt" | "kts" => "Kotlin",
+        "swift" => "Swift",
+        "c" | "h" => "C",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" => "C++",
+        "cs" => "C#",
+        "rb" => "Ruby",
+        "php" => "PHP",
+        "html" | "htm" => "HTML",
+        "css" | "scss" | "sass" | "less" => "CSS",
+        "vue" => "Vue",
+        "svelte" => "Svelte",
+        "sh" | "bash" | "zsh" => "Shell",
+        "sql" => "SQL",
+        _ => return None,
+    })
+}
+
+/// Patterns for top-level declarations, capturing the kind and the name.
+/// Only unindented lines are matched.
+struct SymbolPatterns {
+    rust: Regex,
+    python: Regex,
+    script: Regex,
+    go: Regex,
+}
+
+impl SymbolPatterns {
+    fn new() -> Self {
+        let build = |source: &str| Regex::new(source).expect("valid symbol pattern");
+        SymbolPatterns {
+            rust: build(
+                r"^(pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe)\s+)*(fn|struct|enum|trait|type|const|static|macro_rules!)\s*([A-Za-z_][A-Za-z0-9_]*)",
+            ),
+            python: build(r"^(?:async\s+)?(def|class)\s+([A-Za-z_][A-Za-z0-9_]*)"),
+            script: build(
+                r"^(export\s+)?(?:default\s+)?(?:declare\s+)?(?:abstract\s+)?(?:async\s+)?(function\*?|class|interface|type|enum|const|let|var)\s+([A-Za-z_$][A-Za-z0-9_$]*)",
+            ),
+            go: build(r"^(func|type)\s+(?:\([^)]*\)\s*)?([A-Za-z_][A-Za-z0-9_]*)"),
+        }
+    }
+
+    fn scan(&self, language: &str, text: &str) -> Vec<String> {
+        let mut symbols = Vec::new();
+        for line in text.lines() {
+            let symbol = match language {
+                // Private functions and constants are mostly helpers.
+                "Rust" => self
+                    .rust
+                    .captures(line)
+                    .filter(|caps| {
+                        caps.get(1).is_some() || !matches!(&caps[2], "fn" | "const" | "static")
+                    })
+                    .map(|caps| format!("{} {}", caps[2].trim_end_matches('!'), &caps[3])),
+                "Python" => self
+                    .python
+                    .captures(line)
+                    .map(|caps| format!("{} {}", &caps[1], &caps[2])),
+                // Plain variables are only worth listing when exported.
+                "JavaScript" | "TypeScript" => self
+                    .script
+                    .captures(line)
+                    .filter(|caps| {
+                        caps.get(1).is_some() || !matches!(&caps[2], "const" | "let" | "var")
+                    })
+                    .map(|caps| format!("{} {}", &caps[2], &caps[3])),
+                "Go" => self
+                    .go
+                    .captures(line)
+                    .map(|caps| format!("{} {}", &caps[1], &caps[2])),
+                _ => return Vec::new(),
+            };
+            if let Some(symbol) = symbol {
+                symbols.push(symbol);
+                if symbols.len() >= MAX_FILE_SYMBOLS {
+                    break;
+                }
+            }
+        }
+        symbols
+    }
+}
+
+/// What a manifest declares, e.g. "Rust crate `app`".
+fn describe_manifest(name: &str, content: &str) -> String {
+    match name {
+        "Cargo.toml" => {
+            let Ok(value) = content.parse::<toml::Value>() else {
+                return "Rust manifest".to_string();
+            };
+            let package = value
+                .get("package")
+                .and_then(|package| package.get("name"))
+                .and_then(|name| name.as_str());
+            let members: Vec<&str> = value
+                .get("workspace")
+                .and_then(|workspace| workspace.get("members"))
+                .and_then(|members| members.as_array())
+                .map(|members| members.iter().filter_map(|m| m.as_str()).collect())
+                .unwrap_or_default();
+            match (package, members.is_empty()) {
+                (Some(name), true) => format!("Rust crate `{name}`"),
+                (Some(name), false) => {
+                    format!(
+                        "Rust crate `{name}`, workspace members: {}",
+                        members.join(", ")
+                    )
+                }
+                (None, false) => format!("Rust workspace, members: {}", members.join(", ")),
+                (None, true) => "Rust manifest".to_string(),
+            }
+        }
+        "package.json" => {
+            let Ok(value) = serde_json::from_str::<serde_json::Value>(content) else {
+                return "Node package".to_string();
+            };
+            let mut description = match value.get("name").and_then(|name| name.as_str()) {
+                Some(name) => format!("Node package `{name}`"),
+                None => "Node package".to_string(),
+            };
+            if let Some(scripts) = value.get("scripts").and_then(|s| s.as_object()) {
+                let names: Vec<&str> = scripts.keys().map(String::as_str).take(10).collect();
+                if !names.is_empty() {
+                    description.push_str(&format!(", scripts: {}", names.join(", ")));
+                }
+            }
+            description
+        }
+        "pyproject.toml" => {
+            let name = content.parse::<toml::Value>().ok().and_then(|value| {
+                value
+                    .get("project")
+                    .or_else(|| value.get("tool").and_then(|tool| tool.get("poetry")))
+                    .and_then(|project| project.get("name"))
+                    .and_then(|name| name.as_str())
+                    .map(str::to_string)
+            });
+            match name {
+                Some(name) => format!("Python project `{name}`"),
+                None => "Python project".to_string(),
+            }
+        }
+        "go.mod" => match content
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("module "))
+        {
+            Some(module) => format!("Go module `{}`", module.trim()),
+            None => "Go module".to_string(),
+        },
+        _ => String::new(),
+    }
+}
+
+struct Scan {
+    patterns: SymbolPatterns,
+    ignores: IgnoreStack,
+    walked: usize,
+    truncated: bool,
+    languages: HashMap<&'static str, usize>,
+    manifests: Vec<(String, String)>,
+}
+
+impl Scan {
+    /// The node of directory `dir`, with everything below it.
+    fn walk(&mut self, dir: &Path, name: String, rel: &str, depth: usize) -> Node {
+        let mut node = Node {
+            name,
+            is_dir: true,
+            children: Vec::new(),
+            files: 0,
+            code: false,
+            symbols: Vec::new(),
+        };
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return node;
+        };
+        let ignore_depth = self.ignores.enter(dir, rel);
+        let mut entries: Vec<_> = read_dir.flatten().collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            if self.walked >= MAX_WALKED {
+                self.truncated = true;
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            let child_rel = if rel.is_empty() {
+                name.clone()
+            } else {
+                format!("{rel}/{name}")
+            };
+            // Symlinked directories are listed but not followed.
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_dir = file_type.is_dir();
+            if self.ignores.is_ignored(&child_rel, is_dir) {
+                continue;
+            }
+            self.walked += 1;
+
+            if is_dir {
+                let child = self.walk(&entry.path(), name, &child_rel, depth + 1);
+                node.files += child.files;
+                node.code |= child.code;
+                node.children.push(child);
+                continue;
+            }
+
+            node.files += 1;
+            let manifest = MANIFESTS.contains(&name.as_str());
+            if manifest && self.manifests.len() < MAX_MANIFESTS {
+                let content = fs::read_to_string(entry.path()).unwrap_or_default();
+                self.manifests
+                    .push((child_rel.clone(), describe_manifest(&name, &content)));
+            }
+            let language = language(&name);
+            let mut symbols = Vec::new();
+            if let Some(language) = language {
+                *self.languages.entry(language).or_default() += 1;
+                // Only files that can show up in the map are read.
+                let small = entry
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.len() <= MAX_SYMBOL_FILE_BYTES);
+                if depth <= MAX_DEPTH && small {
+                    if let Ok(text) = fs::read_to_string(entry.path()) {
+                        symbols = self.patterns.scan(language, &text);
+                    }
+                }
+            }
+            node.code |= manifest || language.is_some();
+            node.children.push(Node {
+                name,
+                is_dir,
+                children: Vec::new(),
+                files: 0,
+                code: manifest || language.is_some(),
+                symbols,
+            });
+        }
+        self.ignores.leave(ignore_depth);
+
+        // Directories first, like the file tree.
+        node.children.sort_by_key(|child| !child.is_dir);
+        node
+    }
+}
+
+impl RepoMap {
+    /// Scan `root`, honoring the same ignore rules as the file tree.
+    pub fn build(root: &Path) -> Self {
+        let mut scan = Scan {
+            patterns: SymbolPatterns::new(),
+            ignores: IgnoreStack::for_root(root),
+            walked: 0,
+            truncated: false,
+            languages: HashMap::new(),
+            manifests: Vec::new(),
+        };
+        let root = scan.walk(root, ".".to_string(), "", 1);
+        let mut languages: Vec<_> = scan.languages.into_iter().collect();
+        languages.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        RepoMap {
+            root,
+            languages,
+            manifests: scan.manifests,
+            truncated: scan.truncated,
+            built_at: Instant::now(),
+        }
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.built_at.elapsed() < MAP_TTL
+    }
+
+    /// The map as text of about `token_budget` tokens at most. Symbols are
+    /// dropped first, then the deepest tree levels, and as a last resort the
+    /// text is cut.
+    pub fn render(&self, token_budget: usize) -> String {
+        let budget = token_budget * CHARS_PER_TOKEN;
+        let attempts = std::iter::once((MAX_DEPTH, true))
+            .chain((1..=MAX_DEPTH).rev().map(|depth| (depth, false)));
+        let mut text = String::new();
+        for (depth, symbols) in attempts {
+            text = self.render_with(depth, symbols);
+            if text.len() <= budget {
+                return text;
+            }
+        }
+        let mut cut = budget;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(newline) = text[..cut].rfind('\n') {
+            cut = newline + 1;
+        }
+        text.truncate(cut);
+        text.push_str("… (map cut to fit)\n");
+        text
+    }
+
+    fn render_with(&self, max_depth: usize, symbols: bool) -> String {
+        let mut out = String::new();
+        if !self.languages.is_empty() {
+            let languages: Vec<String> = self
+                .languages
+                .iter()
+                .map(|(name, files)| format!("{name} ({files})"))
+                .collect();
+            out.push_str(&format!("Languages (files): {}\n", languages.join(", ")));
+        }
+        if !self.manifests.is_empty() {
+            out.push_str("Manifests:\n");
+            for (path, description) in &self.manifests {
+                out.push_str(&format!("  {path}: {description}\n"));
+            }
+        }
+        out.push_str(&format!("Tree ({} files):\n", self.root.files));
+        render_children(&self.root, 1, max_depth, symbols, &mut out);
+        if self.truncated {
+            out.push_str(&format!("(listing stopped after {MAX_WALKED} entries)\n"));
+        }
+        out
+    }
+}
+
+fn render_children(dir: &Node, depth: usize, max_depth: usize, symbols: bool, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    for node in dir.children.iter().take(MAX_DIR_ENTRIES) {
+        if node.is_dir {
+            if depth < max_depth && node.code {
+                out.push_str(&format!("{indent}{}/\n", node.name));
+                render_children(node, depth + 1, max_depth, symbols, out);
+            } else {
+                let files = match node.files {
+                    0 => String::new(),
+                    1 => " (1 file)".to_string(),
+                    count => format!(" ({count} files)"),
+                };
+                out.push_str(&format!("{indent}{}/{files}\n", node.name));
+            }
+        } else if symbols && !node.symbols.is_empty() {
+            out.push_str(&format!(
+                "{indent}{}: {}\n",
+                node.name,
+                node.symbols.join(", ")
+            ));
+        } else {
+            out.push_str(&format!("{indent}{}\n", node.name));
+        }
+    }
+    if dir.children.len() > MAX_DIR_ENTRIES {
+        out.push_str(&format!(
+            "{indent}… {} more\n",
+            dir.children.len() - MAX_DIR_ENTRIES
+        ));
+    }
+}
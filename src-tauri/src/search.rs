@@ -0,0 +1,361 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::oauth::common_headers;
+
+const SEARCH_TIMEOUT_SECS: u64 = 30;
+
+/// Service keys checked in `services`, in order. Backends the user set up
+/// themselves win over the Moonshot service.
+const BACKEND_KEYS: [&str; 3] = ["custom_search", "searxng_search", "moonshot_search"];
+
+/// One search hit, whatever backend produced it.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SearchResult {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub snippet: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default)]
+    pub date: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MoonshotResponse {
+    #[serde(default)]
+    search_results: Vec<SearchResult>,
+}
+
+/// URL, credentials and extra headers shared by every backend.
+#[derive(Clone, Debug)]
+pub struct Endpoint {
+    base_url: String,
+    api_key: Option<String>,
+    /// Header that carries `api_key` verbatim; `Authorization: Bearer` when
+    /// unset.
+    api_key_header: Option<String>,
+    custom_headers: HashMap<String, String>,
+}
+
+impl Endpoint {
+    fn parse(service: &serde_json::Value) -> Option<Self> {
+        let base_url = service.get("base_url")?.as_str()?.trim().to_string();
+        if base_url.is_empty() {
+            return None;
+        }
+        let text = |key: &str| {
+            service
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+        Some(Endpoint {
+            base_url,
+            api_key: text("api_key"),
+            api_key_header: text("api_key_header"),
+            custom_headers: string_map(service.get("custom_headers")),
+        })
+    }
+
+    fn apply(&self, mut req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(key) = &self.api_key {
+            req = match &self.api_key_header {
+                Some(header) => req.header(header, key),
+                None => req.header("Authorization", format!("Bearer {key}")),
+            };
+        }
+        for (k, v) in self.custom_headers.iter() {
+            req = req.header(k, v);
+        }
+        req.timeout(Duration::from_secs(SEARCH_TIMEOUT_SECS))
+    }
+}
+
+fn string_map(value: Option<&serde_json::Value>) -> HashMap<String, String> {
+    value
+        .and_then(|v| v.as_object())
+        .map(|map| {
+            map.iter()
+                .filter_map(|(k, v)| json_text(v).map(|v| (k.clone(), v)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// A generic JSON search API described in config:
+///
+/// ```toml
+/// [services.custom_search]
+/// base_url = "https://api.search.brave.com/res/v1/web/search"
+/// api_key = "..."
+/// api_key_header = "X-Subscription-Token"
+/// method = "GET"               # or "POST" to send a JSON body
+/// query_param = "q"
+/// limit_param = "count"
+/// params = { safesearch = "moderate" }
+/// results_path = "web.results"
+/// fields = { title = "title", url = "url", snippet = "description", date = "age" }
+/// ```
+///
+/// Paths are dot-separated and may index arrays (`items.0.link`).
+#[derive(Clone, Debug)]
+pub struct HttpMapping {
+    post: bool,
+    query_param: String,
+    limit_param: Option<String>,
+    params: serde_json::Map<String, serde_json::Value>,
+    results_path: String,
+    fields: HashMap<String, String>,
+}
+
+impl HttpMapping {
+    fn parse(service: &serde_json::Value) -> Self {
+        let text = |key: &str, default: &str| {
+            service
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or(default)
+                .trim()
+                .to_string()
+        };
+        HttpMapping {
+            post: text("method", "GET").eq_ignore_ascii_case("POST"),
+            query_param: text("query_param", "q"),
+            limit_param: Some(text("limit_param", "")).filter(|param| !param.is_empty()),
+            params: service
+                .get("params")
+                .and_then(|v| v.as_object())
+                .cloned()
+                .unwrap_or_default(),
+            results_path: text("results_path", "results"),
+            fields: string_map(service.get("fields")),
+        }
+    }
+
+    fn field<'a>(&'a self, name: &'a str) -> &'a str {
+        self.fields.get(name).map(String::as_str).unwrap_or(name)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum SearchBackend {
+    Moonshot(Endpoint),
+    /// A SearXNG instance with the JSON output format enabled.
+    Searxng {
+        endpoint: Endpoint,
+        categories: Option<String>,
+        language: Option<String>,
+    },
+    Http {
+        endpoint: Endpoint,
+        mapping: HttpMapping,
+    },
+}
+
+impl SearchBackend {
+    /// The first configured backend from `services`.
+    pub fn from_config(config: &serde_json::Value) -> Option<Self> {
+        let services = config.get("services")?;
+        BACKEND_KEYS.iter().find_map(|key| {
+            let service = services.get(*key)?;
+            let endpoint = Endpoint::parse(service)?;
+            let optional = |name: &str| {
+                service
+                    .get(name)
+                    .and_then(|v| v.as_str())
+                    .map(str::to_string)
+            };
+            match *key {
+                "moonshot_search" => endpoint
+                    .api_key
+                    .is_some()
+                    .then_some(SearchBackend::Moonshot(endpoint)),
+                "searxng_search" => Some(SearchBackend::Searxng {
+                    endpoint,
+                    categories: optional("categories"),
+                    language: optional("language"),
+                }),
+                _ => Some(SearchBackend::Http {
+                    endpoint,
+                    mapping: HttpMapping::parse(service),
+                }),
+            }
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SearchBackend::Moonshot(_) => "Moonshot search",
+            SearchBackend::Searxng { .. } => "SearXNG",
+            SearchBackend::Http { .. } => "custom search",
+        }
+    }
+
+    /// Run a query. Only the Moonshot service can crawl result pages, so
+    /// `include_content` is ignored by the other backends.
+    pub async fn search(
+        &self,
+        tool_call_id: &str,
+        query: &str,
+        limit: usize,
+        include_content: bool,
+    ) -> Result<Vec<SearchResult>, String> {
+        let client = reqwest::Client::new();
+        let mut results = match self {
+            SearchBackend::Moonshot(endpoint) => {
+                let mut req = endpoint.apply(client.post(&endpoint.base_url));
+                req = req.header("X-Msh-Tool-Call-Id", tool_call_id);
+                for (k, v) in common_headers().into_iter() {
+                    req = req.header(k, v);
+                }
+                let response = send(req.json(&serde_json::json!({
+                    "text_query": query,
+                    "limit": limit,
+                    "enable_page_crawling": include_content,
+                    "timeout_seconds": SEARCH_TIMEOUT_SECS
+                })))
+                .await?;
+                response
+                    .json::<MoonshotResponse>()
+                    .await
+                    .map_err(|err| format!("Failed to parse search response: {err}"))?
+                    .search_results
+            }
+            SearchBackend::Searxng {
+                endpoint,
+                categories,
+                language,
+            } => {
+                let base = endpoint.base_url.trim_end_matches('/');
+                let url = if base.ends_with("/search") {
+                    base.to_string()
+                } else {
+                    format!("{base}/search")
+                };
+                let mut params = vec![("q", query.to_string()), ("format", "json".to_string())];
+                if let Some(categories) = categories {
+                    params.push(("categories", categories.clone()));
+                }
+                if let Some(language) = language {
+                    params.push(("language", language.clone()));
+                }
+                let value = send_json(endpoint.apply(client.get(url).query(&params))).await?;
+                value
+                    .get("results")
+                    .and_then(|v| v.as_array())
+                    .map(|items| {
+                        items
+                            .iter()
+                            .map(|item| SearchResult {
+                                title: field_text(item, "title"),
+                                url: field_text(item, "url"),
+                                snippet: field_text(item, "content"),
+                                content: String::new(),
+                                date: field_text(item, "publishedDate"),
+                            })
+                            .collect()
+                    })
+                    .ok_or_else(|| {
+                        "SearXNG response has no results list; is the json format enabled?"
+                            .to_string()
+                    })?
+            }
+            SearchBackend::Http { endpoint, mapping } => {
+                let mut params = mapping.params.clone();
+                params.insert(mapping.query_param.clone(), query.into());
+                if let Some(limit_param) = &mapping.limit_param {
+                    params.insert(limit_param.clone(), limit.into());
+                }
+                let req = if mapping.post {
+                    client.post(&endpoint.base_url).json(&params)
+                } else {
+                    let query: Vec<(String, String)> = params
+                        .iter()
+                        .filter_map(|(k, v)| json_text(v).map(|v| (k.clone(), v)))
+                        .collect();
+                    client.get(&endpoint.base_url).query(&query)
+                };
+                let value = send_json(endpoint.apply(req)).await?;
+                lookup(&value, &mapping.results_path)
+                    .and_then(|v| v.as_array())
+                    .map(|items| {
+                        items
+                            .iter()
+                            .map(|item| SearchResult {
+                                title: field_text(item, mapping.field("title")),
+                                url: field_text(item, mapping.field("url")),
+                                snippet: field_text(item, mapping.field("snippet")),
+                                content: field_text(item, mapping.field("content")),
+                                date: field_text(item, mapping.field("date")),
+                            })
+                            .collect()
+                    })
+                    .ok_or_else(|| {
+                        format!("Search response has no array at {}", mapping.results_path)
+                    })?
+            }
+        };
+        results.retain(|result| !result.url.is_empty() || !result.title.is_empty());
+        results.truncate(limit);
+        Ok(results)
+    }
+}
+
+async fn send(req: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+    let response = req
+        .send()
+        .await
+        .map_err(|err| format!("Failed to search: {err}"))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Search request failed with status {}",
+            response.status()
+        ));
+    }
+    Ok(response)
+}
+
+async fn send_json(req: reqwest::RequestBuilder) -> Result<serde_json::Value, String> {
+    send(req)
+        .await?
+        .json()
+        .await
+        .map_err(|err| format!("Failed to parse search response: {err}"))
+}
+
+/// Follow a dot-separated path through objects and arrays.
+fn lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .filter(|part| !part.is_empty())
+        .try_fold(value, |current, part| match current {
+            serde_json::Value::Array(items) => items.get(part.parse::<usize>().ok()?),
+            _ => current.get(part),
+        })
+}
+
+fn json_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Number(number) => Some(number.to_string()),
+        serde_json::Value::Bool(flag) => Some(flag.to_string()),
+        serde_json::Value::Array(items) => {
+            let parts: Vec<String> = items.iter().filter_map(json_text).collect();
+            (!parts.is_empty()).then(|| parts.join(", "))
+        }
+        _ => None,
+    }
+}
+
+fn field_text(item: &serde_json::Value, path: &str) -> String {
+    lookup(item, path)
+        .and_then(json_text)
+        .map(|text| text.trim().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,238 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::content::{sniff, ContentKind, SNIFF_BYTES};
+use crate::file_index::IndexedFile;
+
+/// Matches reported when the request sets no limit.
+pub const DEFAULT_LIMIT: usize = 2_000;
+
+/// Larger files are skipped.
+const MAX_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Previews longer than this are cut down to a window around the match.
+const PREVIEW_CHARS: usize = 200;
+
+/// Characters kept before the match in a shortened preview.
+const PREVIEW_CONTEXT: usize = 40;
+
+#[derive(Clone, Deserialize)]
+pub struct SearchOptions {
+    pub query: String,
+    /// Treat `query` as a regular expression rather than literal text.
+    #[serde(default)]
+    pub regex: bool,
+    #[serde(default)]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub whole_word: bool,
+    /// Only search files matching one of these globs.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Skip files matching any of these globs.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Stop after this many matches.
+    pub limit: Option<usize>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct SearchMatch {
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column, in characters.
+    pub column: usize,
+    pub preview: String,
+    /// Character range of the match inside `preview`.
+    pub match_start: usize,
+    pub match_end: usize,
+}
+
+/// The matches found in one file.
+#[derive(Clone, Serialize)]
+pub struct FileMatches {
+    pub path: String,
+    pub matches: Vec<SearchMatch>,
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct SearchSummary {
+    pub files_searched: usize,
+    pub files_matched: usize,
+    pub matches: usize,
+    /// The match limit was reached, or the file index is incomplete.
+    pub truncated: bool,
+    pub cancelled: bool,
+}
+
+/// A validated search, ready to run over a list of files.
+pub struct Searcher {
+    pattern: Regex,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    limit: usize,
+}
+
+/// Globs without a slash match at any depth, like in ignore files.
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>, String> {
+    let mut builder = GlobSetBuilder::new();
+    let mut any = false;
+    for pattern in patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let pattern = pattern.trim_start_matches("./").trim_start_matches('/');
+        let pattern = if pattern.contains('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{pattern}")
+        };
+        for pattern in [pattern.clone(), format!("{pattern}/**")] {
+            let glob = GlobBuilder::new(&pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Invalid glob {pattern:?}: {e}"))?;
+            builder.add(glob);
+        }
+        any = true;
+    }
+    if !any {
+        return Ok(None);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| format!("Invalid globs: {e}"))
+}
+
+impl Searcher {
+    pub fn new(options: &SearchOptions) -> Result<Self, String> {
+        if options.query.is_empty() {
+            return Err("Search query is empty".to_string());
+        }
+        let mut source = if options.regex {
+            options.query.clone()
+        } else {
+            regex::escape(&options.query)
+        };
+        if options.whole_word {
+            source = format!(r"\b(?:{source})\b");
+        }
+        let pattern = RegexBuilder::new(&source)
+            .case_insensitive(!options.case_sensitive)
+            .build()
+            .map_err(|e| format!("Invalid regular expression: {e}"))?;
+        Ok(Searcher {
+            pattern,
+            include: glob_set(&options.include)?,
+            exclude: glob_set(&options.exclude)?.unwrap_or_else(GlobSet::empty),
+            limit: options.limit.unwrap_or(DEFAULT_LIMIT).max(1),
+        })
+    }
+
+    fn wants(&self, path: &str) -> bool {
+        !self.exclude.is_match(path)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(path))
+    }
+
+    /// Search `files`, handing each file with matches to `found` as soon as
+    /// it is read. Stops at the limit or once `cancel` is set. Globs apply
+    /// to paths relative to each file's root.
+    pub fn run<'a>(
+        &self,
+        files: impl Iterator<Item = IndexedFile<'a>>,
+        cancel: &AtomicBool,
+        found: &mut dyn FnMut(FileMatches),
+    ) -> SearchSummary {
+        let mut summary = SearchSummary::default();
+        for file in files.filter(|file| self.wants(file.rel)) {
+            if cancel.load(Ordering::Relaxed) {
+                summary.cancelled = true;
+                break;
+            }
+            let Some(text) = read_text(&file.full) else {
+                continue;
+            };
+            summary.files_searched += 1;
+            let remaining = self.limit - summary.matches;
+            let matches = self.search_text(&text, remaining);
+            if matches.is_empty() {
+                continue;
+            }
+            summary.files_matched += 1;
+            summary.matches += matches.len();
+            found(FileMatches {
+                path: file.path.to_string(),
+                matches,
+            });
+            if summary.matches >= self.limit {
+                summary.truncated = true;
+                break;
+            }
+        }
+        summary
+    }
+
+    fn search_text(&self, text: &str, limit: usize) -> Vec<SearchMatch> {
+        let mut matches = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            for found in self.pattern.find_iter(line) {
+                // Empty matches, e.g. from `^`, carry nothing to show.
+                if found.is_empty() {
+                    continue;
+                }
+                if matches.len() >= limit {
+                    return matches;
+                }
+                matches.push(preview(line, index + 1, found.start(), found.end()));
+            }
+        }
+        matches
+    }
+}
+
+/// Decode a file as text, or `None` for binary and oversized files.
+fn read_text(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_FILE_BYTES {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    let ContentKind::Text { encoding, .. } = sniff(&bytes[..bytes.len().min(SNIFF_BYTES)]) else {
+        return None;
+    };
+    Some(encoding.decode(&bytes).0.into_owned())
+}
+
+fn preview(line: &str, line_number: usize, start: usize, end: usize) -> SearchMatch {
+    let column = line[..start].chars().count();
+    let match_len = line[start..end].chars().count();
+    let total = line.chars().count();
+
+    let (skip, take) = if total <= PREVIEW_CHARS {
+        (0, total)
+    } else {
+        let skip = column.saturating_sub(PREVIEW_CONTEXT);
+        (skip, PREVIEW_CHARS.max(match_len + column - skip))
+    };
+    let mut preview: String = line.chars().skip(skip).take(take).collect();
+    let mut match_start = column - skip;
+    if skip > 0 {
+        preview.insert(0, '…');
+        match_start += 1;
+    }
+    if skip + take < total {
+        preview.push('…');
+    }
+    SearchMatch {
+        line: line_number,
+        column: column + 1,
+        preview,
+        match_start,
+        match_end: match_start + match_len,
+    }
+}
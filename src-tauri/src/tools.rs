@@ -1,19 +1,28 @@
-use reqwest::header::CONTENT_TYPE;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use encoding_rs::{Encoding, UTF_8};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read};
 use std::path::{Path, PathBuf};
 use tokio::process::Command;
 use tokio::time::{timeout, Duration};
 
+use crate::content::{self, ContentKind, TranscodingReader, SNIFF_BYTES};
+use crate::diff::{self, FileDiff};
+use crate::fetch::{self, FetchPolicy};
+use crate::html;
 use crate::oauth::common_headers;
+use crate::pdf;
+use crate::permissions;
+use crate::search::SearchBackend;
 const MAX_LINES: usize = 1000;
 const MAX_LINE_LENGTH: usize = 2000;
 const MAX_BYTES: usize = 100_000;
 const MAX_OUTPUT_CHARS: usize = 50_000;
 const MAX_OUTPUT_LINE_LENGTH: usize = 2000;
 const TRUNCATION_MARKER: &str = "[...truncated]";
+const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
 
 #[derive(Clone, Debug)]
 pub struct ToolOutput {
@@ -22,28 +31,6 @@ pub struct ToolOutput {
     pub output: String,
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct SearchResponse {
-    #[serde(default)]
-    search_results: Vec<SearchResult>,
-}
-
-#[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct SearchResult {
-    #[serde(default)]
-    title: String,
-    #[serde(default)]
-    url: String,
-    #[serde(default)]
-    snippet: String,
-    #[serde(default)]
-    content: String,
-    #[serde(default)]
-    date: String,
-}
-
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 struct ServiceConfig {
@@ -106,48 +93,21 @@ fn parse_service_config(value: &serde_json::Value, key: &str) -> Option<ServiceC
     })
 }
 
-fn resolve_path(work_dir: &str, path: &str, must_exist: bool) -> Result<PathBuf, String> {
+/// Resolve a tool path to an absolute one. `~` expands to the home
+/// directory and `name:rest` to a path inside the named workspace root. The
+/// result must lie inside a workspace root or one of the extra roots allowed
+/// in the permission settings.
+pub fn resolve_path(work_dir: &str, path: &str, must_exist: bool) -> Result<PathBuf, String> {
     if path.trim().is_empty() {
         return Err("Path cannot be empty".to_string());
     }
 
-    let input_path = Path::new(path);
-    let base = Path::new(work_dir);
-    let target = if input_path.is_absolute() {
-        input_path.to_path_buf()
-    } else {
-        base.join(input_path)
-    };
-
-    if must_exist {
-        let canonical = target
-            .canonicalize()
-            .map_err(|e| format!("Failed to resolve path: {e}"))?;
-        if !input_path.is_absolute() {
-            let canonical_root = base
-                .canonicalize()
-                .map_err(|e| format!("Failed to resolve work dir: {e}"))?;
-            if !canonical.starts_with(&canonical_root) {
-                return Err("Path is outside working directory".to_string());
-            }
-        }
-        return Ok(canonical);
+    let resolved = permissions::normalize_path(work_dir, path);
+    if must_exist && !resolved.exists() {
+        return Err(format!("Failed to resolve path: {path} does not exist"));
     }
-
-    if !input_path.is_absolute() {
-        let canonical_root = base
-            .canonicalize()
-            .map_err(|e| format!("Failed to resolve work dir: {e}"))?;
-        let parent = target.parent().ok_or_else(|| "Invalid path".to_string())?;
-        let canonical_parent = parent
-            .canonicalize()
-            .map_err(|e| format!("Failed to resolve path: {e}"))?;
-        if !canonical_parent.starts_with(&canonical_root) {
-            return Err("Path is outside working directory".to_string());
-        }
-    }
-
-    Ok(target)
+    permissions::check_boundary(work_dir, &resolved)?;
+    Ok(resolved)
 }
 
 fn truncate_line(line: &str) -> (String, bool) {
@@ -167,7 +127,7 @@ fn truncate_line(line: &str) -> (String, bool) {
     (out, true)
 }
 
-fn truncate_output(text: &str) -> (String, bool) {
+pub fn truncate_output(text: &str) -> (String, bool) {
     let mut output = String::new();
     let mut total_chars = 0usize;
     let mut truncated = false;
@@ -253,13 +213,14 @@ pub fn tool_definitions() -> Vec<serde_json::Value> {
             "type": "function",
             "function": {
                 "name": "ReadFile",
-                "description": "Read the contents of a text file from disk.",
+                "description": "Read the contents of a text file from disk. The encoding is detected automatically, binary files are summarized, and PNG/JPEG images are shown to models that support images. Files of any size can be read in pages of up to 1000 lines using line_offset and n_lines; the result reports the total line count and whether more lines remain.",
                 "parameters": {
                     "type": "object",
                     "properties": {
                         "path": { "type": "string", "description": "File path to read." },
                         "line_offset": { "type": "integer", "description": "Line number to start from.", "minimum": 1 },
-                        "n_lines": { "type": "integer", "description": "Number of lines to read.", "minimum": 1 }
+                        "n_lines": { "type": "integer", "description": "Number of lines to read.", "minimum": 1 },
+                        "encoding": { "type": "string", "description": "Text encoding label (e.g. gbk, utf-16le) to use instead of auto-detection." }
                     },
                     "required": ["path"]
                 }
@@ -300,7 +261,7 @@ pub fn tool_definitions() -> Vec<serde_json::Value> {
             "type": "function",
             "function": {
                 "name": "StrReplaceFile",
-                "description": "Replace specific strings in a file.",
+                "description": "Replace specific strings in a file. Each `old` string must match exactly one location unless `replace_all` is set; if any edit fails, the file is left unchanged and the result reports each edit.",
                 "parameters": {
                     "type": "object",
                     "properties": {
@@ -329,12 +290,125 @@ pub fn tool_definitions() -> Vec<serde_json::Value> {
                                     }
                                 }
                             ]
+                        },
+                        "match_mode": {
+                            "type": "string",
+                            "enum": ["exact", "ignore_whitespace", "ignore_indentation"],
+                            "description": "How old strings are matched. The tolerant modes compare whole lines; ignore_indentation re-indents the replacement to the matched code. Defaults to exact."
                         }
                     },
                     "required": ["path", "edit"]
                 }
             }
         }),
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": "ReadNotebook",
+                "description": "Read a Jupyter notebook (.ipynb) as a list of cells with their index, id, type, source and truncated outputs. Use this instead of ReadFile for notebooks.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Notebook path." }
+                    },
+                    "required": ["path"]
+                }
+            }
+        }),
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": "EditNotebook",
+                "description": "Edit cells of a Jupyter notebook (.ipynb) while preserving its metadata. Use this instead of WriteFile or StrReplaceFile for notebooks. Edits are applied in order; cells are addressed by cell_id or 0-based index. Replacing a code cell's source clears its outputs.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "path": { "type": "string", "description": "Notebook path." },
+                        "edits": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "action": { "type": "string", "enum": ["replace", "insert", "delete"] },
+                                    "index": { "type": "integer", "minimum": 0, "description": "Cell index; for insert, the new cell's position." },
+                                    "cell_id": { "type": "string", "description": "Cell id; for insert, the new cell goes after this cell." },
+                                    "cell_type": { "type": "string", "enum": ["code", "markdown", "raw"] },
+                                    "source": { "type": "string", "description": "New cell source." }
+                                },
+                                "required": ["action"]
+                            }
+                        }
+                    },
+                    "required": ["path", "edits"]
+                }
+            }
+        }),
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": "Git",
+                "description": "Run a git operation in the workspace and get compact, structured output. Prefer this over Shell for git. status, diff, log, show and blame (and listing branches or stashes) are read-only; commit, branch changes and stash changes require user approval.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "operation": {
+                            "type": "string",
+                            "enum": ["status", "diff", "log", "show", "blame", "commit", "branch", "stash"],
+                            "description": "Git operation to run."
+                        },
+                        "ref": { "type": "string", "description": "diff: compare against this ref. log: start from this ref or range (e.g. main..HEAD). show: commit to show (default HEAD). blame: blame at this revision." },
+                        "staged": { "type": "boolean", "description": "diff: show staged changes instead of unstaged ones." },
+                        "stat": { "type": "boolean", "description": "diff/show: only show the per-file change summary." },
+                        "path": { "type": "string", "description": "blame: file to blame (required). show: print this file as of ref. diff/log/commit: limit to this path." },
+                        "paths": { "type": "array", "items": { "type": "string" }, "description": "diff/log: limit to these paths. commit: stage these paths before committing. stash push: only stash these paths." },
+                        "max_count": { "type": "integer", "description": "log: number of commits to show (default 20)." },
+                        "start_line": { "type": "integer", "description": "blame: first line (1-based)." },
+                        "end_line": { "type": "integer", "description": "blame: last line (inclusive)." },
+                        "message": { "type": "string", "description": "commit: commit message. stash push: stash message." },
+                        "all": { "type": "boolean", "description": "commit: stage all tracked changes (-a). branch list: include remote branches." },
+                        "action": {
+                            "type": "string",
+                            "enum": ["list", "create", "switch", "delete", "push", "pop", "apply", "drop"],
+                            "description": "branch: list, create, switch or delete. stash: list, push, pop, apply or drop. Defaults to list."
+                        },
+                        "name": { "type": "string", "description": "branch: branch name." },
+                        "start_point": { "type": "string", "description": "branch create/switch: ref to start the new branch from." },
+                        "create": { "type": "boolean", "description": "branch switch: create the branch first." },
+                        "index": { "type": "integer", "description": "stash pop/apply/drop: stash entry index (default latest)." },
+                        "include_untracked": { "type": "boolean", "description": "stash push: also stash untracked files." }
+                    },
+                    "required": ["operation"]
+                }
+            }
+        }),
+        serde_json::json!({
+            "type": "function",
+            "function": {
+                "name": "ApplyPatch",
+                "description": "Apply changes to several files at once. Pass either `patch`, a unified diff such as `git diff` output (`/dev/null` marks added or deleted files, `rename from`/`rename to` renames), or `operations`, a list of structured changes. Every hunk is checked before anything is written; if any part fails, no file is changed.",
+                "parameters": {
+                    "type": "object",
+                    "properties": {
+                        "patch": { "type": "string", "description": "Unified diff covering one or more files." },
+                        "operations": {
+                            "type": "array",
+                            "description": "Structured changes, applied together.",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "action": { "type": "string", "enum": ["add", "delete", "rename", "update"] },
+                                    "path": { "type": "string", "description": "File the change applies to." },
+                                    "to": { "type": "string", "description": "New path for rename (or update with a move)." },
+                                    "content": { "type": "string", "description": "Full content for add." },
+                                    "diff": { "type": "string", "description": "Unified diff hunks (@@ ... @@) for update or rename." }
+                                },
+                                "required": ["action", "path"]
+                            }
+                        }
+                    }
+                }
+            }
+        }),
         serde_json::json!({
             "type": "function",
             "function": {
@@ -355,7 +429,7 @@ pub fn tool_definitions() -> Vec<serde_json::Value> {
             "type": "function",
             "function": {
                 "name": "FetchURL",
-                "description": "Fetch a URL and return text/HTML content.",
+                "description": "Fetch a URL and return its content. Web pages are reduced to their main content as Markdown, JSON is pretty-printed and PDFs are returned as extracted text.",
                 "parameters": {
                     "type": "object",
                     "properties": {
@@ -368,7 +442,45 @@ pub fn tool_definitions() -> Vec<serde_json::Value> {
     ]
 }
 
-pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize) -> ToolOutput {
+/// Tool offered only in plan mode; calling it ends planning and asks the
+/// user to approve the plan.
+pub fn submit_plan_definition() -> serde_json::Value {
+    serde_json::json!({
+        "type": "function",
+        "function": {
+            "name": "SubmitPlan",
+            "description": "Submit the implementation plan for user approval. Call this once the investigation is complete. If approved, plan mode ends and you should carry out the plan.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "summary": { "type": "string", "description": "Short overview of the proposed change." },
+                    "steps": {
+                        "type": "array",
+                        "description": "Ordered steps of the plan.",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "title": { "type": "string" },
+                                "description": { "type": "string" },
+                                "files": { "type": "array", "items": { "type": "string" } }
+                            },
+                            "required": ["title"]
+                        }
+                    }
+                },
+                "required": ["summary", "steps"]
+            }
+        }
+    })
+}
+
+pub fn read_file(
+    work_dir: &str,
+    path: &str,
+    line_offset: usize,
+    n_lines: usize,
+    encoding: Option<&str>,
+) -> ToolOutput {
     let resolved = match resolve_path(work_dir, path, true) {
         Ok(p) => p,
         Err(err) => {
@@ -388,37 +500,81 @@ pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize)
         };
     }
 
-    let metadata = match fs::metadata(&resolved) {
-        Ok(m) => m,
+    let file = match fs::File::open(&resolved) {
+        Ok(f) => f,
         Err(err) => {
             return ToolOutput {
                 ok: false,
-                summary: format!("Failed to read file metadata: {err}"),
+                summary: format!("Failed to read file: {err}"),
                 output: String::new(),
             }
         }
     };
 
-    if metadata.len() > MAX_BYTES as u64 {
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    if let Err(err) = (&file).take(SNIFF_BYTES as u64).read_to_end(&mut sample) {
         return ToolOutput {
             ok: false,
-            summary: "File too large (max 100KB)".to_string(),
+            summary: format!("Failed to read file: {err}"),
             output: String::new(),
         };
     }
 
-    let file = match fs::File::open(&resolved) {
-        Ok(f) => f,
-        Err(err) => {
+    let kind = match encoding {
+        Some(label) => match Encoding::for_label(label.trim().as_bytes()) {
+            Some(encoding) => ContentKind::Text {
+                encoding,
+                has_bom: false,
+            },
+            None => {
+                return ToolOutput {
+                    ok: false,
+                    summary: format!("Unknown encoding: {label}"),
+                    output: String::new(),
+                }
+            }
+        },
+        None => content::sniff(&sample),
+    };
+
+    let (encoding, has_bom) = match kind {
+        ContentKind::Text { encoding, has_bom } => (encoding, has_bom),
+        ContentKind::Image { mime } => {
+            let format = if mime == "image/png" { "PNG" } else { "JPEG" };
+            let dimensions = content::image_dimensions(&sample)
+                .map(|(width, height)| format!("{width}x{height}, "))
+                .unwrap_or_default();
             return ToolOutput {
-                ok: false,
-                summary: format!("Failed to read file: {err}"),
+                ok: true,
+                summary: format!(
+                    "{format} image ({dimensions}{}).",
+                    content::format_size(size)
+                ),
                 output: String::new(),
-            }
+            };
+        }
+        ContentKind::Binary { mime } => {
+            return ToolOutput {
+                ok: false,
+                summary: format!(
+                    "Binary file ({}, {}) cannot be read as text. The first bytes are shown as hex.",
+                    mime.unwrap_or("unknown type"),
+                    content::format_size(size)
+                ),
+                output: content::hex_preview(&sample[..sample.len().min(256)]),
+            };
         }
     };
 
-    let reader = io::BufReader::new(file);
+    // Put the sniffed bytes back in front of the rest of the file.
+    let source = io::Cursor::new(sample).chain(file);
+    let mut reader: Box<dyn BufRead> = if encoding == UTF_8 && !has_bom {
+        Box::new(io::BufReader::new(source))
+    } else {
+        Box::new(io::BufReader::new(TranscodingReader::new(source, encoding)))
+    };
+    let mut buffer = Vec::new();
     let mut lines = Vec::new();
     let mut truncated_lines = Vec::new();
     let mut total_bytes = 0usize;
@@ -426,17 +582,26 @@ pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize)
     let start = line_offset.max(1);
     let max_lines = n_lines.max(1).min(MAX_LINES);
 
-    for line in reader.lines() {
-        let line = match line {
-            Ok(line) => line,
-            Err(_) => continue,
-        };
+    loop {
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                return ToolOutput {
+                    ok: false,
+                    summary: format!("Failed to read file: {err}"),
+                    output: String::new(),
+                }
+            }
+        }
         line_no += 1;
         if line_no < start {
             continue;
         }
 
-        let (truncated, did_truncate) = truncate_line(&line);
+        let line = String::from_utf8_lossy(&buffer);
+        let (truncated, did_truncate) = truncate_line(line.trim_end_matches(['\n', '\r']));
         if did_truncate {
             truncated_lines.push(line_no);
         }
@@ -449,18 +614,30 @@ pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize)
         }
     }
 
+    let total_lines = match count_remaining_lines(&mut reader) {
+        Ok(remaining) => line_no + remaining,
+        Err(err) => {
+            return ToolOutput {
+                ok: false,
+                summary: format!("Failed to read file: {err}"),
+                output: String::new(),
+            }
+        }
+    };
+
     let mut output = String::new();
     for (line_no, line) in &lines {
         output.push_str(&format!("{line_no:6}\t{line}\n"));
     }
 
     let mut summary = if lines.is_empty() {
-        "No lines read from file.".to_string()
+        format!("No lines read from file. The file has {total_lines} lines.")
     } else {
         format!(
-            "{} lines read from file starting at line {}.",
+            "{} lines read from file starting at line {}. The file has {} lines.",
             lines.len(),
-            start
+            start,
+            total_lines
         )
     };
 
@@ -470,10 +647,25 @@ pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize)
         summary.push_str(" Max bytes reached.");
     }
 
+    if let Some((last, _)) = lines.last() {
+        if *last < total_lines {
+            summary.push_str(&format!(
+                " More lines remain; continue with line_offset {}.",
+                last + 1
+            ));
+        } else {
+            summary.push_str(" End of file reached.");
+        }
+    }
+
     if !truncated_lines.is_empty() {
         summary.push_str(&format!(" Lines {:?} were truncated.", truncated_lines));
     }
 
+    if encoding != UTF_8 {
+        summary.push_str(&format!(" Decoded from {}.", encoding.name()));
+    }
+
     ToolOutput {
         ok: true,
         summary,
@@ -481,6 +673,54 @@ pub fn read_file(work_dir: &str, path: &str, line_offset: usize, n_lines: usize)
     }
 }
 
+/// PNG or JPEG content of `path` as a `data:` URL, or `None` for other files.
+pub fn image_data_url(work_dir: &str, path: &str) -> Result<Option<String>, String> {
+    let resolved = resolve_path(work_dir, path, true)?;
+    let mut file =
+        fs::File::open(&resolved).map_err(|err| format!("Failed to read file: {err}"))?;
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    (&mut file)
+        .take(SNIFF_BYTES as u64)
+        .read_to_end(&mut sample)
+        .map_err(|err| format!("Failed to read file: {err}"))?;
+    let ContentKind::Image { mime } = content::sniff(&sample) else {
+        return Ok(None);
+    };
+
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if size > MAX_IMAGE_BYTES {
+        return Err(format!(
+            "The image is too large to attach (max {}).",
+            content::format_size(MAX_IMAGE_BYTES)
+        ));
+    }
+    let mut bytes = sample;
+    file.read_to_end(&mut bytes)
+        .map_err(|err| format!("Failed to read file: {err}"))?;
+    Ok(Some(format!("data:{mime};base64,{}", BASE64.encode(bytes))))
+}
+
+/// Count the lines left in `reader` without decoding them, including a final
+/// line that has no trailing newline.
+fn count_remaining_lines(reader: &mut impl BufRead) -> io::Result<usize> {
+    let mut count = 0usize;
+    let mut last = None;
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        count += chunk.iter().filter(|byte| **byte == b'\n').count();
+        last = chunk.last().copied();
+        let len = chunk.len();
+        reader.consume(len);
+    }
+    if last.is_some_and(|byte| byte != b'\n') {
+        count += 1;
+    }
+    Ok(count)
+}
+
 pub async fn run_shell(work_dir: &str, command: &str, timeout_secs: u64) -> ToolOutput {
     if command.trim().is_empty() {
         return ToolOutput {
@@ -640,7 +880,309 @@ pub struct ReplaceEdit {
     pub replace_all: bool,
 }
 
-pub fn str_replace_file(work_dir: &str, path: &str, edits: Vec<ReplaceEdit>) -> ToolOutput {
+/// How `old` strings of `StrReplaceFile` edits are matched against the file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchMode {
+    Exact,
+    /// Whole lines, ignoring differences in whitespace.
+    IgnoreWhitespace,
+    /// Whole lines, ignoring leading and trailing whitespace. The replacement
+    /// is re-indented to the matched lines.
+    IgnoreIndentation,
+}
+
+impl MatchMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "exact" => Some(MatchMode::Exact),
+            "ignore_whitespace" => Some(MatchMode::IgnoreWhitespace),
+            "ignore_indentation" => Some(MatchMode::IgnoreIndentation),
+            _ => None,
+        }
+    }
+}
+
+/// Result of applying a batch of edits to a file's content.
+struct EditOutcome {
+    updated: String,
+    replacements: usize,
+    failed: usize,
+    report: Vec<String>,
+}
+
+fn line_number(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn normalize_line(line: &str, mode: MatchMode) -> String {
+    match mode {
+        MatchMode::Exact => line.to_string(),
+        MatchMode::IgnoreWhitespace => line.split_whitespace().collect::<Vec<_>>().join(" "),
+        MatchMode::IgnoreIndentation => line.trim().to_string(),
+    }
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Byte ranges where `old` matches `text`, without overlaps. Tolerant modes
+/// compare whole lines.
+fn find_matches(text: &str, old: &str, mode: MatchMode) -> Vec<(usize, usize)> {
+    if mode == MatchMode::Exact {
+        return text
+            .match_indices(old)
+            .map(|(start, matched)| (start, start + matched.len()))
+            .collect();
+    }
+
+    let old_lines: Vec<String> = old.lines().map(|line| normalize_line(line, mode)).collect();
+    if old_lines.is_empty() {
+        return Vec::new();
+    }
+    // (start, end without line break, end with line break) of each line.
+    let mut spans = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        spans.push((offset, offset + content.len(), offset + line.len()));
+        offset += line.len();
+    }
+
+    let mut matches = Vec::new();
+    let mut index = 0;
+    while index + old_lines.len() <= spans.len() {
+        let matched = old_lines.iter().enumerate().all(|(k, expected)| {
+            let (start, end, _) = spans[index + k];
+            normalize_line(&text[start..end], mode) == *expected
+        });
+        if matched {
+            let (start, _, _) = spans[index];
+            let (_, end, end_with_break) = spans[index + old_lines.len() - 1];
+            let end = if old.ends_with('\n') {
+                end_with_break
+            } else {
+                end
+            };
+            matches.push((start, end));
+            index += old_lines.len();
+        } else {
+            index += 1;
+        }
+    }
+    matches
+}
+
+/// Replacement text for a match, re-indented in `IgnoreIndentation` mode.
+fn replacement_for(matched: &str, edit: &ReplaceEdit, mode: MatchMode) -> String {
+    if mode != MatchMode::IgnoreIndentation {
+        return edit.new.clone();
+    }
+    let first_nonblank = |text: &str| {
+        text.lines()
+            .position(|line| !line.trim().is_empty())
+            .unwrap_or(0)
+    };
+    let old_index = first_nonblank(&edit.old);
+    let old_indent = edit.old.lines().nth(old_index).map(leading_whitespace);
+    let file_indent = matched.lines().nth(old_index).map(leading_whitespace);
+    let (Some(old_indent), Some(file_indent)) = (old_indent, file_indent) else {
+        return edit.new.clone();
+    };
+    edit.new
+        .split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_string()
+            } else {
+                let body = line.strip_prefix(old_indent).unwrap_or(line);
+                format!("{file_indent}{body}")
+            }
+        })
+        .collect()
+}
+
+fn similarity(a: &str, b: &str) -> f32 {
+    similar::TextDiff::configure()
+        .timeout(Duration::from_millis(200))
+        .diff_chars(a, b)
+        .ratio()
+}
+
+/// The identifiers and numbers in a line.
+fn words(line: &str) -> HashSet<&str> {
+    line.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// The part of `text` most similar to `old`, as (line number, similarity,
+/// snippet), to help correct an edit whose `old` string was not found.
+fn closest_snippet(text: &str, old: &str) -> Option<(usize, f32, String)> {
+    const MAX_SNIPPET_LINES: usize = 20;
+    const MIN_SIMILARITY: f32 = 0.5;
+    const CANDIDATES: usize = 3;
+    const PREFILTERED: usize = 50;
+
+    let lines: Vec<&str> = text.lines().collect();
+    let old_lines: Vec<&str> = old.lines().collect();
+    let first_index = old_lines.iter().position(|line| !line.trim().is_empty())?;
+    let first = old_lines[first_index].trim();
+    let window = old_lines.len();
+    if lines.is_empty() {
+        return None;
+    }
+
+    // Candidate windows: those containing the most lines of `old`, and those
+    // starting where the first line of `old` fits best. Both are found with
+    // cheap line and word comparisons so that only a handful of windows get
+    // the full character diff.
+    let old_set: HashSet<&str> = old_lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let mut hits_before = vec![0usize; lines.len() + 1];
+    for (index, line) in lines.iter().enumerate() {
+        hits_before[index + 1] = hits_before[index] + usize::from(old_set.contains(line.trim()));
+    }
+    let mut by_hits: Vec<(usize, usize)> = (0..lines.len())
+        .map(|start| {
+            let end = (start + window).min(lines.len());
+            (hits_before[end] - hits_before[start], start)
+        })
+        .filter(|(hits, _)| *hits > 0)
+        .collect();
+    by_hits.sort_by_key(|(hits, _)| std::cmp::Reverse(*hits));
+
+    let first_words = words(first);
+    let mut by_words: Vec<(usize, usize)> = lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| (words(line).intersection(&first_words).count(), index))
+        .filter(|(shared, _)| *shared > 0)
+        .collect();
+    by_words.sort_by_key(|(shared, _)| std::cmp::Reverse(*shared));
+    let mut by_first: Vec<(f32, usize)> = by_words
+        .iter()
+        .take(PREFILTERED)
+        .map(|(_, index)| {
+            (
+                similarity(lines[*index].trim(), first),
+                index.saturating_sub(first_index),
+            )
+        })
+        .collect();
+    by_first.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut candidates: Vec<usize> = by_hits
+        .iter()
+        .take(CANDIDATES)
+        .map(|(_, start)| *start)
+        .chain(by_first.iter().take(CANDIDATES).map(|(_, start)| *start))
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let target = old.trim_end_matches('\n');
+    let (score, start) = candidates
+        .into_iter()
+        .map(|start| {
+            let end = (start + window).min(lines.len());
+            (similarity(&lines[start..end].join("\n"), target), start)
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))?;
+    if score < MIN_SIMILARITY {
+        return None;
+    }
+
+    let end = (start + window.min(MAX_SNIPPET_LINES)).min(lines.len());
+    let mut snippet: Vec<String> = lines[start..end]
+        .iter()
+        .map(|line| truncate_line(line).0)
+        .collect();
+    if window > MAX_SNIPPET_LINES {
+        snippet.push(TRUNCATION_MARKER.to_string());
+    }
+    Some((start + 1, score, snippet.join("\n")))
+}
+
+fn format_lines(lines: &[usize]) -> String {
+    lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Apply edits in order, recording for each whether it matched, was
+/// ambiguous or was missing. Failed edits leave the text unchanged.
+fn apply_edits(original: &str, edits: &[ReplaceEdit], mode: MatchMode) -> EditOutcome {
+    let mut outcome = EditOutcome {
+        updated: original.to_string(),
+        replacements: 0,
+        failed: 0,
+        report: Vec::new(),
+    };
+
+    for (index, edit) in edits.iter().enumerate() {
+        let edit_no = index + 1;
+        if edit.old.is_empty() {
+            outcome.failed += 1;
+            outcome
+                .report
+                .push(format!("Edit {edit_no}: failed, old string is empty."));
+            continue;
+        }
+
+        let matches = find_matches(&outcome.updated, &edit.old, mode);
+        let lines: Vec<usize> = matches
+            .iter()
+            .map(|(start, _)| line_number(&outcome.updated, *start))
+            .collect();
+        if matches.is_empty() {
+            outcome.failed += 1;
+            let mut line = format!("Edit {edit_no}: missing, old string was not found.");
+            if let Some((line_no, score, snippet)) = closest_snippet(&outcome.updated, &edit.old) {
+                line.push_str(&format!(
+                    " Closest match at line {line_no} ({:.0}% similar):\n{snippet}",
+                    score * 100.0
+                ));
+            }
+            outcome.report.push(line);
+            continue;
+        }
+        if matches.len() > 1 && !edit.replace_all {
+            outcome.failed += 1;
+            outcome.report.push(format!(
+                "Edit {edit_no}: ambiguous, old string matches {} locations (lines {}). Add more context or set replace_all.",
+                matches.len(),
+                format_lines(&lines)
+            ));
+            continue;
+        }
+
+        for (start, end) in matches.iter().rev() {
+            let replacement = replacement_for(&outcome.updated[*start..*end], edit, mode);
+            outcome.updated.replace_range(*start..*end, &replacement);
+        }
+        outcome.replacements += matches.len();
+        outcome.report.push(format!(
+            "Edit {edit_no}: matched {} location(s) ({} {}).",
+            matches.len(),
+            if lines.len() == 1 { "line" } else { "lines" },
+            format_lines(&lines)
+        ));
+    }
+    outcome
+}
+
+pub fn str_replace_file(
+    work_dir: &str,
+    path: &str,
+    edits: Vec<ReplaceEdit>,
+    mode: MatchMode,
+) -> ToolOutput {
     let resolved = match resolve_path(work_dir, path, true) {
         Ok(p) => p,
         Err(err) => {
@@ -671,29 +1213,29 @@ pub fn str_replace_file(work_dir: &str, path: &str, edits: Vec<ReplaceEdit>) ->
         }
     };
 
-    let mut updated = original.clone();
-    let mut total_replacements = 0usize;
-
-    for edit in &edits {
-        if edit.replace_all {
-            let count = updated.matches(&edit.old).count();
-            total_replacements += count;
-            updated = updated.replace(&edit.old, &edit.new);
-        } else if updated.contains(&edit.old) {
-            updated = updated.replacen(&edit.old, &edit.new, 1);
-            total_replacements += 1;
-        }
+    let outcome = apply_edits(&original, &edits, mode);
+    let output = outcome.report.join("\n");
+    if outcome.failed > 0 {
+        return ToolOutput {
+            ok: false,
+            summary: format!(
+                "No changes were made: {} of {} edit(s) could not be applied.",
+                outcome.failed,
+                edits.len()
+            ),
+            output,
+        };
     }
 
-    if updated == original {
+    if outcome.updated == original {
         return ToolOutput {
             ok: false,
-            summary: "No replacements were made. The old string was not found.".to_string(),
-            output: String::new(),
+            summary: "No changes were made: the edits would not change the file.".to_string(),
+            output,
         };
     }
 
-    if let Err(err) = fs::write(&resolved, updated) {
+    if let Err(err) = fs::write(&resolved, outcome.updated) {
         return ToolOutput {
             ok: false,
             summary: format!("Failed to write file: {err}"),
@@ -706,10 +1248,91 @@ pub fn str_replace_file(work_dir: &str, path: &str, edits: Vec<ReplaceEdit>) ->
         summary: format!(
             "File successfully edited. Applied {} edit(s) with {} replacement(s).",
             edits.len(),
-            total_replacements
+            outcome.replacements
         ),
-        output: String::new(),
+        output,
+    }
+}
+
+pub fn finish_preview(mut preview: FileDiff) -> FileDiff {
+    let (diff, truncated) = truncate_output(&preview.diff);
+    preview.diff = diff;
+    if truncated {
+        if !preview.diff.ends_with('\n') {
+            preview.diff.push('\n');
+        }
+        preview.diff.push_str(TRUNCATION_MARKER);
+        preview.diff.push('\n');
     }
+    preview
+}
+
+/// Compute the diff `write_file` would produce without touching the disk.
+pub fn preview_write_file(
+    work_dir: &str,
+    path: &str,
+    content: &str,
+    mode: &str,
+) -> Result<FileDiff, String> {
+    let resolved = resolve_path(work_dir, path, false)?;
+    let parent = resolved
+        .parent()
+        .ok_or_else(|| "Invalid file path".to_string())?;
+    if !parent.exists() {
+        return Err("Parent directory does not exist".to_string());
+    }
+    if resolved.is_dir() {
+        return Err("Path is a directory".to_string());
+    }
+
+    // Files that are not UTF-8 can still be overwritten; their old content
+    // is only shown approximately.
+    let existing = if resolved.exists() {
+        let bytes = fs::read(&resolved).map_err(|err| format!("Failed to read file: {err}"))?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        None
+    };
+    let updated = match (mode, existing.as_deref()) {
+        ("append", Some(old)) => format!("{old}{content}"),
+        _ => content.to_string(),
+    };
+
+    Ok(finish_preview(diff::unified_diff(
+        path,
+        existing.as_deref(),
+        &updated,
+    )))
+}
+
+/// Compute the diff `str_replace_file` would produce, failing with the
+/// per-edit report if any edit would not apply cleanly.
+pub fn preview_str_replace_file(
+    work_dir: &str,
+    path: &str,
+    edits: &[ReplaceEdit],
+    mode: MatchMode,
+) -> Result<FileDiff, String> {
+    let resolved = resolve_path(work_dir, path, true)?;
+    if !resolved.is_file() {
+        return Err("Path is not a file".to_string());
+    }
+    let original =
+        fs::read_to_string(&resolved).map_err(|err| format!("Failed to read file: {err}"))?;
+
+    let outcome = apply_edits(&original, edits, mode);
+    if outcome.failed > 0 {
+        return Err(outcome.report.join("\n"));
+    }
+    if outcome.updated == original {
+        return Err("Edits would not change the file.".to_string());
+    }
+
+    Ok(finish_preview(diff::unified_diff(
+        path,
+        Some(&original),
+        &outcome.updated,
+    )))
 }
 
 #[allow(dead_code)]
@@ -731,70 +1354,30 @@ pub async fn search_web(
         }
     };
 
-    let service = match parse_service_config(&config, "moonshot_search") {
-        Some(cfg) => cfg,
-        None => {
-            return ToolOutput {
-                ok: false,
-                summary: "Search service is not configured.".to_string(),
-                output: String::new(),
-            }
-        }
-    };
-
-    let client = reqwest::Client::new();
-    let mut req = client.post(&service.base_url);
-    req = req.header("Authorization", format!("Bearer {}", service.api_key));
-    req = req.header("X-Msh-Tool-Call-Id", tool_call_id);
-    for (k, v) in common_headers().into_iter() {
-        req = req.header(k, v);
-    }
-    for (k, v) in service.custom_headers.iter() {
-        req = req.header(k, v);
-    }
-
-    let response = req
-        .json(&serde_json::json!({
-            "text_query": query,
-            "limit": limit,
-            "enable_page_crawling": include_content,
-            "timeout_seconds": 30
-        }))
-        .send()
-        .await;
-
-    let response = match response {
-        Ok(resp) => resp,
-        Err(err) => {
-            return ToolOutput {
-                ok: false,
-                summary: format!("Failed to search: {err}"),
-                output: String::new(),
-            }
-        }
-    };
-
-    if !response.status().is_success() {
+    let Some(backend) = SearchBackend::from_config(&config) else {
         return ToolOutput {
             ok: false,
-            summary: format!("Search request failed with status {}", response.status()),
+            summary: "Search service is not configured. Add services.moonshot_search, services.searxng_search or services.custom_search to the config file.".to_string(),
             output: String::new(),
         };
-    }
+    };
 
-    let data: SearchResponse = match response.json().await {
-        Ok(value) => value,
-        Err(err) => {
+    let results = match backend
+        .search(tool_call_id, query, limit, include_content)
+        .await
+    {
+        Ok(results) => results,
+        Err(summary) => {
             return ToolOutput {
                 ok: false,
-                summary: format!("Failed to parse search response: {err}"),
+                summary,
                 output: String::new(),
             }
         }
     };
 
     let mut output = String::new();
-    for (i, result) in data.search_results.iter().enumerate() {
+    for (i, result) in results.iter().enumerate() {
         if i > 0 {
             output.push_str("---\n\n");
         }
@@ -810,7 +1393,14 @@ pub async fn search_web(
     let (output, truncated) = truncate_output(&output);
     ToolOutput {
         ok: true,
-        summary: append_truncation("Search completed.".to_string(), truncated),
+        summary: append_truncation(
+            format!(
+                "Search completed with {} result(s) from {}.",
+                results.len(),
+                backend.name()
+            ),
+            truncated,
+        ),
         output,
     }
 }
@@ -818,10 +1408,22 @@ pub async fn search_web(
 #[allow(dead_code)]
 pub async fn fetch_url(config_path: Option<&str>, tool_call_id: &str, url: &str) -> ToolOutput {
     let config = load_config_value(config_path).ok();
+    let policy = FetchPolicy::from_config(config.as_ref());
+    let checked = reqwest::Url::parse(url.trim())
+        .map_err(|err| format!("Invalid URL: {err}"))
+        .and_then(|parsed| policy.check_url(&parsed));
+    if let Err(summary) = checked {
+        return ToolOutput {
+            ok: false,
+            summary,
+            output: String::new(),
+        };
+    }
+
     if let Some(config) = config {
         if let Some(service) = parse_service_config(&config, "moonshot_fetch") {
             let client = reqwest::Client::new();
-            let mut req = client.post(&service.base_url);
+            let mut req = client.post(&service.base_url).timeout(policy.timeout);
             req = req.header("Authorization", format!("Bearer {}", service.api_key));
             req = req.header("Accept", "text/markdown");
             req = req.header("X-Msh-Tool-Call-Id", tool_call_id);
@@ -850,64 +1452,185 @@ pub async fn fetch_url(config_path: Option<&str>, tool_call_id: &str, url: &str)
         }
     }
 
-    let client = reqwest::Client::new();
-    let response = match client
-        .get(url)
-        .header(
-            "User-Agent",
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
-             (KHTML, like Gecko) Chrome/91.0.4472.124 Safari/537.36",
-        )
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(err) => {
+    let fetched = match fetch::get(&policy, url).await {
+        Ok(fetched) => fetched,
+        Err(summary) => {
             return ToolOutput {
                 ok: false,
-                summary: format!("Failed to fetch URL: {err}"),
+                summary,
                 output: String::new(),
             }
         }
     };
-
-    if !response.status().is_success() {
-        return ToolOutput {
-            ok: false,
-            summary: format!("Fetch failed with status {}", response.status()),
-            output: String::new(),
-        };
+    let mut output = fetched_content(&fetched.url, &fetched.content_type, &fetched.body);
+    if fetched.truncated && output.ok {
+        output.summary = format!(
+            "{} Download stopped at the {} size limit.",
+            output.summary,
+            content::format_size(policy.max_bytes)
+        );
     }
+    output
+}
 
-    let content_type = response
-        .headers()
-        .get(CONTENT_TYPE)
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("")
-        .to_lowercase();
+/// Turn a fetched response body into text for the model based on its
+/// content type, sniffing the body when the server does not say.
+fn fetched_content(url: &reqwest::Url, content_type: &str, body: &[u8]) -> ToolOutput {
+    let declared = content_type.split(';').next().unwrap_or("").trim();
+    let mime = if declared.is_empty() || declared == "application/octet-stream" {
+        infer::get(body)
+            .map(|kind| kind.mime_type())
+            .unwrap_or(declared)
+    } else {
+        declared
+    };
 
-    let body = match response.text().await {
-        Ok(text) => text,
-        Err(err) => {
-            return ToolOutput {
-                ok: false,
-                summary: format!("Failed to read response body: {err}"),
-                output: String::new(),
+    let (summary, output) = if mime == "text/html" || mime == "application/xhtml+xml" {
+        let html = decode_body(body, content_type, true);
+        let page = html::to_markdown(&html, Some(url));
+        let mut header = String::new();
+        if let Some(title) = &page.title {
+            header.push_str(&format!("Title: {title}\n"));
+        }
+        header.push_str(&format!("URL: {url}\n"));
+        if page.markdown.is_empty() {
+            (
+                "Fetched web page, but it has no readable text. It may need JavaScript to render."
+                    .to_string(),
+                header,
+            )
+        } else {
+            (
+                "Fetched web page and converted its main content to Markdown.".to_string(),
+                format!("{header}\n{}", page.markdown),
+            )
+        }
+    } else if mime == "application/json" || mime.ends_with("+json") {
+        let text = decode_body(body, content_type, false);
+        match serde_json::from_str::<serde_json::Value>(&text)
+            .ok()
+            .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        {
+            Some(pretty) => ("Fetched JSON content.".to_string(), pretty),
+            None => (
+                "Fetched content labelled as JSON, but it does not parse.".to_string(),
+                text,
+            ),
+        }
+    } else if mime == "application/pdf" {
+        match pdf::extract_text(body) {
+            Ok(text) => (
+                format!(
+                    "Extracted text from PDF ({}).",
+                    content::format_size(body.len() as u64)
+                ),
+                text,
+            ),
+            Err(err) => {
+                return ToolOutput {
+                    ok: false,
+                    summary: format!("Failed to extract text from PDF: {err}"),
+                    output: String::new(),
+                }
             }
         }
-    };
-
-    let summary =
-        if content_type.starts_with("text/plain") || content_type.starts_with("text/markdown") {
+    } else if is_text_mime(mime) {
+        let summary = if mime == "text/plain" || mime == "text/markdown" {
             "Fetched plain text content.".to_string()
         } else {
-            "Fetched response body.".to_string()
+            format!("Fetched {mime} content.")
         };
-    let (output, truncated) = truncate_output(&body);
+        (summary, decode_body(body, content_type, false))
+    } else {
+        let sample = &body[..body.len().min(SNIFF_BYTES)];
+        match content::sniff(sample) {
+            ContentKind::Text { .. } if !body.is_empty() => (
+                "Fetched response body.".to_string(),
+                decode_body(body, content_type, false),
+            ),
+            ContentKind::Text { .. } => ("Fetched an empty response.".to_string(), String::new()),
+            ContentKind::Image { mime } | ContentKind::Binary { mime: Some(mime) } => {
+                return binary_refusal(mime, body.len())
+            }
+            ContentKind::Binary { mime: None } => {
+                return binary_refusal(
+                    if mime.is_empty() {
+                        "unknown type"
+                    } else {
+                        mime
+                    },
+                    body.len(),
+                )
+            }
+        }
+    };
 
+    let (output, truncated) = truncate_output(&output);
     ToolOutput {
         ok: true,
         summary: append_truncation(summary, truncated),
         output,
     }
 }
+
+fn binary_refusal(mime: &str, size: usize) -> ToolOutput {
+    ToolOutput {
+        ok: false,
+        summary: format!(
+            "URL returned binary content ({mime}, {}). FetchURL can only read web pages, text, JSON and PDF.",
+            content::format_size(size as u64)
+        ),
+        output: String::new(),
+    }
+}
+
+fn is_text_mime(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime,
+            "application/xml"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/ecmascript"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/toml"
+                | "application/x-sh"
+                | "application/sql"
+                | "application/graphql"
+        )
+}
+
+/// Decode a response body using the charset from the Content-Type header,
+/// a `<meta charset>` declaration for HTML, or content sniffing.
+fn decode_body(body: &[u8], content_type: &str, html: bool) -> String {
+    let declared = charset_label(content_type).or_else(|| {
+        if !html {
+            return None;
+        }
+        let head = String::from_utf8_lossy(&body[..body.len().min(4096)]).to_lowercase();
+        charset_label(&head)
+    });
+    let encoding = declared
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or_else(
+            || match content::sniff(&body[..body.len().min(SNIFF_BYTES)]) {
+                ContentKind::Text { encoding, .. } => encoding,
+                _ => UTF_8,
+            },
+        );
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+/// The value of the first `charset=` in a header or markup snippet.
+fn charset_label(text: &str) -> Option<String> {
+    let start = text.find("charset=")? + "charset=".len();
+    let label: String = text[start..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | ':' | '.'))
+        .collect();
+    (!label.is_empty()).then_some(label)
+}
@@ -0,0 +1,664 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::file_index::{self, IgnoreStack};
+
+/// Quiet period before a batch of changes is sent.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// A steady stream of changes is still reported this often.
+const MAX_DELAY: Duration = Duration::from_secs(2);
+/// How often the polling fallback rescans the tree.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often a waiting watcher checks whether it was stopped.
+const STOP_CHECK: Duration = Duration::from_millis(250);
+/// Trees with more entries than this are only partly polled.
+const MAX_POLLED: usize = 100_000;
+
+/// Files in `.git` whose changes mean the status may differ, such as after
+/// staging or committing from a terminal.
+const GIT_STATE_FILES: &[&str] = &["index", "HEAD", "ORIG_HEAD", "MERGE_HEAD", "packed-refs"];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Renamed {
+    pub from: String,
+    pub to: String,
+}
+
+/// Debounced changes under a work dir. Paths are relative to it, with `/`
+/// separators, and never include ignored files.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChangeBatch {
+    pub created: Vec<String>,
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    pub renamed: Vec<Renamed>,
+    /// Events were lost, so the whole tree should be reloaded.
+    pub rescan: bool,
+    /// Repository state such as the index or `HEAD` changed.
+    pub git_changed: bool,
+}
+
+impl ChangeBatch {
+    fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.renamed.is_empty()
+            && !self.rescan
+            && !self.git_changed
+    }
+}
+
+/// Stops the watcher thread when dropped.
+pub struct WatchHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Watch `root` recursively. Batches arrive on the receiver until the handle
+/// is dropped. Uses inotify on Linux and falls back to polling elsewhere or
+/// when inotify is unavailable, for instance when the watch limit is hit.
+pub fn watch(root: &Path) -> Result<(WatchHandle, UnboundedReceiver<ChangeBatch>), String> {
+    if !root.is_dir() {
+        return Err("Path is not a directory".to_string());
+    }
+    let root = root.to_path_buf();
+    let stop = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = unbounded_channel();
+    let thread_stop = stop.clone();
+    std::thread::Builder::new()
+        .name("fs-watcher".to_string())
+        .spawn(move || {
+            let tree = Tree::new(&root);
+            #[cfg(target_os = "linux")]
+            if inotify::run(&tree, &thread_stop, &tx).is_ok() {
+                return;
+            }
+            poll(&tree, &thread_stop, &tx);
+        })
+        .map_err(|e| format!("Failed to start file watcher: {}", e))?;
+    Ok((WatchHandle { stop }, rx))
+}
+
+/// A watched tree with what is loaded once per watcher: the ignore rules from
+/// above the root, as finding the global excludes file runs git, and the git
+/// directories holding the repository state.
+struct Tree {
+    root: PathBuf,
+    ignores: IgnoreStack,
+    /// The checkout's git directory and, for a linked worktree, the main
+    /// repository's, which holds `packed-refs`.
+    git_dirs: Vec<PathBuf>,
+}
+
+impl Tree {
+    fn new(root: &Path) -> Self {
+        let mut git_dirs: Vec<PathBuf> = Vec::new();
+        if let Some(repo) = root.ancestors().find(|dir| dir.join(".git").exists()) {
+            for dir in [
+                file_index::worktree_git_dir(repo),
+                file_index::git_dir(repo),
+            ]
+            .into_iter()
+            .flatten()
+            .filter_map(|dir| dir.canonicalize().ok())
+            {
+                if !git_dirs.contains(&dir) {
+                    git_dirs.push(dir);
+                }
+            }
+        }
+        Tree {
+            root: root.to_path_buf(),
+            ignores: IgnoreStack::for_root(root),
+            git_dirs,
+        }
+    }
+
+    /// The ignore rules in effect inside the directory `rel`.
+    fn ignores_in(&self, rel: &Path) -> IgnoreStack {
+        let mut ignores = self.ignores.clone();
+        ignores.enter_dirs(&self.root, rel);
+        ignores
+    }
+}
+
+fn relative(root: &Path, path: &Path) -> Option<String> {
+    let rel = path
+        .strip_prefix(root)
+        .ok()?
+        .to_string_lossy()
+        .replace('\\', "/");
+    (!rel.is_empty()).then_some(rel)
+}
+
+/// Walk the tree below the directory `rel`, the root when empty, skipping
+/// ignored entries, and call `visit` with each entry's path, relative path
+/// and metadata. Stops early when `visit` returns false.
+fn walk(tree: &Tree, rel: &str, visit: &mut dyn FnMut(&Path, &str, &fs::Metadata) -> bool) {
+    fn walk_dir(
+        dir: &Path,
+        rel: &str,
+        ignores: &mut IgnoreStack,
+        visit: &mut dyn FnMut(&Path, &str, &fs::Metadata) -> bool,
+    ) -> bool {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return true;
+        };
+        let depth = ignores.enter(dir, rel);
+        for entry in read_dir.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            let child_rel = if rel.is_empty() {
+                name
+            } else {
+                format!("{rel}/{name}")
+            };
+            // `DirEntry::metadata` does not follow symlinks, so linked
+            // directories are not descended into.
+            if ignores.is_ignored(&child_rel, metadata.is_dir()) {
+                continue;
+            }
+            let path = entry.path();
+            if !visit(&path, &child_rel, &metadata) {
+                ignores.leave(depth);
+                return false;
+            }
+            if metadata.is_dir() && !walk_dir(&path, &child_rel, ignores, visit) {
+                ignores.leave(depth);
+                return false;
+            }
+        }
+        ignores.leave(depth);
+        true
+    }
+    if rel.is_empty() {
+        let mut ignores = tree.ignores.clone();
+        walk_dir(&tree.root, "", &mut ignores, visit);
+    } else {
+        // `walk_dir` loads the ignore files of `rel` itself.
+        let parent = Path::new(rel).parent().unwrap_or(Path::new(""));
+        let mut ignores = tree.ignores_in(parent);
+        walk_dir(&tree.root.join(rel), rel, &mut ignores, visit);
+    }
+}
+
+/// Ignore rules per directory, loaded on demand.
+struct IgnoreCache<'a> {
+    tree: &'a Tree,
+    rules: HashMap<String, IgnoreStack>,
+}
+
+impl<'a> IgnoreCache<'a> {
+    fn new(tree: &'a Tree) -> Self {
+        IgnoreCache {
+            tree,
+            rules: HashMap::new(),
+        }
+    }
+
+    fn matches(&mut self, rel: &str, is_dir: bool) -> bool {
+        let parent = rel.rsplit_once('/').map(|(p, _)| p).unwrap_or("");
+        let tree = self.tree;
+        self.rules
+            .entry(parent.to_string())
+            .or_insert_with(|| tree.ignores_in(Path::new(parent)))
+            .is_ignored(rel, is_dir)
+    }
+
+    /// Whether `rel` or one of the directories above it is ignored.
+    fn is_ignored(&mut self, rel: &str, is_dir: bool) -> bool {
+        rel.match_indices('/')
+            .any(|(end, _)| self.matches(&rel[..end], true))
+            || self.matches(rel, is_dir)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Change {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// Changes collected since the last batch was sent.
+#[derive(Default)]
+struct Pending {
+    changes: HashMap<String, (Change, bool)>,
+    renames: Vec<(String, String, bool)>,
+    rescan: bool,
+    git_changed: bool,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Pending {
+    fn touch(&mut self) {
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+    }
+
+    fn record(&mut self, rel: String, change: Change, is_dir: bool) {
+        self.touch();
+        let merged = match (self.changes.get(&rel).map(|(c, _)| *c), change) {
+            // Created and gone again within one batch.
+            (Some(Change::Created), Change::Deleted) => {
+                self.changes.remove(&rel);
+                return;
+            }
+            (Some(Change::Created), _) => Change::Created,
+            (Some(Change::Deleted), Change::Created) => Change::Modified,
+            (_, change) => change,
+        };
+        self.changes.insert(rel, (merged, is_dir));
+    }
+
+    fn rename(&mut self, from: String, to: String, is_dir: bool) {
+        self.touch();
+        // Saving through a temporary file and renaming it over the original
+        // is an edit, not a rename.
+        if !is_dir
+            && self
+                .changes
+                .get(&from)
+                .is_some_and(|(c, _)| *c == Change::Created)
+        {
+            self.changes.remove(&from);
+            self.record(to, Change::Modified, false);
+            return;
+        }
+        if is_dir {
+            // Changes already seen inside the directory now live under `to`.
+            let prefix = format!("{from}/");
+            let moved: Vec<String> = self
+                .changes
+                .keys()
+                .filter(|rel| rel.starts_with(&prefix))
+                .cloned()
+                .collect();
+            for rel in moved {
+                if let Some(change) = self.changes.remove(&rel) {
+                    self.changes
+                        .insert(format!("{to}/{}", &rel[prefix.len()..]), change);
+                }
+            }
+        }
+        self.renames.push((from, to, is_dir));
+    }
+
+    fn is_due(&self) -> bool {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => last.elapsed() >= DEBOUNCE || first.elapsed() >= MAX_DELAY,
+            _ => false,
+        }
+    }
+
+    /// How long to wait for more events before the batch is due.
+    fn wait(&self) -> Duration {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => DEBOUNCE
+                .saturating_sub(last.elapsed())
+                .min(MAX_DELAY.saturating_sub(first.elapsed()))
+                .min(STOP_CHECK),
+            _ => STOP_CHECK,
+        }
+    }
+
+    /// Drop ignored paths and turn what is left into a batch.
+    fn take(&mut self, tree: &Tree) -> ChangeBatch {
+        let pending = std::mem::take(self);
+        let mut ignores = IgnoreCache::new(tree);
+        let mut ignored = |rel: &str, is_dir: bool| ignores.is_ignored(rel, is_dir);
+
+        let mut batch = ChangeBatch {
+            rescan: pending.rescan,
+            git_changed: pending.git_changed,
+            ..Default::default()
+        };
+        for (rel, (change, is_dir)) in pending.changes {
+            if ignored(&rel, is_dir) {
+                continue;
+            }
+            match change {
+                Change::Created => batch.created.push(rel),
+                Change::Modified => batch.modified.push(rel),
+                Change::Deleted => batch.deleted.push(rel),
+            }
+        }
+        for (from, to, is_dir) in pending.renames {
+            match (ignored(&from, is_dir), ignored(&to, is_dir)) {
+                (false, false) => batch.renamed.push(Renamed { from, to }),
+                (false, true) => batch.deleted.push(from),
+                (true, false) => batch.created.push(to),
+                (true, true) => {}
+            }
+        }
+        batch.created.sort();
+        batch.modified.sort();
+        batch.deleted.sort();
+        batch
+    }
+}
+
+/// Modification stamps of the repository files in `GIT_STATE_FILES`.
+fn git_stamp(tree: &Tree) -> Vec<Option<SystemTime>> {
+    tree.git_dirs
+        .iter()
+        .flat_map(|git_dir| {
+            GIT_STATE_FILES
+                .iter()
+                .map(|name| git_dir.join(name))
+                .chain([git_dir.join("logs").join("HEAD")])
+        })
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+type Snapshot = HashMap<String, (bool, Option<SystemTime>, u64)>;
+
+fn snapshot(tree: &Tree) -> Snapshot {
+    let mut entries = Snapshot::new();
+    walk(tree, "", &mut |_, rel, metadata| {
+        entries.insert(
+            rel.to_string(),
+            (metadata.is_dir(), metadata.modified().ok(), metadata.len()),
+        );
+        entries.len() < MAX_POLLED
+    });
+    entries
+}
+
+/// Rescan the tree every `POLL_INTERVAL` and report the differences.
+fn poll(tree: &Tree, stop: &AtomicBool, tx: &UnboundedSender<ChangeBatch>) {
+    let mut previous = snapshot(tree);
+    let mut previous_git = git_stamp(tree);
+    loop {
+        let started = Instant::now();
+        while started.elapsed() < POLL_INTERVAL {
+            if stop.load(Ordering::Relaxed) {
+                return;
+            }
+            std::thread::sleep(STOP_CHECK);
+        }
+
+        let current = snapshot(tree);
+        let current_git = git_stamp(tree);
+        let mut batch = ChangeBatch {
+            git_changed: current_git != previous_git,
+            ..Default::default()
+        };
+        for (rel, (is_dir, modified, len)) in &current {
+            match previous.get(rel) {
+                None => batch.created.push(rel.clone()),
+                Some(before) if !is_dir && *before != (*is_dir, *modified, *len) => {
+                    batch.modified.push(rel.clone())
+                }
+                _ => {}
+            }
+        }
+        batch.deleted = previous
+            .keys()
+            .filter(|rel| !current.contains_key(*rel))
+            .cloned()
+            .collect();
+        batch.created.sort();
+        batch.modified.sort();
+        batch.deleted.sort();
+        previous = current;
+        previous_git = current_git;
+
+        if !batch.is_empty() && tx.send(batch).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind, RemoveKind, RenameMode};
+    use notify::{Config, Event, EventKind, INotifyWatcher, RecursiveMode, Watcher};
+    use std::collections::{HashMap, HashSet};
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::{relative, walk, Change, ChangeBatch, IgnoreCache, Pending, Tree, GIT_STATE_FILES};
+
+    /// One non-recursive watch per directory that is not ignored, so trees
+    /// such as `node_modules` cost no watches.
+    struct Watches {
+        watcher: INotifyWatcher,
+        dirs: HashSet<PathBuf>,
+    }
+
+    impl Watches {
+        fn add(&mut self, dir: &Path) -> Result<(), String> {
+            self.watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|error| error.to_string())?;
+            self.dirs.insert(dir.to_path_buf());
+            Ok(())
+        }
+
+        /// Watch the directory `rel`, the root when empty, and every
+        /// directory below it that is not ignored. Returns the entries below
+        /// it as relative paths, each with whether it is a directory.
+        fn add_tree(&mut self, tree: &Tree, rel: &str) -> Result<Vec<(String, bool)>, String> {
+            let dir = if rel.is_empty() {
+                tree.root.clone()
+            } else {
+                tree.root.join(rel)
+            };
+            self.add(&dir)?;
+            let mut entries = Vec::new();
+            let mut result = Ok(());
+            walk(tree, rel, &mut |path, child_rel, metadata| {
+                if metadata.is_dir() {
+                    if let Err(error) = self.add(path) {
+                        result = Err(error);
+                        return false;
+                    }
+                }
+                entries.push((child_rel.to_string(), metadata.is_dir()));
+                true
+            });
+            result.map(|()| entries)
+        }
+
+        /// Forget a directory that was deleted or moved, and those below
+        /// it. notify drops their watches itself.
+        fn forget_under(&mut self, dir: &Path) {
+            self.dirs.retain(|path| !path.starts_with(dir));
+        }
+    }
+
+    /// Record a path that appeared in the tree. A new directory is watched,
+    /// and whatever it already holds is reported as created too, since it
+    /// may have been filled before its watch was in place.
+    fn created(
+        tree: &Tree,
+        watches: &mut Watches,
+        pending: &mut Pending,
+        rel: String,
+        is_dir: bool,
+    ) {
+        let entries = if is_dir && !IgnoreCache::new(tree).is_ignored(&rel, true) {
+            watches.add_tree(tree, &rel).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        pending.record(rel, Change::Created, is_dir);
+        for (child, child_is_dir) in entries {
+            pending.record(child, Change::Created, child_is_dir);
+        }
+    }
+
+    /// Record one event, pairing the two halves of a move by their cookie.
+    fn handle(
+        tree: &Tree,
+        watches: &mut Watches,
+        git_watches: &[PathBuf],
+        moves: &mut HashMap<usize, PathBuf>,
+        pending: &mut Pending,
+        event: Event,
+    ) {
+        if event.need_rescan() {
+            pending.rescan = true;
+            pending.touch();
+        }
+        let Some(path) = event.paths.first().cloned() else {
+            return;
+        };
+        if path
+            .parent()
+            .is_some_and(|dir| git_watches.iter().any(|watched| watched == dir))
+        {
+            let name = path.file_name().and_then(|n| n.to_str());
+            if name.is_some_and(|name| GIT_STATE_FILES.contains(&name)) {
+                pending.git_changed = true;
+                pending.touch();
+            }
+            return;
+        }
+        let Some(rel) = relative(&tree.root, &path) else {
+            return;
+        };
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                // A moved directory's own watch reports it without a
+                // cookie; its parent's event is the one to pair. An
+                // unpaired move is flushed as a deletion once the batch is
+                // due.
+                if let Some(cookie) = event.tracker() {
+                    moves.insert(cookie, path);
+                    pending.touch();
+                }
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                let is_dir = path.is_dir();
+                match event.tracker().and_then(|cookie| moves.remove(&cookie)) {
+                    Some(from) => {
+                        if is_dir {
+                            // notify drops the watches of a moved directory,
+                            // and anything created in it before they are
+                            // added again goes unseen.
+                            watches.forget_under(&from);
+                            if !IgnoreCache::new(tree).is_ignored(&rel, true) {
+                                let _ = watches.add_tree(tree, &rel);
+                            }
+                            pending.rescan = true;
+                        }
+                        if let Some(from_rel) = relative(&tree.root, &from) {
+                            pending.rename(from_rel, rel, is_dir);
+                        }
+                    }
+                    None => created(tree, watches, pending, rel, is_dir),
+                }
+            }
+            EventKind::Create(kind) => {
+                created(tree, watches, pending, rel, kind == CreateKind::Folder)
+            }
+            EventKind::Remove(kind) => {
+                let is_dir = kind == RemoveKind::Folder || watches.dirs.contains(&path);
+                if is_dir {
+                    watches.forget_under(&path);
+                }
+                pending.record(rel, Change::Deleted, is_dir);
+            }
+            EventKind::Modify(ModifyKind::Data(_))
+            | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                pending.record(rel, Change::Modified, false);
+            }
+            _ => {}
+        }
+    }
+
+    /// Watch with inotify until stopped. Fails without sending anything when
+    /// the watches cannot be set up, so the caller can poll instead.
+    pub fn run(
+        tree: &Tree,
+        stop: &AtomicBool,
+        tx: &UnboundedSender<ChangeBatch>,
+    ) -> Result<(), String> {
+        let (events_tx, events) = channel();
+        let watcher =
+            INotifyWatcher::new(events_tx, Config::default()).map_err(|error| error.to_string())?;
+        let mut watches = Watches {
+            watcher,
+            dirs: HashSet::new(),
+        };
+        watches.add_tree(tree, "")?;
+        // Repository state lives in the git directory, which the tree walk
+        // skips.
+        let git_watches: Vec<PathBuf> = tree
+            .git_dirs
+            .iter()
+            .flat_map(|dir| [dir.clone(), dir.join("logs")])
+            .filter(|dir| {
+                dir.is_dir()
+                    && watches
+                        .watcher
+                        .watch(dir, RecursiveMode::NonRecursive)
+                        .is_ok()
+            })
+            .collect();
+
+        let mut pending = Pending::default();
+        let mut moves: HashMap<usize, PathBuf> = HashMap::new();
+        while !stop.load(Ordering::Relaxed) {
+            let event = match events.recv_timeout(pending.wait()) {
+                Ok(Ok(event)) => Some(event),
+                Ok(Err(_)) => {
+                    pending.rescan = true;
+                    pending.touch();
+                    None
+                }
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if let Some(event) = event {
+                handle(
+                    tree,
+                    &mut watches,
+                    &git_watches,
+                    &mut moves,
+                    &mut pending,
+                    event,
+                );
+            }
+
+            if pending.is_due() {
+                // A move whose destination never showed up left the tree.
+                for (_, from) in moves.drain() {
+                    let is_dir = watches.dirs.contains(&from);
+                    if is_dir {
+                        watches.forget_under(&from);
+                    }
+                    if let Some(rel) = relative(&tree.root, &from) {
+                        pending.record(rel, Change::Deleted, is_dir);
+                    }
+                }
+                let batch = pending.take(tree);
+                if !batch.is_empty() && tx.send(batch).is_err() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::worktree;
+
+/// A named folder in a workspace. Paths inside it are addressed as
+/// `name:relative/path`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorkspaceRoot {
+    pub name: String,
+    pub path: String,
+}
+
+/// Extra roots per primary work dir. The work dir itself is always the
+/// first root and is not stored.
+type WorkspaceFile = HashMap<String, Vec<WorkspaceRoot>>;
+
+fn workspaces_path() -> PathBuf {
+    crate::kimi_share_dir().join("workspaces.json")
+}
+
+fn load_all() -> WorkspaceFile {
+    fs::read_to_string(workspaces_path())
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+fn save_all(workspaces: &WorkspaceFile) -> Result<(), String> {
+    let path = workspaces_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create directory {parent:?}: {error}"))?;
+    }
+    let raw = serde_json::to_string_pretty(workspaces)
+        .map_err(|error| format!("Failed to encode workspaces: {error}"))?;
+    fs::write(&path, raw).map_err(|error| format!("Failed to write {path:?}: {error}"))
+}
+
+/// Root names are at least two characters so `C:\...` still reads as a
+/// Windows path.
+fn valid_name(name: &str) -> bool {
+    name.len() >= 2
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+}
+
+/// A root name derived from a folder's name.
+fn default_name(path: &Path) -> String {
+    let name: String = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.') {
+                ch
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('-').to_string();
+    if valid_name(&name) {
+        name
+    } else {
+        "root".to_string()
+    }
+}
+
+fn key(work_dir: &str) -> String {
+    work_dir.trim_end_matches(['/', '\\']).to_string()
+}
+
+/// Every root of the workspace whose primary root is `work_dir`, primary
+/// first. A work dir without extra roots is a workspace of one. In a session
+/// worktree the primary root is the worktree, named and extended like the
+/// project it was made from.
+pub fn roots(work_dir: &str) -> Vec<WorkspaceRoot> {
+    let project = worktree::project_dir(work_dir);
+    let mut roots = vec![WorkspaceRoot {
+        name: default_name(Path::new(&project)),
+        path: work_dir.to_string(),
+    }];
+    if let Some(extra) = load_all().remove(&key(&project)) {
+        roots.extend(
+            extra
+                .into_iter()
+                .filter(|root| Path::new(&root.path).is_dir()),
+        );
+    }
+    roots
+}
+
+/// Split `name:relative/path` into the named root's directory and the rest.
+/// Returns `None` for paths that do not start with a known root name.
+pub fn split_root_path(work_dir: &str, path: &str) -> Option<(PathBuf, String)> {
+    let (name, rest) = path.split_once(':')?;
+    if !valid_name(name) {
+        return None;
+    }
+    let root = roots(work_dir).into_iter().find(|root| root.name == name)?;
+    Some((
+        PathBuf::from(root.path),
+        rest.trim_start_matches(['/', '\\']).to_string(),
+    ))
+}
+
+#[tauri::command]
+pub fn workspace_load(work_dir: String) -> Vec<WorkspaceRoot> {
+    roots(&work_dir)
+}
+
+/// Add a folder to the workspace of `work_dir`. Without a name, one is made
+/// from the folder's name.
+#[tauri::command]
+pub fn workspace_add_root(
+    work_dir: String,
+    path: String,
+    name: Option<String>,
+) -> Result<Vec<WorkspaceRoot>, String> {
+    let folder = Path::new(&path);
+    if !folder.is_dir() {
+        return Err(format!("{path} is not a directory"));
+    }
+    let current = roots(&work_dir);
+    if current.iter().any(|root| key(&root.path) == key(&path)) {
+        return Err(format!("{path} is already in the workspace"));
+    }
+
+    let name = match name.map(|name| name.trim().to_string()) {
+        Some(name) if !name.is_empty() => {
+            if !valid_name(&name) {
+                return Err(format!(
+                    "Invalid root name {name:?}: use at least two letters, digits, '-', '_' or '.'"
+                ));
+            }
+            if current.iter().any(|root| root.name == name) {
+                return Err(format!("A root named {name} already exists"));
+            }
+            name
+        }
+        _ => {
+            let base = default_name(folder);
+            let mut name = base.clone();
+            let mut suffix = 2;
+            while current.iter().any(|root| root.name == name) {
+                name = format!("{base}-{suffix}");
+                suffix += 1;
+            }
+            name
+        }
+    };
+
+    let mut workspaces = load_all();
+    workspaces
+        .entry(key(&work_dir))
+        .or_default()
+        .push(WorkspaceRoot { name, path });
+    save_all(&workspaces)?;
+    Ok(roots(&work_dir))
+}
+
+#[tauri::command]
+pub fn workspace_remove_root(work_dir: String, name: String) -> Result<Vec<WorkspaceRoot>, String> {
+    let mut workspaces = load_all();
+    let key = key(&work_dir);
+    let Some(extra) = workspaces.get_mut(&key) else {
+        return Err(format!("No extra root named {name}"));
+    };
+    let before = extra.len();
+    extra.retain(|root| root.name != name);
+    if extra.len() == before {
+        return Err(format!("No extra root named {name}"));
+    }
+    if extra.is_empty() {
+        workspaces.remove(&key);
+    }
+    save_all(&workspaces)?;
+    Ok(roots(&work_dir))
+}
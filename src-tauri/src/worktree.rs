@@ -0,0 +1,292 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git::git;
+
+/// A git worktree an agent session runs in, on its own branch, so its edits
+/// stay out of the main checkout until they are merged.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionWorktree {
+    pub session_id: String,
+    /// Top level of the main checkout.
+    pub repo: String,
+    /// Top level of the session's worktree.
+    pub path: String,
+    pub branch: String,
+    /// Commit the branch started from.
+    pub base: String,
+    /// The session's work dir inside the worktree; matches the original work
+    /// dir's position inside the repository.
+    pub work_dir: String,
+    /// The work dir the session was started from.
+    pub project_dir: String,
+    pub created_at: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct WorktreeStatus {
+    pub worktree: SessionWorktree,
+    /// Commits on the session branch since `base`, newest first.
+    pub commits: Vec<String>,
+    /// Paths with uncommitted changes in the worktree.
+    pub uncommitted: usize,
+}
+
+fn worktrees_root() -> PathBuf {
+    crate::kimi_share_dir().join("worktrees")
+}
+
+fn check_session_id(session_id: &str) -> Result<(), String> {
+    if session_id.is_empty()
+        || session_id.contains(['/', '\\'])
+        || session_id == "."
+        || session_id == ".."
+    {
+        return Err("Invalid session id".to_string());
+    }
+    Ok(())
+}
+
+fn record_path(session_id: &str) -> Result<PathBuf, String> {
+    check_session_id(session_id)?;
+    Ok(worktrees_root().join(format!("{session_id}.json")))
+}
+
+/// The worktree recorded for a session, if its directory still exists.
+pub fn load(session_id: &str) -> Option<SessionWorktree> {
+    let raw = fs::read_to_string(record_path(session_id).ok()?).ok()?;
+    let worktree: SessionWorktree = serde_json::from_str(&raw).ok()?;
+    Path::new(&worktree.path).is_dir().then_some(worktree)
+}
+
+fn save(worktree: &SessionWorktree) -> Result<(), String> {
+    let path = record_path(&worktree.session_id)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create directory {parent:?}: {error}"))?;
+    }
+    let raw = serde_json::to_string_pretty(worktree)
+        .map_err(|error| format!("Failed to encode worktree: {error}"))?;
+    fs::write(&path, raw).map_err(|error| format!("Failed to write {path:?}: {error}"))
+}
+
+fn branch_name(session_id: &str) -> String {
+    let name: String = session_id
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' {
+                ch
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("kimi/session-{}", name.trim_matches('-'))
+}
+
+/// The session's worktree, created from the current `HEAD` of the
+/// repository containing `work_dir` on first use.
+pub async fn prepare(session_id: &str, work_dir: &str) -> Result<SessionWorktree, String> {
+    if let Some(worktree) = load(session_id) {
+        return Ok(worktree);
+    }
+    check_session_id(session_id)?;
+    let repo = git(work_dir, &["rev-parse", "--show-toplevel"])
+        .await
+        .map_err(|_| format!("{work_dir} is not inside a git repository"))?
+        .trim()
+        .to_string();
+    let prefix = git(work_dir, &["rev-parse", "--show-prefix"]).await?;
+    let base = git(work_dir, &["rev-parse", "--verify", "HEAD"])
+        .await
+        .map_err(|_| "The repository has no commits to branch from".to_string())?
+        .trim()
+        .to_string();
+
+    let path = worktrees_root().join(session_id);
+    let path_text = path.to_string_lossy().to_string();
+    let branch = branch_name(session_id);
+    git(
+        &repo,
+        &["worktree", "add", "-q", "-b", &branch, &path_text, &base],
+    )
+    .await?;
+
+    let worktree = SessionWorktree {
+        session_id: session_id.to_string(),
+        work_dir: path
+            .join(prefix.trim_end_matches(['\n', '\r']))
+            .to_string_lossy()
+            .trim_end_matches(['/', '\\'])
+            .to_string(),
+        project_dir: work_dir.to_string(),
+        repo,
+        path: path_text,
+        branch,
+        base,
+        created_at: chrono::Utc::now().timestamp(),
+    };
+    save(&worktree)?;
+    Ok(worktree)
+}
+
+/// The work dir a session worktree's `work_dir` stands in for, or `work_dir`
+/// itself when it is not a session's. Permission trust and workspace roots
+/// are keyed by the project's own work dir, so they follow the session into
+/// its worktree.
+pub fn project_dir(work_dir: &str) -> String {
+    let trimmed = work_dir.trim_end_matches(['/', '\\']);
+    let session_id = Path::new(trimmed)
+        .strip_prefix(worktrees_root())
+        .ok()
+        .and_then(|rel| rel.components().next())
+        .map(|component| component.as_os_str().to_string_lossy().to_string());
+    session_id
+        .and_then(|session_id| load(&session_id))
+        .filter(|worktree| worktree.work_dir == trimmed)
+        .map(|worktree| worktree.project_dir)
+        .unwrap_or_else(|| work_dir.to_string())
+}
+
+/// Commit whatever the agent left uncommitted so the branch holds all of
+/// the session's work.
+async fn commit_pending(worktree: &SessionWorktree) -> Result<(), String> {
+    let status = git(&worktree.path, &["status", "--porcelain"]).await?;
+    if status.trim().is_empty() {
+        return Ok(());
+    }
+    git(&worktree.path, &["add", "--all"]).await?;
+    let message = format!("Agent session {}", worktree.session_id);
+    git(&worktree.path, &["commit", "-q", "-m", &message]).await?;
+    Ok(())
+}
+
+/// Remove the worktree, its branch and the session's record. A worktree
+/// directory or branch that is already gone is skipped.
+async fn remove(worktree: &SessionWorktree) -> Result<(), String> {
+    if Path::new(&worktree.path).is_dir() {
+        git(
+            &worktree.repo,
+            &["worktree", "remove", "--force", &worktree.path],
+        )
+        .await?;
+    } else {
+        git(&worktree.repo, &["worktree", "prune"]).await?;
+    }
+    let branch_ref = format!("refs/heads/{}", worktree.branch);
+    if git(
+        &worktree.repo,
+        &["rev-parse", "--verify", "--quiet", &branch_ref],
+    )
+    .await
+    .is_ok()
+    {
+        git(&worktree.repo, &["branch", "-D", &worktree.branch]).await?;
+    }
+    let record = record_path(&worktree.session_id)?;
+    if record.exists() {
+        fs::remove_file(&record)
+            .map_err(|error| format!("Failed to remove {record:?}: {error}"))?;
+    }
+    Ok(())
+}
+
+/// Remove a deleted session's worktree and branch, if it has any, without
+/// applying its changes.
+pub async fn delete_session(session_id: &str) -> Result<(), String> {
+    let Ok(raw) = fs::read_to_string(record_path(session_id)?) else {
+        return Ok(());
+    };
+    let worktree: SessionWorktree = serde_json::from_str(&raw)
+        .map_err(|error| format!("Failed to parse worktree record: {error}"))?;
+    remove(&worktree).await
+}
+
+fn require(session_id: &str) -> Result<SessionWorktree, String> {
+    load(session_id).ok_or_else(|| "This session has no worktree".to_string())
+}
+
+#[tauri::command]
+pub async fn worktree_status(session_id: String) -> Result<Option<WorktreeStatus>, String> {
+    let Some(worktree) = load(&session_id) else {
+        return Ok(None);
+    };
+    let range = format!("{}..{}", worktree.base, worktree.branch);
+    let commits = git(&worktree.path, &["log", "--format=%h %s", &range])
+        .await?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let uncommitted = git(&worktree.path, &["status", "--porcelain"])
+        .await?
+        .lines()
+        .count();
+    Ok(Some(WorktreeStatus {
+        worktree,
+        commits,
+        uncommitted,
+    }))
+}
+
+/// Merge the session branch into the branch checked out in the main
+/// checkout, then remove the worktree. A conflicting merge is aborted.
+#[tauri::command]
+pub async fn worktree_merge(session_id: String) -> Result<String, String> {
+    let worktree = require(&session_id)?;
+    commit_pending(&worktree).await?;
+    let message = format!("Merge agent session {}", worktree.branch);
+    if let Err(error) = git(
+        &worktree.repo,
+        &[
+            "merge",
+            "--no-ff",
+            "--no-edit",
+            "-m",
+            &message,
+            &worktree.branch,
+        ],
+    )
+    .await
+    {
+        let _ = git(&worktree.repo, &["merge", "--abort"]).await;
+        return Err(error);
+    }
+    remove(&worktree).await?;
+    let head = git(&worktree.repo, &["log", "-1", "--format=%h %s"]).await?;
+    Ok(head.trim().to_string())
+}
+
+/// Replay the session's commits onto the main checkout's branch, then
+/// remove the worktree. A conflicting cherry-pick is aborted.
+#[tauri::command]
+pub async fn worktree_cherry_pick(session_id: String) -> Result<Vec<String>, String> {
+    let worktree = require(&session_id)?;
+    commit_pending(&worktree).await?;
+    let range = format!("{}..{}", worktree.base, worktree.branch);
+    let commits: Vec<String> = git(&worktree.repo, &["rev-list", "--reverse", &range])
+        .await?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    if commits.is_empty() {
+        return Err("The session branch has no changes to apply".to_string());
+    }
+    let mut command = vec!["cherry-pick"];
+    command.extend(commits.iter().map(String::as_str));
+    if let Err(error) = git(&worktree.repo, &command).await {
+        let _ = git(&worktree.repo, &["cherry-pick", "--abort"]).await;
+        return Err(error);
+    }
+    remove(&worktree).await?;
+    let count = commits.len().to_string();
+    let applied = git(&worktree.repo, &["log", "--format=%h %s", "-n", &count]).await?;
+    Ok(applied.lines().map(str::to_string).collect())
+}
+
+/// Delete the worktree and its branch without applying anything.
+#[tauri::command]
+pub async fn worktree_discard(session_id: String) -> Result<(), String> {
+    let worktree = require(&session_id)?;
+    remove(&worktree).await
+}
@@ -16,6 +16,9 @@
       pinned_sessions: [],
       pinned_cowork_tasks: [],
     },
+    planMode: false,
+    // Whether sessions run in their own git worktree
+    worktreeMode: false,
     paths: null,
     config: null,
     mcp: null,
@@ -38,6 +41,10 @@
     activeTab: null,
     explorerCollapsed: false,
     gitStatus: {},
+    gitOrigPaths: {},
+    watchedRoots: [],
+    reviewedPermissions: new Set(),
+    lastTrashed: null,
     fileModified: {},
     // Co-Work state
     currentMode: 'code', // 'code' | 'cowork'
@@ -52,6 +59,7 @@
     SESSION: 'session'
   };
   const MAX_RECENT_WORK_DIRS = 5;
+  const LARGE_FILE_HIGHLIGHT_LIMIT = 512 * 1024;
   
   // Autocomplete state
   const autocomplete = {
@@ -91,6 +99,9 @@
       btnConfigChat: $('btn-config-chat'),
       yoloSwitchMain: $('yolo-switch-main'),
       yoloSwitchChat: $('yolo-switch-chat'),
+      planSwitchChat: $('plan-switch-chat'),
+      worktreeSwitchMain: $('worktree-switch-main'),
+      worktreeSwitchChat: $('worktree-switch-chat'),
       btnFolder: $('btn-folder'),
       btnModel: $('btn-model'),
       folderLabel: $('folder-label'),
@@ -157,10 +168,12 @@
       toolApprovalTitle: $('tool-approval-title'),
       toolApprovalDetails: $('tool-approval-details'),
       btnToolApprove: $('btn-tool-approve'),
+      btnToolAlwaysAllow: $('btn-tool-always-allow'),
       btnToolReject: $('btn-tool-reject'),
       // File explorer elements
       fileExplorer: $('file-explorer'),
       fileExplorerContent: $('file-explorer-content'),
+      fileExplorerBranch: $('file-explorer-branch'),
       btnRefreshFiles: $('btn-refresh-files'),
       btnCollapseExplorer: $('btn-collapse-explorer'),
       btnToggleExplorer: $('btn-toggle-explorer'),
@@ -233,6 +246,7 @@
       // Load file tree if work_dir is set and user is logged in
       if (state.isLoggedIn && state.settings.work_dir) {
         await loadFileTree();
+        await reviewWorkspacePermissions();
       }
       
       // Only load sessions and models if logged in
@@ -267,6 +281,7 @@
         listen('chat://event', handleChatEvent);
         listen('oauth://event', handleOAuthEvent);
         listen('cowork://event', handleCoworkEvent);
+        listen('fs://changed', handleFsChanged);
       }
     } catch (err) {
       const message = err?.message || err || 'Initialization failed';
@@ -332,6 +347,14 @@
       case 'tool_approval':
         openToolApprovalModal(data);
         break;
+      case 'permission_mode':
+        state.planMode = data?.mode === 'plan';
+        if (elements.planSwitchChat) elements.planSwitchChat.checked = state.planMode;
+        break;
+      case 'rule_error':
+        // The call went ahead; only remembering the rule failed
+        showError(data?.message || 'Failed to save the permission rule');
+        break;
       case 'error':
         showError(data?.message || 'An error occurred');
         finishStreaming();
@@ -374,6 +397,7 @@
   let currentThinkingBuffer = '';
   const toolMessages = new Map();
   let pendingApprovalId = null;
+  let pendingApprovalRule = null;
 
   function appendStreamingText(text) {
     if (!currentMessageEl) {
@@ -599,23 +623,48 @@
   function openToolApprovalModal(data) {
     pendingApprovalId = data?.request_id || null;
     if (!pendingApprovalId) return;
+    pendingApprovalRule = data?.suggested_rule || null;
+    if (elements.btnToolAlwaysAllow) {
+      elements.btnToolAlwaysAllow.hidden = !pendingApprovalRule;
+      elements.btnToolAlwaysAllow.textContent = pendingApprovalRule
+        ? `始终允许 ${pendingApprovalRule}`
+        : 'Always allow';
+    }
     const toolName = data?.name || 'Tool';
     elements.toolApprovalTitle.textContent = `需要批准：${toolName}`;
-    elements.toolApprovalDetails.textContent = JSON.stringify(data?.args || {}, null, 2);
+    const preview = data?.preview;
+    if (toolName === 'SubmitPlan') {
+      const steps = (data?.args?.steps || [])
+        .map((step, i) => {
+          const files = step.files?.length ? `\n   ${step.files.join(', ')}` : '';
+          const desc = step.description ? `\n   ${step.description}` : '';
+          return `${i + 1}. ${step.title}${desc}${files}`;
+        })
+        .join('\n');
+      elements.toolApprovalDetails.textContent = `${data?.args?.summary || ''}\n\n${steps}`;
+    } else if (preview?.diff !== undefined) {
+      const stats = `${preview.new_file ? '新文件 ' : ''}+${preview.added} -${preview.removed}`;
+      elements.toolApprovalDetails.textContent = `${preview.path} (${stats})\n\n${preview.diff}`;
+    } else {
+      elements.toolApprovalDetails.textContent = JSON.stringify(data?.args || {}, null, 2);
+    }
     elements.toolApprovalModal.classList.add('open');
   }
 
-  async function respondToolApproval(approved) {
+  async function respondToolApproval(approved, rememberRule = null) {
     if (!pendingApprovalId) return;
     try {
       await invoke('tool_approval_respond', {
         requestId: pendingApprovalId,
-        approved
+        approved,
+        rememberRule,
+        rememberScope: rememberRule ? 'workspace' : null
       });
     } catch (err) {
       showError(err.message || 'Failed to submit approval');
     }
     pendingApprovalId = null;
+    pendingApprovalRule = null;
     elements.toolApprovalModal.classList.remove('open');
   }
 
@@ -697,34 +746,186 @@
     try {
       const tree = await invoke('list_dir_tree', { path: workDir });
       state.fileTree = tree;
-      
-      // Store git status in a lookup map
       state.gitStatus = {};
-      if (tree.git_status) {
-        tree.git_status.forEach(status => {
-          state.gitStatus[status.path] = status.status;
-        });
-      }
+      state.gitOrigPaths = {};
+      applyGitStatus(tree.path, tree.git_status, tree.git_branch);
+      
+      // Other workspace roots show as top-level folders with their entries preloaded
+      tree.rootNodes = tree.roots.map(root => {
+        applyGitStatus(root.path, root.git_status, null);
+        return {
+          name: root.name,
+          path: root.path,
+          is_dir: true,
+          is_symlink: false,
+          is_loop: false,
+          child_count: root.total,
+          children: root.entries,
+          childrenTotal: root.total,
+          workspaceRoot: true
+        };
+      });
       
+      await restoreExpandedFolders(tree.entries);
+      for (const node of tree.rootNodes) {
+        if (state.expandedFolders.has(node.path)) {
+          await restoreExpandedFolders(node.children);
+        }
+      }
       renderFileTree();
+      watchRoots([tree.path, ...tree.roots.map(root => root.path)]);
     } catch (err) {
       console.error('Failed to load file tree:', err);
       elements.fileExplorerContent.innerHTML = '<div class="file-explorer-empty">Failed to load files</div>';
     }
   }
   
-  function getGitStatusForPath(relPath) {
+  function applyGitStatus(root, entries, branch) {
+    // Store git status in a lookup map keyed by full path, so every
+    // workspace root shares it
+    const prefix = root + '/';
+    Object.keys(state.gitStatus)
+      .filter(path => path.startsWith(prefix))
+      .forEach(path => {
+        delete state.gitStatus[path];
+        delete state.gitOrigPaths[path];
+      });
+    (entries || []).forEach(status => {
+      const path = prefix + status.path;
+      state.gitStatus[path] = status.status;
+      if (status.orig_path) {
+        state.gitOrigPaths[path] = status.orig_path;
+      }
+    });
+    if (root === state.settings.work_dir) {
+      renderGitBranch(branch);
+    }
+  }
+  
+  // Watch exactly the roots of the current workspace
+  // The backend watches every root of the workspace; starting it again
+  // picks up roots added or removed since
+  async function watchRoots(roots) {
+    const [workDir] = roots;
+    const previous = state.watchedRoots[0];
+    if (previous === workDir && roots.join('\n') === state.watchedRoots.join('\n')) return;
+    if (previous && previous !== workDir) {
+      try {
+        await invoke('watch_stop', { workDir: previous });
+      } catch (err) {
+        console.error('Failed to stop watching:', err);
+      }
+      state.watchedRoots = [];
+    }
+    try {
+      await invoke('watch_start', { workDir });
+      state.watchedRoots = roots;
+    } catch (err) {
+      console.error('Failed to watch work dir:', err);
+    }
+  }
+  
+  // The workspace root holding `path`; nested roots win over their parents
+  function rootFor(path) {
+    const roots = [state.settings.work_dir, ...(state.fileTree?.roots || []).map(root => root.path)];
+    return roots
+      .filter(root => root && (path === root || path.startsWith(root + '/')))
+      .sort((a, b) => b.length - a.length)[0] || state.settings.work_dir;
+  }
+  
+  function handleFsChanged(event) {
+    const change = event.payload;
+    if (!change || !state.watchedRoots.includes(change.work_dir)) return;
+    const structural = change.rescan
+      || change.created.length > 0
+      || change.deleted.length > 0
+      || change.renamed.length > 0;
+    if (structural) {
+      loadFileTree();
+      return;
+    }
+    applyGitStatus(change.work_dir, change.git_status, change.git_branch);
+    renderFileTree();
+  }
+  
+  async function loadFolderChildren(entry, append = false) {
+    const offset = append && entry.children ? entry.children.length : 0;
+    const listing = await invoke('list_dir_children', {
+      root: rootFor(entry.path),
+      path: entry.path,
+      offset
+    });
+    entry.children = append && entry.children
+      ? entry.children.concat(listing.entries)
+      : listing.entries;
+    entry.childrenTotal = listing.total;
+  }
+  
+  // Reload the children of folders that were expanded before a refresh
+  async function restoreExpandedFolders(entries) {
+    for (const entry of entries) {
+      if (!entry.is_dir || entry.is_loop || !state.expandedFolders.has(entry.path)) continue;
+      try {
+        await loadFolderChildren(entry);
+        await restoreExpandedFolders(entry.children);
+      } catch (err) {
+        state.expandedFolders.delete(entry.path);
+      }
+    }
+  }
+  
+  function createLoadMoreItem(shown, total, depth, onClick) {
+    const row = document.createElement('div');
+    row.className = 'file-tree-item file-tree-more';
+    row.style.paddingLeft = `${12 + depth * 16 + 18}px`;
+    row.textContent = `Show more (${shown} of ${total})`;
+    row.onclick = async (e) => {
+      e.stopPropagation();
+      try {
+        await onClick();
+      } catch (err) {
+        console.error('Failed to load more entries:', err);
+      }
+      renderFileTree();
+    };
+    return row;
+  }
+  
+  function renderGitBranch(info) {
+    const el = elements.fileExplorerBranch;
+    if (!el) return;
+    if (!info) {
+      el.classList.add('hidden');
+      el.textContent = '';
+      el.title = '';
+      return;
+    }
+    let label = info.branch || (info.head ? info.head.slice(0, 7) : 'no commits');
+    if (info.ahead) label += ` ↑${info.ahead}`;
+    if (info.behind) label += ` ↓${info.behind}`;
+    el.textContent = label;
+    el.title = info.upstream
+      ? `${info.branch || 'HEAD'} → ${info.upstream} (ahead ${info.ahead}, behind ${info.behind})`
+      : (info.branch ? `${info.branch} (no upstream)` : 'Detached HEAD');
+    el.classList.remove('hidden');
+  }
+  
+  function getGitStatusForPath(fullPath) {
     // Check exact match
-    if (state.gitStatus[relPath]) {
-      return state.gitStatus[relPath];
+    if (state.gitStatus[fullPath]) {
+      return state.gitStatus[fullPath];
     }
     // Check if any parent directory has status
+    let containsChanges = false;
     for (const [path, status] of Object.entries(state.gitStatus)) {
-      if (path.startsWith(relPath + '/')) {
-        return 'modified'; // Directory contains changes
+      if (path.startsWith(fullPath + '/') && status !== 'ignored') {
+        if (status === 'conflicted') {
+          return 'conflicted';
+        }
+        containsChanges = true;
       }
     }
-    return null;
+    return containsChanges ? 'modified' : null; // Directory contains changes
   }
   
   function renderFileTree() {
@@ -746,25 +947,170 @@
       container.appendChild(item);
     });
     
+    const rootTree = state.fileTree;
+    if (rootTree.total > rootTree.entries.length) {
+      container.appendChild(createLoadMoreItem(rootTree.entries.length, rootTree.total, 0, async () => {
+        const listing = await invoke('list_dir_children', {
+          root: state.settings.work_dir,
+          path: rootTree.path,
+          offset: rootTree.entries.length
+        });
+        rootTree.entries = rootTree.entries.concat(listing.entries);
+        rootTree.total = listing.total;
+      }));
+    }
+    
+    (rootTree.rootNodes || []).forEach(node => {
+      container.appendChild(createFileTreeItem(node, 0));
+    });
+    
     elements.fileExplorerContent.innerHTML = '';
     elements.fileExplorerContent.appendChild(container);
   }
   
+  // ================================
+  // File Management
+  // ================================
+  
+  function closeFileContextMenu() {
+    document.querySelector('.file-context-menu')?.remove();
+  }
+  
+  function showFileContextMenu(event, entry) {
+    event.preventDefault();
+    event.stopPropagation();
+    closeFileContextMenu();
+    const workDir = state.settings.work_dir;
+    if (!workDir) return;
+    
+    // New entries go inside a folder, or next to a file
+    const parent = !entry
+      ? workDir
+      : entry.is_dir ? entry.path : entry.path.slice(0, entry.path.lastIndexOf('/'));
+    const items = [
+      ['New File…', () => createEntry(parent, false)],
+      ['New Folder…', () => createEntry(parent, true)],
+    ];
+    if (entry?.workspaceRoot) {
+      items.push(['Remove from Workspace', () => removeWorkspaceRoot(entry)]);
+    } else if (entry) {
+      items.push(['Rename…', () => renameEntry(entry)]);
+      items.push(['Move to Trash', () => trashEntry(entry)]);
+    } else {
+      items.push(['Add Folder to Workspace…', addWorkspaceRoot]);
+    }
+    if (state.lastTrashed) {
+      items.push([`Undo Delete of ${state.lastTrashed.path}`, restoreLastTrashed]);
+    }
+    
+    const menu = document.createElement('div');
+    menu.className = 'file-context-menu';
+    items.forEach(([label, action]) => {
+      const item = document.createElement('div');
+      item.className = 'file-context-menu-item';
+      item.textContent = label;
+      item.onclick = async (e) => {
+        e.stopPropagation();
+        closeFileContextMenu();
+        try {
+          await action();
+        } catch (err) {
+          showError(err?.message || String(err));
+        }
+      };
+      menu.appendChild(item);
+    });
+    menu.style.left = `${event.clientX}px`;
+    menu.style.top = `${event.clientY}px`;
+    document.body.appendChild(menu);
+  }
+  
+  function relativeToRoot(path, root = rootFor(path)) {
+    return path === root ? '' : path.slice(root.length + 1);
+  }
+  
+  // Close tabs showing a path that was moved or deleted
+  function closeTabsUnder(path) {
+    state.openTabs
+      .filter(tab => tab.type === TAB_TYPES.FILE && (tab.path === path || tab.path.startsWith(path + '/')))
+      .forEach(tab => closeTab(tab.path));
+  }
+  
+  // The tree reloads from the fs://changed event each command emits
+  async function createEntry(parent, isDir) {
+    const name = prompt(isDir ? 'New folder name' : 'New file name');
+    if (!name || !name.trim()) return;
+    const workDir = rootFor(parent);
+    const path = [relativeToRoot(parent, workDir), name.trim()].filter(Boolean).join('/');
+    if (parent !== state.settings.work_dir) {
+      state.expandedFolders.add(parent);
+    }
+    await invoke(isDir ? 'file_create_dir' : 'file_create', { workDir, path });
+    if (!isDir) {
+      openFile(`${workDir}/${path}`, path.split('/').pop());
+    }
+  }
+  
+  async function renameEntry(entry) {
+    const workDir = rootFor(entry.path);
+    const from = relativeToRoot(entry.path, workDir);
+    const to = prompt('Rename or move to', from);
+    if (!to || !to.trim() || to.trim() === from) return;
+    closeTabsUnder(entry.path);
+    await invoke('file_rename', { workDir, from, to: to.trim() });
+  }
+  
+  async function trashEntry(entry) {
+    if (!confirm(`Move ${entry.name} to the trash?`)) return;
+    closeTabsUnder(entry.path);
+    const workDir = rootFor(entry.path);
+    state.lastTrashed = await invoke('file_trash', {
+      workDir,
+      path: relativeToRoot(entry.path, workDir)
+    });
+    showSuccess(`Moved ${entry.name} to the trash`);
+  }
+  
+  async function addWorkspaceRoot() {
+    const path = await invoke('pick_folder');
+    if (!path) return;
+    await invoke('workspace_add_root', { workDir: state.settings.work_dir, path });
+    await loadFileTree();
+  }
+  
+  async function removeWorkspaceRoot(entry) {
+    if (!confirm(`Remove ${entry.name} from the workspace? Its files are not deleted.`)) return;
+    closeTabsUnder(entry.path);
+    await invoke('workspace_remove_root', { workDir: state.settings.work_dir, name: entry.name });
+    await loadFileTree();
+  }
+  
+  async function restoreLastTrashed() {
+    const entry = state.lastTrashed;
+    if (!entry) return;
+    await invoke('trash_restore', { id: entry.id });
+    state.lastTrashed = null;
+    showSuccess(`Restored ${entry.path}`);
+  }
+  
   function createFileTreeItem(entry, depth) {
-    const hasChildren = entry.is_dir && entry.children && entry.children.length > 0;
-    const isExpanded = state.expandedFolders.has(entry.path);
+    // child_count is known before a folder is loaded; symlink loops never expand
+    const hasChildren = entry.is_dir && !entry.is_loop && entry.child_count !== 0;
+    const isExpanded = hasChildren && state.expandedFolders.has(entry.path);
     
-    // Get relative path for git status lookup
-    const workDir = state.settings.work_dir || '';
-    const relPath = entry.path.replace(workDir + '/', '').replace(workDir, '');
-    const gitStatus = getGitStatusForPath(relPath);
+    const gitStatus = getGitStatusForPath(entry.path);
     
     const item = document.createElement('div');
     item.className = 'file-tree-item-wrapper';
     
     const row = document.createElement('div');
-    row.className = 'file-tree-item' + (gitStatus ? ` git-${gitStatus}` : '');
+    row.className = 'file-tree-item'
+      + (gitStatus ? ` git-${gitStatus}` : '')
+      + (entry.workspaceRoot ? ' workspace-root' : '');
     row.style.paddingLeft = `${12 + depth * 16}px`;
+    if (entry.workspaceRoot) {
+      row.title = entry.path;
+    }
     
     // Toggle button for folders
     const toggle = document.createElement('button');
@@ -773,7 +1119,7 @@
     toggle.disabled = !hasChildren;
     toggle.onclick = (e) => {
       e.stopPropagation();
-      toggleFolder(entry.path);
+      toggleFolder(entry);
     };
     row.appendChild(toggle);
     
@@ -792,19 +1138,28 @@
     const name = document.createElement('span');
     name.className = 'file-tree-name';
     name.textContent = entry.name;
+    if (entry.is_symlink) {
+      name.classList.add('symlink');
+      name.title = entry.is_loop ? 'Symbolic link to a parent folder' : 'Symbolic link';
+    }
     row.appendChild(name);
     
     // Git status indicator dot
     if (gitStatus && !entry.is_dir) {
       const statusDot = document.createElement('span');
       statusDot.className = `file-tree-status ${gitStatus}`;
+      statusDot.title = state.gitOrigPaths[entry.path]
+        ? `${gitStatus} from ${state.gitOrigPaths[entry.path]}`
+        : gitStatus;
       row.appendChild(statusDot);
     }
     
+    row.oncontextmenu = (e) => showFileContextMenu(e, entry);
+    
     // Click handler
     row.onclick = () => {
       if (entry.is_dir) {
-        toggleFolder(entry.path);
+        toggleFolder(entry);
       } else {
         openFile(entry.path, entry.name);
       }
@@ -833,6 +1188,11 @@
           const childItem = createFileTreeItem(child, depth + 1);
           children.appendChild(childItem);
         });
+        
+        if (entry.children.length < (entry.childrenTotal || 0)) {
+          children.appendChild(createLoadMoreItem(entry.children.length, entry.childrenTotal, depth + 1,
+            () => loadFolderChildren(entry, true)));
+        }
       }
       
       item.appendChild(children);
@@ -841,10 +1201,20 @@
     return item;
   }
   
-  function toggleFolder(path) {
+  async function toggleFolder(entry) {
+    const path = entry.path;
     if (state.expandedFolders.has(path)) {
       state.expandedFolders.delete(path);
     } else {
+      if (entry.is_loop) return;
+      if (!entry.children) {
+        try {
+          await loadFolderChildren(entry);
+        } catch (err) {
+          console.error('Failed to load folder:', err);
+          return;
+        }
+      }
       state.expandedFolders.add(path);
     }
     renderFileTree();
@@ -883,12 +1253,24 @@
         return;
       }
       
-      // Read file content
-      const relPath = filePath.replace(workDir + '/', '');
-      const content = await invoke('read_file', { workDir, filePath: relPath });
+      // Read the first chunk; large files load the rest on demand
+      const root = rootFor(filePath);
+      const chunk = await invoke('read_file_range', {
+        workDir: root,
+        filePath: relativeToRoot(filePath, root)
+      });
       
       // Add to tabs
-      const tab = { path: filePath, name: fileName, content, type: TAB_TYPES.FILE };
+      const tab = {
+        path: filePath,
+        name: fileName,
+        content: chunk.content,
+        type: TAB_TYPES.FILE,
+        nextOffset: chunk.next_offset,
+        totalSize: chunk.total_size,
+        hasMore: chunk.has_more,
+        version: chunk.version,
+      };
       state.openTabs.push(tab);
       state.activeTab = filePath;
       
@@ -899,6 +1281,38 @@
     }
   }
   
+  async function loadMoreFile(tab) {
+    const workDir = state.settings.work_dir;
+    if (!workDir || !tab.hasMore) return;
+    
+    try {
+      const root = rootFor(tab.path);
+      const chunk = await invoke('read_file_range', {
+        workDir: root,
+        filePath: relativeToRoot(tab.path, root),
+        offset: tab.nextOffset,
+      });
+      tab.content += chunk.content;
+      tab.nextOffset = chunk.next_offset;
+      tab.totalSize = chunk.total_size;
+      tab.hasMore = chunk.has_more;
+      
+      if (state.activeTab === tab.path) {
+        const scrollTop = elements.fileViewContent.scrollTop;
+        showFileView(tab);
+        elements.fileViewContent.scrollTop = scrollTop;
+      }
+    } catch (err) {
+      showError('Failed to load file: ' + err.message);
+    }
+  }
+  
+  function formatFileSize(bytes) {
+    if (bytes < 1024) return `${bytes} B`;
+    if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+    return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+  }
+  
   function renderTabs() {
     if (state.openTabs.length === 0) {
       elements.tabBar.classList.add('hidden');
@@ -984,6 +1398,7 @@
     elements.fileView.classList.add('hidden');
     elements.chatView.classList.remove('hidden');
     elements.chatTitle.textContent = session.title;
+    loadSessionWorktree(session);
     
     // Load messages if not already loaded
     if (elements.messages.innerHTML === '' || elements.messages.innerHTML.includes('No messages yet')) {
@@ -1015,8 +1430,19 @@
     
     elements.fileViewContent.innerHTML = `<pre><code class="hljs ${lang}">${escaped}</code></pre>`;
     
-    // Apply syntax highlighting
-    if (typeof hljs !== 'undefined' && lang) {
+    if (tab.hasMore) {
+      const more = document.createElement('div');
+      more.className = 'file-view-more';
+      more.innerHTML = `
+        <span>Showing ${formatFileSize(tab.nextOffset)} of ${formatFileSize(tab.totalSize)}</span>
+        <button class="btn-secondary">Load more</button>
+      `;
+      more.querySelector('button').addEventListener('click', () => loadMoreFile(tab));
+      elements.fileViewContent.appendChild(more);
+    }
+    
+    // Apply syntax highlighting (skipped for partially loaded large files)
+    if (typeof hljs !== 'undefined' && lang && !tab.hasMore && tab.content.length <= LARGE_FILE_HIGHLIGHT_LIMIT) {
       elements.fileViewContent.querySelectorAll('pre code').forEach(block => {
         hljs.highlightElement(block);
       });
@@ -1039,6 +1465,10 @@
     
     const tab = state.openTabs.find(t => t.path === state.activeTab);
     if (!tab) return;
+    if (tab.hasMore) {
+      showError('This file is only partially loaded. Load the rest before editing.');
+      return;
+    }
     
     // Show editor
     elements.fileViewContent.classList.add('hidden');
@@ -1075,15 +1505,42 @@
     if (!tab) return;
     
     const newContent = elements.fileEditorTextarea.value;
-    const workDir = state.settings.work_dir;
-    if (!workDir) return;
+    if (!state.settings.work_dir) return;
+    const workDir = rootFor(tab.path);
     
     try {
-      const relPath = tab.path.replace(workDir + '/', '');
-      await invoke('write_file', { workDir, filePath: relPath, content: newContent });
+      const relPath = relativeToRoot(tab.path, workDir);
+      let version;
+      try {
+        version = await invoke('write_file', {
+          workDir,
+          filePath: relPath,
+          content: newContent,
+          expectedVersion: tab.version
+        });
+      } catch (err) {
+        if (err?.kind !== 'conflict') throw err;
+        // Changed on disk since it was opened: overwrite, or take the disk version
+        if (!confirm(`${err.message}. Overwrite it with your changes?`)) {
+          if (err.content !== null) {
+            tab.content = err.content;
+            tab.version = err.version;
+          }
+          exitEditMode();
+          showFileView(tab);
+          return;
+        }
+        version = await invoke('write_file', {
+          workDir,
+          filePath: relPath,
+          content: newContent,
+          expectedVersion: err.version
+        });
+      }
       
       // Update tab content
       tab.content = newContent;
+      tab.version = version;
       
       // Exit edit mode and refresh view
       exitEditMode();
@@ -1411,6 +1868,7 @@
       await loadSkills();
       await loadSessions();
       await loadFileTree();
+      await reviewWorkspacePermissions();
     }
 
     if (persist) {
@@ -1418,6 +1876,33 @@
     }
   }
 
+  // A repository can ship its own permission rules; its allow rules only
+  // apply once the user trusts that exact file content
+  async function reviewWorkspacePermissions() {
+    const workDir = state.settings.work_dir;
+    if (!workDir) return;
+    try {
+      const payload = await invoke('permissions_load', { workDir });
+      const allow = payload.workspace?.allow || [];
+      const version = payload.workspace_version;
+      const key = `${workDir}\n${version}`;
+      if (!version || payload.workspace_trusted || allow.length === 0
+          || state.reviewedPermissions.has(key)) {
+        return;
+      }
+      state.reviewedPermissions.add(key);
+      const trusted = confirm(
+        `${payload.workspace_path} allows these tools without asking:\n\n`
+        + `${allow.join('\n')}\n\nTrust these rules?`
+      );
+      if (trusted) {
+        await invoke('permissions_trust_workspace', { workDir, version });
+      }
+    } catch (err) {
+      showError('Failed to load workspace permissions: ' + (err.message || err));
+    }
+  }
+  
   function resolveSessionWorkDir(session = null) {
     return session?.work_dir || state.settings.work_dir || '.';
   }
@@ -1614,6 +2099,8 @@
           ...state.settings,
           work_dir: sessionWorkDir,
         },
+        permissionMode: state.planMode ? 'plan' : 'default',
+        worktree: state.worktreeMode,
       });
     } catch (err) {
       const errorMsg = err?.message || err || 'Failed to send message';
@@ -1673,6 +2160,8 @@
           ...state.settings,
           work_dir: sessionWorkDir,
         },
+        permissionMode: state.planMode ? 'plan' : 'default',
+        worktree: state.worktreeMode,
       });
       
       // Add to sessions list
@@ -1735,6 +2224,8 @@
           ...state.settings,
           work_dir: sessionWorkDir,
         },
+        permissionMode: state.planMode ? 'plan' : 'default',
+        worktree: state.worktreeMode,
       });
       
       // Add to sessions list
@@ -1851,6 +2342,24 @@
     if (elements.yoloSwitchChat) elements.yoloSwitchChat.checked = value;
   }
 
+  function syncWorktreeSwitches() {
+    const value = !!state.worktreeMode;
+    if (elements.worktreeSwitchMain) elements.worktreeSwitchMain.checked = value;
+    if (elements.worktreeSwitchChat) elements.worktreeSwitchChat.checked = value;
+  }
+
+  // An existing session keeps running where it already is
+  async function loadSessionWorktree(session) {
+    try {
+      const status = await invoke('worktree_status', { sessionId: session.id });
+      if (state.currentSession?.id !== session.id) return;
+      state.worktreeMode = !!status;
+      syncWorktreeSwitches();
+    } catch (err) {
+      console.error('Failed to load session worktree:', err);
+    }
+  }
+
   function showLoginPrompt() {
     // Replace empty-state content with login prompt
     elements.emptyState.innerHTML = `
@@ -2140,11 +2649,12 @@
         if (state.settings.work_dir) {
           try {
             const workDir = state.settings.work_dir;
-            const files = await invoke('list_files', { 
+            const page = await invoke('list_files', { 
               workDir: workDir, 
-              query: query.length > 0 ? query : null 
+              query: query.length > 0 ? query : null,
+              limit: 10
             });
-            suggestions = files.slice(0, 10).map(f => ({
+            suggestions = page.files.map(f => ({
               value: '@' + f,
               display: '@' + f,
               description: 'File',
@@ -3109,6 +3619,20 @@ Current task: ${task.prompt}`;
     };
     bindYoloSwitch(elements.yoloSwitchMain);
     bindYoloSwitch(elements.yoloSwitchChat);
+    if (elements.planSwitchChat) {
+      elements.planSwitchChat.addEventListener('change', () => {
+        state.planMode = elements.planSwitchChat.checked;
+      });
+    }
+    const bindWorktreeSwitch = (input) => {
+      if (!input) return;
+      input.addEventListener('change', () => {
+        state.worktreeMode = input.checked;
+        syncWorktreeSwitches();
+      });
+    };
+    bindWorktreeSwitch(elements.worktreeSwitchMain);
+    bindWorktreeSwitch(elements.worktreeSwitchChat);
     
     elements.btnConfig.addEventListener('click', () => {
       elements.drawerBackdrop.classList.add('open');
@@ -3266,6 +3790,9 @@ Current task: ${task.prompt}`;
     if (elements.btnToolApprove) {
       elements.btnToolApprove.addEventListener('click', () => respondToolApproval(true));
     }
+    if (elements.btnToolAlwaysAllow) {
+      elements.btnToolAlwaysAllow.addEventListener('click', () => respondToolApproval(true, pendingApprovalRule));
+    }
     if (elements.btnToolReject) {
       elements.btnToolReject.addEventListener('click', () => respondToolApproval(false));
     }
@@ -3365,6 +3892,7 @@ Current task: ${task.prompt}`;
       await loadSkills();
       await loadSessions();
       await loadFileTree();
+      await reviewWorkspacePermissions();
       updateUI();
       elements.drawerBackdrop.classList.remove('open');
       showSuccess('Settings saved');
@@ -3401,6 +3929,12 @@ Current task: ${task.prompt}`;
       elements.btnRefreshFiles.addEventListener('click', loadFileTree);
     }
     
+    elements.fileExplorerContent.addEventListener('contextmenu', (e) => showFileContextMenu(e, null));
+    document.addEventListener('click', closeFileContextMenu);
+    document.addEventListener('keydown', (e) => {
+      if (e.key === 'Escape') closeFileContextMenu();
+    });
+    
     if (elements.btnCollapseExplorer) {
       elements.btnCollapseExplorer.addEventListener('click', toggleExplorer);
     }